@@ -1,20 +1,13 @@
-use super::types::{Res, ENDIANNESS};
+use super::types::{Instruction, Integer, Layout, Number, Res, ENDIANNESS};
 use nom::{
-	combinator::verify,
-	number::{
-		complete::{self, u8},
-		Endianness,
-	},
+	combinator::map,
+	number::{complete, Endianness},
 };
 use std::{
-	io::{Result, Write},
-	mem::size_of,
+	convert::TryFrom,
+	io::{Error, ErrorKind, Result, Write},
 };
 
-pub fn verify_size_of<T>(input: &[u8]) -> Res<u8> {
-	verify(u8, |&v| size_of::<T>() == usize::from(v))(input)
-}
-
 macro_rules! impl_serde {
 	($t:ty, $func:expr) => {
 		impl Serde for $t {
@@ -26,7 +19,7 @@ macro_rules! impl_serde {
 				}
 			}
 
-			fn deser(input: &[u8]) -> Res<Self> {
+			fn deser(input: &[u8]) -> Res<'_, Self> {
 				$func(ENDIANNESS)(input)
 			}
 		}
@@ -38,7 +31,7 @@ where
 	Self: Sized,
 {
 	fn ser(self, w: &mut dyn Write) -> Result<()>;
-	fn deser(input: &[u8]) -> Res<Self>;
+	fn deser(input: &[u8]) -> Res<'_, Self>;
 }
 
 impl Serde for i8 {
@@ -46,7 +39,7 @@ impl Serde for i8 {
 		w.write_all(&self.to_le_bytes())
 	}
 
-	fn deser(input: &[u8]) -> Res<Self> {
+	fn deser(input: &[u8]) -> Res<'_, Self> {
 		complete::i8(input)
 	}
 }
@@ -56,7 +49,7 @@ impl Serde for u8 {
 		w.write_all(&self.to_le_bytes())
 	}
 
-	fn deser(input: &[u8]) -> Res<Self> {
+	fn deser(input: &[u8]) -> Res<'_, Self> {
 		complete::u8(input)
 	}
 }
@@ -71,3 +64,60 @@ impl_serde!(u128, complete::u128);
 impl_serde!(u16, complete::u16);
 impl_serde!(u32, complete::u32);
 impl_serde!(u64, complete::u64);
+
+pub fn load_instruction_as(layout: Layout) -> impl Fn(&[u8]) -> Res<'_, Instruction> {
+	move |input| complete::u32(layout.endianness())(input)
+}
+
+pub fn load_integer_as(layout: Layout) -> impl Fn(&[u8]) -> Res<'_, Integer> {
+	move |input| match layout.size_integer {
+		4 => map(complete::i32(layout.endianness()), Integer::from)(input),
+		_ => complete::i64(layout.endianness())(input),
+	}
+}
+
+pub fn load_number_as(layout: Layout) -> impl Fn(&[u8]) -> Res<'_, Number> {
+	move |input| match layout.size_number {
+		4 => map(complete::f32(layout.endianness()), Number::from)(input),
+		_ => complete::f64(layout.endianness())(input),
+	}
+}
+
+// `bytes` are little endian, as `to_le_bytes` gives them
+fn dump_ordered(layout: Layout, mut bytes: Vec<u8>, w: &mut dyn Write) -> Result<()> {
+	if layout.big_endian {
+		bytes.reverse();
+	}
+
+	w.write_all(&bytes)
+}
+
+pub fn dump_instruction_as(layout: Layout, value: Instruction, w: &mut dyn Write) -> Result<()> {
+	dump_ordered(layout, value.to_le_bytes().to_vec(), w)
+}
+
+pub fn dump_integer_as(layout: Layout, value: Integer, w: &mut dyn Write) -> Result<()> {
+	let bytes = match layout.size_integer {
+		4 => i32::try_from(value)
+			.map_err(|_| {
+				let msg = format!("integer {} does not fit a 4 byte lua_Integer", value);
+
+				Error::new(ErrorKind::InvalidData, msg)
+			})?
+			.to_le_bytes()
+			.to_vec(),
+		_ => value.to_le_bytes().to_vec(),
+	};
+
+	dump_ordered(layout, bytes, w)
+}
+
+// narrowing to a 4 byte float rounds, the same as the compiler would have
+pub fn dump_number_as(layout: Layout, value: Number, w: &mut dyn Write) -> Result<()> {
+	let bytes = match layout.size_number {
+		4 => (value as f32).to_le_bytes().to_vec(),
+		_ => value.to_le_bytes().to_vec(),
+	};
+
+	dump_ordered(layout, bytes, w)
+}
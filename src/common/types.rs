@@ -1,28 +1,151 @@
 use nom::{error::VerboseError, number::Endianness, IResult};
 use serde::{Deserialize, Serialize};
-use std::rc::Rc;
+use std::{fmt, sync::Arc};
 
 pub const ENDIANNESS: Endianness = Endianness::Little;
 
-pub type Named<T> = Vec<(Rc<str>, T)>;
+// a list rather than a map, so things are written out in the order they were
+// read in and the same chunk always serializes the same way
+pub type Named<T> = Vec<(Arc<str>, T)>;
 pub type Res<'a, T> = IResult<&'a [u8], T, VerboseError<&'a [u8]>>;
 
 pub type Instruction = u32;
 pub type Integer = i64;
 pub type Number = f64;
 
-#[derive(Deserialize, Serialize)]
+// how integers, floats and instructions are stored, as read off the header of
+// a chunk, so that it can be written back the same way
+#[derive(Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct Layout {
+	pub big_endian: bool,
+	pub size_integer: u8,
+	pub size_number: u8,
+}
+
+impl Layout {
+	pub fn is_native(&self) -> bool {
+		*self == Self::default()
+	}
+
+	pub fn endianness(self) -> Endianness {
+		if self.big_endian {
+			Endianness::Big
+		} else {
+			Endianness::Little
+		}
+	}
+}
+
+impl Default for Layout {
+	fn default() -> Self {
+		Layout {
+			big_endian: false,
+			size_integer: 8,
+			size_number: 8,
+		}
+	}
+}
+
+#[derive(Clone, Deserialize, Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub enum Value {
 	Nil,
 	False,
 	True,
 	Integer(Integer),
-	Number(Number),
+	Number(
+		#[serde(with = "float")]
+		#[cfg_attr(feature = "schema", schemars(schema_with = "float::schema"))]
+		Number,
+	),
 	NoString,
 	String(String),
+	// a string that isn't valid UTF-8, kept byte for byte
+	Bytes(Vec<u8>),
+}
+
+// JSON has no way to write a float that isn't finite, so those are written as
+// the strings Rust prints them as, RON then reads back either form
+mod float {
+	use super::Number;
+	use serde::{de, Deserializer, Serializer};
+	use std::fmt;
+
+	pub fn serialize<S: Serializer>(value: &Number, serializer: S) -> Result<S::Ok, S::Error> {
+		if value.is_finite() {
+			serializer.serialize_f64(*value)
+		} else {
+			serializer.serialize_str(&value.to_string())
+		}
+	}
+
+	struct FloatVisitor;
+
+	impl<'de> de::Visitor<'de> for FloatVisitor {
+		type Value = Number;
+
+		fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+			f.write_str("a float, `inf`, `-inf` or `NaN`")
+		}
+
+		fn visit_f64<E: de::Error>(self, value: f64) -> Result<Number, E> {
+			Ok(value)
+		}
+
+		fn visit_i64<E: de::Error>(self, value: i64) -> Result<Number, E> {
+			Ok(value as Number)
+		}
+
+		fn visit_u64<E: de::Error>(self, value: u64) -> Result<Number, E> {
+			Ok(value as Number)
+		}
+
+		fn visit_str<E: de::Error>(self, value: &str) -> Result<Number, E> {
+			value
+				.parse()
+				.map_err(|_| E::invalid_value(de::Unexpected::Str(value), &self))
+		}
+	}
+
+	pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Number, D::Error> {
+		deserializer.deserialize_any(FloatVisitor)
+	}
+
+	// a number, or one of the strings a float that isn't finite is written as
+	#[cfg(feature = "schema")]
+	pub fn schema(gen: &mut schemars::gen::SchemaGenerator) -> schemars::schema::Schema {
+		use schemars::schema::{InstanceType, Schema, SchemaObject, SubschemaValidation};
+
+		let name_list = ["inf", "-inf", "NaN"].iter().map(|&v| v.into()).collect();
+		let name = SchemaObject {
+			instance_type: Some(InstanceType::String.into()),
+			enum_values: Some(name_list),
+			..Default::default()
+		};
+
+		Schema::Object(SchemaObject {
+			subschemas: Some(Box::new(SubschemaValidation {
+				any_of: Some(vec![gen.subschema_for::<Number>(), name.into()]),
+				..Default::default()
+			})),
+			..Default::default()
+		})
+	}
 }
 
 impl Value {
+	// constants are strings of bytes to Lua, only those that aren't text are
+	// left as bytes, along with any holding a NUL since RON writes `\0` but
+	// can't read it back
+	pub fn from_bytes(data: &[u8]) -> Self {
+		match String::from_utf8(data.to_vec()) {
+			Ok(text) if !text.contains('\0') => Value::String(text),
+			Ok(text) => Value::Bytes(text.into_bytes()),
+			Err(e) => Value::Bytes(e.into_bytes()),
+		}
+	}
+
 	pub fn as_str(&self) -> &str {
 		match self {
 			Value::Nil => "nil",
@@ -32,32 +155,39 @@ impl Value {
 			Value::Number(_) => "number",
 			Value::NoString => "no string",
 			Value::String(v) => v.as_ref(),
+			Value::Bytes(_) => "bytes",
 		}
 	}
 }
 
-#[derive(Deserialize, Serialize)]
+#[derive(Clone, Deserialize, Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct Local {
 	pub name: Option<String>,
 	pub start_pc: u32,
 	pub end_pc: u32,
 }
 
-#[derive(Deserialize, Serialize)]
+#[derive(Clone, Deserialize, Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct Upvalue {
 	pub name: Option<String>,
 	pub in_stack: bool,
 	pub index: u8,
+	#[serde(default)]
+	pub kind: u8,
 }
 
-#[derive(Deserialize, Serialize)]
+#[derive(Clone, Deserialize, Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct LineInfo {
 	pub line_defined: (u32, u32),
 	pub line_offset: Vec<i8>,
 	pub line_data: Vec<(u32, u32)>,
 }
 
-#[derive(Deserialize, Serialize)]
+#[derive(Clone, Deserialize, Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct StackInfo {
 	pub is_vararg: u8,
 	pub num_stack: u8,
@@ -70,8 +200,60 @@ impl StackInfo {
 	}
 }
 
-#[derive(Deserialize, Serialize)]
+// how far below the main function a recursive pass has gone, bounded so a
+// deep `child_list` stops it with an error instead of overflowing the stack
+#[derive(Clone, Copy)]
+pub struct Depth {
+	level: usize,
+	max_depth: usize,
+}
+
+impl Depth {
+	pub fn new(max_depth: usize) -> Self {
+		Self {
+			level: 0,
+			max_depth,
+		}
+	}
+
+	// the function a pass was started on, which is the main one of a chunk
+	pub fn is_main(self) -> bool {
+		self.level == 0
+	}
+
+	pub fn child(self) -> Result<Self, DepthError> {
+		if self.level >= self.max_depth {
+			return Err(DepthError {
+				max_depth: self.max_depth,
+			});
+		}
+
+		Ok(Self {
+			level: self.level + 1,
+			..self
+		})
+	}
+}
+
+#[derive(Debug)]
+pub struct DepthError {
+	pub max_depth: usize,
+}
+
+impl fmt::Display for DepthError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "proto nesting exceeds {}", self.max_depth)
+	}
+}
+
+impl std::error::Error for DepthError {}
+
+#[derive(Clone, Deserialize, Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct Function<B> {
+	// only the main function's is used, and only written when not the default
+	#[serde(default, skip_serializing_if = "Layout::is_native")]
+	pub layout: Layout,
 	pub source: Option<String>,
 	pub stack_info: StackInfo,
 	pub line_info: LineInfo,
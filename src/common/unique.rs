@@ -1,18 +1,20 @@
 use super::types::{Function, Named, Upvalue, Value};
 use convert_case::{Case, Casing};
-use std::{collections::HashMap, rc::Rc};
+use std::{collections::HashMap, sync::Arc};
 
 fn re_case(raw: &str) -> String {
-	let mut name = raw.to_case(Case::Pascal);
-
-	name.truncate(12);
-
-	name
+	// names have to survive RON, which can't read back a `\0`, and are cut
+	// by characters so text past ASCII can't split one
+	raw.to_case(Case::Pascal)
+		.chars()
+		.filter(|c| !c.is_control())
+		.take(12)
+		.collect()
 }
 
 #[derive(Default)]
 struct Unique {
-	used: HashMap<Rc<str>, u32>,
+	used: HashMap<Arc<str>, u32>,
 	prefix: &'static str,
 }
 
@@ -26,14 +28,14 @@ impl Unique {
 
 	fn alias(&mut self, raw: &str) -> String {
 		let name = re_case(raw).into();
-		let index = self.used.entry(Rc::clone(&name)).or_default();
+		let index = self.used.entry(Arc::clone(&name)).or_default();
 
 		*index += 1;
 
 		format!("{}{}_{}", self.prefix, name, *index)
 	}
 
-	fn with<T, M>(&mut self, list: &[T], ext: M) -> Vec<Rc<str>>
+	fn with<T, M>(&mut self, list: &[T], ext: M) -> Vec<Arc<str>>
 	where
 		M: Fn(&T) -> &str,
 	{
@@ -47,6 +49,17 @@ pub fn name_value_list(list: Vec<Value>) -> Named<Value> {
 	name_list.into_iter().zip(list).collect()
 }
 
+// the first free name for a value added after the list was already named
+pub fn name_added_value(list: &Named<Value>, value: &Value) -> Arc<str> {
+	let name = re_case(value.as_str());
+
+	(1..)
+		.map(|index| format!("v{}_{}", name, index))
+		.find(|v| list.iter().all(|(used, _)| used.as_ref() != v))
+		.unwrap()
+		.into()
+}
+
 pub fn name_upvalue_list(list: Vec<Upvalue>) -> Named<Upvalue> {
 	let name_list = Unique::new("u").with(&list, |v| v.name.as_deref().unwrap_or("no_name"));
 
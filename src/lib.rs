@@ -0,0 +1,185 @@
+use common::types::Function;
+use lua54::{
+	common::{inst::Block, types::Proto},
+	format::{BytecodeFormat, Lua54},
+	loader::{catch_panic, parse_header, LoadError},
+};
+use std::{convert::TryFrom, io};
+
+pub mod common;
+pub mod lua53;
+pub mod lua54;
+
+// writes `func` out as bytecode of the given version
+pub fn assemble_as(func: Function<Block>, lua: &dyn BytecodeFormat) -> io::Result<Vec<u8>> {
+	lua.dump(&Proto::from(func))
+}
+
+pub fn assemble(func: Function<Block>) -> io::Result<Vec<u8>> {
+	assemble_as(func, &Lua54)
+}
+
+// the header is checked on its own first since it gives better errors, what
+// follows the chunk is handed back untouched, and a panic on the way is an
+// error like the rest
+pub fn load_chunk<'a>(
+	bytes: &'a [u8],
+	lua: &dyn BytecodeFormat,
+) -> Result<(Function<Block>, &'a [u8]), LoadError> {
+	parse_header(bytes)?;
+
+	catch_panic(|| {
+		let (trail, proto) = lua.load(bytes)?;
+
+		Ok((Function::try_from(proto)?, trail))
+	})
+}
+
+// anything left over after the chunk is refused
+pub fn disassemble_as(
+	bytes: &[u8],
+	lua: &dyn BytecodeFormat,
+) -> Result<Function<Block>, LoadError> {
+	let (func, trail) = load_chunk(bytes, lua)?;
+
+	if !trail.is_empty() {
+		return Err(LoadError::TrailingData { len: trail.len() });
+	}
+
+	Ok(func)
+}
+
+pub fn disassemble(bytes: &[u8]) -> Result<Function<Block>, LoadError> {
+	disassemble_as(bytes, &Lua54)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	// `output.ron` assembled, which came out of `luac` with its debug info
+	const VSECURE: &[u8] = include_bytes!("../tests/fixtures/vsecure.luac");
+	// a 300 byte binary string and a short one holding NULs
+	const BYTES300: &[u8] = include_bytes!("../tests/fixtures/bytes300.luac");
+
+	#[test]
+	fn roundtrip_keeps_debug_info() {
+		let func = disassemble(VSECURE).unwrap();
+
+		assert!(!func.line_info.line_offset.is_empty());
+		assert!(!func.local_list.is_empty());
+		assert!(func.upval_list.iter().all(|v| v.1.name.is_some()));
+		assert!(assemble(func).unwrap() == VSECURE);
+	}
+
+	// `3` and `3.0` stay apart through the chunk and through JSON, which has
+	// no number for the infinities
+	#[test]
+	fn integer_and_float_keep_their_tags() {
+		use common::types::Value;
+
+		let mut func = disassemble(VSECURE).unwrap();
+		let first = func.value_list.len();
+		let added = vec![
+			Value::Integer(3),
+			Value::Number(3.0),
+			Value::Number(f64::INFINITY),
+			Value::Number(f64::NEG_INFINITY),
+		];
+
+		func.value_list.extend(
+			added
+				.into_iter()
+				.enumerate()
+				.map(|(i, v)| (format!("vAdded_{}", i).into(), v)),
+		);
+
+		let data = assemble(func).unwrap();
+		let text = serde_json::to_string(&disassemble(&data).unwrap()).unwrap();
+		let func: Function<Block> = serde_json::from_str(&text).unwrap();
+
+		match &func.value_list[first..] {
+			[(_, Value::Integer(3)), (_, Value::Number(a)), (_, Value::Number(b)), (_, Value::Number(c))] =>
+			{
+				assert!(*a == 3.0 && *b == f64::INFINITY && *c == f64::NEG_INFINITY);
+			}
+			_ => panic!("added constants came back changed"),
+		}
+
+		assert!(assemble(func).unwrap() == data);
+	}
+
+	#[test]
+	fn binary_constants_roundtrip() {
+		use common::types::Value;
+
+		let func = disassemble(BYTES300).unwrap();
+		let long: Vec<u8> = (0..=255).chain(0..44).collect();
+
+		match &func.value_list[..] {
+			[(_, Value::Bytes(a)), (_, Value::Bytes(b))] => {
+				assert!(*a == long && b == b"a\0b\0");
+			}
+			_ => panic!("binary constants were not read as bytes"),
+		}
+
+		let text = ron::to_string(&func).unwrap();
+		let func: Function<Block> = ron::from_str(&text).unwrap();
+
+		assert!(assemble(func).unwrap() == BYTES300);
+	}
+
+	// cut anywhere, even inside the long string, it is refused and not a panic
+	#[test]
+	fn truncated_chunk_is_refused() {
+		for len in 0..BYTES300.len() {
+			match disassemble(&BYTES300[..len]) {
+				Ok(_) => panic!("a chunk cut to {} bytes loaded", len),
+				Err(LoadError::Panicked(why)) => panic!("cut to {} bytes, {}", len, why),
+				Err(_) => {}
+			}
+		}
+	}
+
+	// malformed input comes back from `try_load` as an error, never as a panic
+	// it caught, and what follows the chunk is handed back as it was
+	#[test]
+	fn try_load_refuses_malformed_input() {
+		use lua54::loader::try_load;
+
+		for len in 0..BYTES300.len() {
+			assert!(matches!(
+				try_load(&BYTES300[..len]),
+				Err(ref e) if !matches!(e, LoadError::Panicked(_))
+			));
+		}
+
+		let mut data = BYTES300.to_vec();
+
+		data.extend(b"trail");
+
+		assert!(matches!(try_load(&data), Ok((trail, _)) if trail == b"trail"));
+		assert!(matches!(
+			catch_panic::<()>(|| panic!("boom")),
+			Err(LoadError::Panicked(why)) if why == "boom"
+		));
+	}
+
+	// a `LOADK` naming a constant past the end of the list is refused
+	#[test]
+	fn constant_index_out_of_range() {
+		let load = (3u32 | 1 << 7 | 1 << 15).to_le_bytes();
+		let at = BYTES300.windows(4).position(|v| v == load).unwrap();
+		let mut data = BYTES300.to_vec();
+
+		data[at..at + 4].copy_from_slice(&(3u32 | 1 << 7 | 7 << 15).to_le_bytes());
+
+		assert!(matches!(
+			disassemble(&data),
+			Err(LoadError::BadIndex {
+				kind: "constant",
+				index: 7
+			})
+		));
+	}
+}
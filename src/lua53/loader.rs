@@ -0,0 +1,226 @@
+use super::{
+	translate::translate,
+	types::{Constant, Inst, Proto, LUA_VERSION},
+};
+use crate::{
+	common::{
+		loader::{load_instruction_as, load_integer_as, load_number_as},
+		types::{Depth, Local, Res, Upvalue, Value},
+	},
+	lua54::{
+		common::types::Proto as Proto54,
+		loader::{
+			check_signature, load_depth, parse_header, LoadError, LuaHeader, CONSTANT_TAG,
+			MAX_NESTING,
+		},
+	},
+};
+use nom::{
+	bytes::complete::take,
+	combinator::{map, map_res},
+	error::context,
+	multi::length_count,
+	number::complete::{self, u8},
+};
+use std::convert::TryFrom;
+
+// `int` is four bytes, checked by the header, but still in the chunk's order
+fn load_int<'a>(header: &LuaHeader, input: &'a [u8]) -> Res<'a, u32> {
+	complete::u32(header.layout.endianness())(input)
+}
+
+// sizes are a byte, with `0xFF` escaping to a full `size_t`
+fn load_bytes_opt<'a>(header: &LuaHeader, input: &'a [u8]) -> Res<'a, Option<&'a [u8]>> {
+	let endianness = header.layout.endianness();
+	let (input, len) = u8(input)?;
+	let (input, len) = match (len, header.size_size_t) {
+		(0, _) => return Ok((input, None)),
+		(0xFF, Some(4)) => map_res(complete::u32(endianness), usize::try_from)(input)?,
+		(0xFF, _) => map_res(complete::u64(endianness), usize::try_from)(input)?,
+		(len, _) => (input, usize::from(len)),
+	};
+
+	map(take(len - 1), Some)(input)
+}
+
+fn load_string_opt<'a>(header: &LuaHeader, input: &'a [u8]) -> Res<'a, Option<String>> {
+	map(
+		|i| load_bytes_opt(header, i),
+		|s| s.map(|v| String::from_utf8_lossy(v).to_string()),
+	)(input)
+}
+
+fn load_string<'a>(header: &LuaHeader, input: &'a [u8]) -> Res<'a, Value> {
+	map(
+		|i| load_bytes_opt(header, i),
+		|s| match s {
+			Some(s) => Value::from_bytes(s),
+			None => Value::NoString,
+		},
+	)(input)
+}
+
+fn load_list<'h, T, F>(header: &'h LuaHeader, func: F) -> impl Fn(&[u8]) -> Res<'_, Vec<T>> + 'h
+where
+	F: Fn(&[u8]) -> Res<'_, T> + Copy + 'h,
+{
+	move |input| length_count(|i| load_int(header, i), func)(input)
+}
+
+fn load_instruction<'a>(header: &LuaHeader, input: &'a [u8]) -> Res<'a, Inst> {
+	map(load_instruction_as(header.layout), |inner| Inst { inner })(input)
+}
+
+fn load_constant<'a>(header: &LuaHeader, input: &'a [u8]) -> Res<'a, Value> {
+	let layout = header.layout;
+	let (input, tag) = context(CONSTANT_TAG, map_res(u8, Constant::try_from))(input)?;
+	let (input, value) = match tag {
+		Constant::Nil => (input, Value::Nil),
+		Constant::Boolean => map(u8, |v| if v == 0 { Value::False } else { Value::True })(input)?,
+		Constant::Integer => map(load_integer_as(layout), Value::Integer)(input)?,
+		Constant::Number => map(load_number_as(layout), Value::Number)(input)?,
+		Constant::ShortString | Constant::LongString => load_string(header, input)?,
+	};
+
+	Ok((input, value))
+}
+
+fn load_upvalue(input: &[u8]) -> Res<'_, Upvalue> {
+	let (input, in_stack) = u8(input)?;
+	let (input, index) = u8(input)?;
+	let result = Upvalue {
+		name: None,
+		in_stack: in_stack != 0,
+		index,
+		kind: 0,
+	};
+
+	Ok((input, result))
+}
+
+fn load_local<'a>(header: &LuaHeader, input: &'a [u8]) -> Res<'a, Local> {
+	let (input, name) = load_string_opt(header, input)?;
+	let (input, start_pc) = load_int(header, input)?;
+	let (input, end_pc) = load_int(header, input)?;
+	let result = Local {
+		name,
+		start_pc,
+		end_pc,
+	};
+
+	Ok((input, result))
+}
+
+fn load_function<'a>(header: &LuaHeader, depth: Depth, input: &'a [u8]) -> Res<'a, Proto> {
+	let (input, source) = load_string_opt(header, input)?;
+	let (input, line_defined) = load_int(header, input)?;
+	let (input, last_line_defined) = load_int(header, input)?;
+
+	// metadata
+	let (input, num_param) = u8(input)?;
+	let (input, is_vararg) = u8(input)?;
+	let (input, num_stack) = u8(input)?;
+
+	// essential
+	let (input, inst_list) = load_list(header, |i| load_instruction(header, i))(input)?;
+	let (input, value_list) = load_list(header, |i| load_constant(header, i))(input)?;
+	let (input, mut upval_list) = load_list(header, load_upvalue)(input)?;
+	let (input, child_list) = load_list(header, |i| {
+		let (i, depth) = load_depth(depth, i)?;
+
+		load_function(header, depth, i)
+	})(input)?;
+
+	// debug
+	let (input, line_list) = load_list(header, |i| load_int(header, i))(input)?;
+	let (input, local_list) = load_list(header, |i| load_local(header, i))(input)?;
+	let (input, name_list) = load_list(header, |i| load_string_opt(header, i))(input)?;
+
+	for (upv, name) in upval_list.iter_mut().zip(name_list) {
+		upv.name = name;
+	}
+
+	let result = Proto {
+		source,
+		is_vararg,
+		num_stack,
+		num_param,
+		line_defined,
+		last_line_defined,
+		value_list,
+		inst_list,
+		child_list,
+		upval_list,
+		line_list,
+		local_list,
+	};
+
+	Ok((input, result))
+}
+
+fn load_lua_chunk<'a>(header: &LuaHeader, input: &'a [u8]) -> Res<'a, Proto> {
+	let (input, _) = u8(input)?; // upvalues :)?
+
+	load_function(header, Depth::new(MAX_NESTING), input)
+}
+
+// 5.3 chunks are translated on load so that everything downstream only ever
+// deals with 5.4 prototypes, written back in the same byte layout
+pub fn load_lua_module(input: &[u8]) -> Result<(&[u8], Proto54), LoadError> {
+	check_signature(input, LUA_VERSION)?;
+
+	let header = parse_header(input)?;
+	let (rest, proto) =
+		load_lua_chunk(&header, &input[header.len..]).map_err(|e| LoadError::from_nom(input, e))?;
+	let mut proto = translate(proto)?;
+
+	proto.layout = header.layout;
+
+	Ok((rest, proto))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::lua54::{dumper::dump_lua_module, loader::load_lua_module as load_lua54_module};
+
+	// hand made for a 32 bit big-endian target, `size_t` and numbers included,
+	// with a 300 byte constant long enough to need the `size_t` length
+	const BE32: &[u8] = include_bytes!("../../tests/fixtures/lua53_be32.luac");
+
+	fn check_values(proto: &Proto54) {
+		let long: Vec<u8> = (0..=255).chain(0..44).collect();
+
+		assert!(matches!(&proto.value_list[0], Value::Bytes(v) if *v == long));
+		assert!(matches!(proto.value_list[1], Value::Integer(-2)));
+	}
+
+	#[test]
+	fn load_be32_chunk() {
+		let header = parse_header(BE32).unwrap();
+
+		assert_eq!(header.size_size_t, Some(4));
+		assert!(header.layout.big_endian);
+		assert_eq!(header.layout.size_integer, 4);
+		assert_eq!(header.layout.size_number, 4);
+
+		let (rest, proto) = load_lua_module(BE32).unwrap();
+
+		assert!(rest.is_empty());
+		check_values(&proto);
+	}
+
+	// written back as 5.4 in the layout it was read in
+	#[test]
+	fn dump_be32_layout() {
+		let (_, proto) = load_lua_module(BE32).unwrap();
+		let data = dump_lua_module(&proto).unwrap();
+		let header = parse_header(&data).unwrap();
+
+		assert!(header.layout == proto.layout);
+
+		let (_, proto) = load_lua54_module(&data).unwrap();
+
+		check_values(&proto);
+	}
+}
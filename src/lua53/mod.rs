@@ -0,0 +1,19 @@
+use crate::lua54::{common::types::Proto, format::BytecodeFormat, loader::LoadError};
+use std::io::{Error, Result};
+
+pub mod loader;
+mod translate;
+pub mod types;
+
+// read only, chunks come out as 5.4 and are written back as such
+pub struct Lua53;
+
+impl BytecodeFormat for Lua53 {
+	fn load<'a>(&self, input: &'a [u8]) -> std::result::Result<(&'a [u8], Proto), LoadError> {
+		loader::load_lua_module(input)
+	}
+
+	fn dump(&self, _proto: &Proto) -> Result<Vec<u8>> {
+		Err(Error::other("writing Lua 5.3 bytecode is not supported"))
+	}
+}
@@ -0,0 +1,600 @@
+use super::types::{Inst as Inst53, Opcode as Opcode53, Proto, BIT_RK, FIELDS_PER_FLUSH};
+use crate::{
+	common::types::{Layout, Local, Value},
+	lua54::{
+		common::{
+			inst::MetaMethod,
+			types::{AbsLine, Inst, Opcode, Proto as Proto54},
+		},
+		loader::LoadError,
+	},
+};
+use std::{convert::TryFrom, mem::take};
+
+// keys longer than this are not short strings and can't go in `GETFIELD`
+const MAX_SHORT_LEN: usize = 40;
+
+// 5.4 stores line deltas, falling back to an absolute line when the delta
+// does not fit or every so many instructions
+const LIMIT_LINE_DIFF: i64 = 0x80;
+const MAX_WITH_ABS: usize = 128;
+const ABS_LINE_INFO: i8 = -0x80;
+
+// 5.3 operands are wider than 5.4's in a few places, what doesn't fit is
+// refused rather than cut short
+const MAX_ARG_BX: u32 = (1 << 17) - 1;
+const MAX_ARG_AX: u32 = (1 << 25) - 1;
+const OFFSET_SJ: i32 = (1 << 24) - 1;
+const OUT_OF_RANGE: LoadError = LoadError::Untranslatable("operand out of range");
+
+// a jump whose offset is only known once every instruction is placed
+struct Fixup {
+	at: usize,
+	target: usize,
+}
+
+// `JMP` with a close level becomes a jump to a `CLOSE; JMP` pair at the end,
+// so the jump after a test stays a single instruction
+struct Trampoline {
+	at: usize,
+	close: u8,
+	target: usize,
+	line: u32,
+}
+
+struct Lifter<'a> {
+	proto: &'a Proto,
+	scratch: u8,
+	uses_scratch: bool,
+	ret_c: u8,
+	close: bool,
+	line: u32,
+	inst_list: Vec<Inst>,
+	line_list: Vec<u32>,
+	fixup_list: Vec<Fixup>,
+	tramp_list: Vec<Trampoline>,
+}
+
+fn is_constant(x: u16) -> bool {
+	x & BIT_RK != 0
+}
+
+fn jump_target(pc: usize, offset: i32) -> usize {
+	let target = pc as i64 + 1 + i64::from(offset);
+
+	usize::try_from(target).unwrap_or(usize::MAX)
+}
+
+fn offset(reg: u8, n: u8) -> Result<u8, LoadError> {
+	reg.checked_add(n).ok_or(OUT_OF_RANGE)
+}
+
+fn bx(x: i64) -> Result<u32, LoadError> {
+	u32::try_from(x)
+		.ok()
+		.filter(|&v| v <= MAX_ARG_BX)
+		.ok_or(OUT_OF_RANGE)
+}
+
+fn arith(op: Opcode53) -> Option<(Opcode, MetaMethod)> {
+	let pair = match op {
+		Opcode53::Add => (Opcode::Add, MetaMethod::Add),
+		Opcode53::Sub => (Opcode::Sub, MetaMethod::Sub),
+		Opcode53::Mul => (Opcode::Mul, MetaMethod::Mul),
+		Opcode53::Mod => (Opcode::Mod, MetaMethod::Mod),
+		Opcode53::Pow => (Opcode::Pow, MetaMethod::Pow),
+		Opcode53::Div => (Opcode::Div, MetaMethod::Div),
+		Opcode53::IDiv => (Opcode::IDiv, MetaMethod::IDiv),
+		Opcode53::Band => (Opcode::Band, MetaMethod::Band),
+		Opcode53::Bor => (Opcode::Bor, MetaMethod::Bor),
+		Opcode53::Bxor => (Opcode::Bxor, MetaMethod::Bxor),
+		Opcode53::Shl => (Opcode::Shl, MetaMethod::Shl),
+		Opcode53::Shr => (Opcode::Shr, MetaMethod::Shr),
+		_ => return None,
+	};
+
+	Some(pair)
+}
+
+fn unary(op: Opcode53) -> Option<Opcode> {
+	let op = match op {
+		Opcode53::Unm => Opcode::Unm,
+		Opcode53::Bnot => Opcode::Bnot,
+		Opcode53::Not => Opcode::Not,
+		Opcode53::Len => Opcode::Len,
+		_ => return None,
+	};
+
+	Some(op)
+}
+
+// table sizes in 5.3 are a "floating point byte", `eeeeexxx`, only a hint
+// so one too large for a `u32` is just capped
+fn from_float_byte(x: u16) -> u32 {
+	let x = u32::from(x);
+
+	match x >> 3 {
+		0 => x,
+		e if e < 29 => ((x & 7) + 8) << (e - 1),
+		_ => u32::MAX,
+	}
+}
+
+impl<'a> Lifter<'a> {
+	fn emit(&mut self, inst: Inst) {
+		self.inst_list.push(inst);
+		self.line_list.push(self.line);
+	}
+
+	fn jump_to(&mut self, op: Opcode, a: u8, target: usize) {
+		self.fixup_list.push(Fixup {
+			at: self.inst_list.len(),
+			target,
+		});
+
+		self.emit(Inst::iabx(op, a, 0));
+	}
+
+	fn constant(&self, x: u16) -> Option<&Value> {
+		if is_constant(x) {
+			self.proto.value_list.get(usize::from(x as u8))
+		} else {
+			None
+		}
+	}
+
+	fn short_key(&self, x: u16) -> Option<u8> {
+		match self.constant(x) {
+			Some(Value::String(v)) if v.len() <= MAX_SHORT_LEN => Some(x as u8),
+			Some(Value::Bytes(v)) if v.len() <= MAX_SHORT_LEN => Some(x as u8),
+			_ => None,
+		}
+	}
+
+	fn small_key(&self, x: u16) -> Option<u8> {
+		match self.constant(x) {
+			Some(Value::Integer(v)) => u8::try_from(*v).ok(),
+			_ => None,
+		}
+	}
+
+	// 5.4 only takes constants in a few places, everywhere else they are
+	// loaded into one of the two registers past the top of the stack
+	fn register(&mut self, x: u16, slot: u8) -> u8 {
+		if !is_constant(x) {
+			return x as u8;
+		}
+
+		// wraps only when the stack is already full, which `translate`
+		// refuses once it sees the scratch registers were used
+		let reg = self.scratch.wrapping_add(slot);
+
+		self.uses_scratch = true;
+		self.emit(Inst::iabx(Opcode::LoadK, reg, (x as u8).into()));
+
+		reg
+	}
+
+	fn get(&mut self, a: u8, table: u8, key: u16) {
+		if let Some(k) = self.short_key(key) {
+			self.emit(Inst::iabc(Opcode::GetField, a, table, k));
+		} else if let Some(n) = self.small_key(key) {
+			self.emit(Inst::iabc(Opcode::GetI, a, table, n));
+		} else {
+			let key = self.register(key, 1);
+
+			self.emit(Inst::iabc(Opcode::GetTable, a, table, key));
+		}
+	}
+
+	fn set(&mut self, table: u8, key: u16, value: u16) {
+		let k = is_constant(value);
+		let value = value as u8;
+
+		let inst = if let Some(b) = self.short_key(key) {
+			Inst::iabc(Opcode::SetField, table, b, value)
+		} else if let Some(n) = self.small_key(key) {
+			Inst::iabc(Opcode::SetI, table, n, value)
+		} else {
+			let key = self.register(key, 1);
+
+			Inst::iabc(Opcode::SetTable, table, key, value)
+		};
+
+		self.emit(inst.set_k(k));
+	}
+
+	fn eq(&mut self, k: bool, b: u16, c: u16) {
+		let inst = match (is_constant(b), is_constant(c)) {
+			(false, false) => Inst::iabc(Opcode::Eq, b as u8, c as u8, 0),
+			(false, true) => Inst::iabc(Opcode::EqK, b as u8, c as u8, 0),
+			(true, false) => Inst::iabc(Opcode::EqK, c as u8, b as u8, 0),
+			(true, true) => {
+				let b = self.register(b, 0);
+
+				Inst::iabc(Opcode::EqK, b, c as u8, 0)
+			}
+		};
+
+		self.emit(inst.set_k(k));
+	}
+
+	fn new_table(&mut self, a: u8, b: u16, c: u16) {
+		let array = from_float_byte(b);
+		let hash = from_float_byte(c);
+		let b = match hash {
+			0 => 0,
+			n => (32 - (n - 1).leading_zeros() + 1).min(u8::MAX.into()) as u8,
+		};
+
+		self.emit(Inst::iabc(Opcode::NewTable, a, b, array as u8).set_k(array > 0xFF));
+		self.emit(Inst::iax(Opcode::ExtraArg, array >> 8));
+	}
+
+	fn set_list(&mut self, a: u8, b: u16, block: u32) {
+		let offset = block.saturating_sub(1) * FIELDS_PER_FLUSH;
+		let inst = Inst::iabc(Opcode::SetList, a, b as u8, offset as u8);
+
+		if offset > 0xFF {
+			self.emit(inst.set_k(true));
+			self.emit(Inst::iax(Opcode::ExtraArg, offset >> 8));
+		} else {
+			self.emit(inst);
+		}
+	}
+
+	fn lift_one(&mut self, pc: usize, inst: Inst53) -> Result<(), LoadError> {
+		let (a, b, c) = (inst.a(), inst.b(), inst.c());
+		let op = inst.opcode();
+
+		if let Some((op, event)) = arith(op) {
+			let b = self.register(b, 0);
+			let c = self.register(c, 1);
+
+			self.emit(Inst::iabc(op, a, b, c));
+			self.emit(Inst::iabc(Opcode::MmBin, b, c, event));
+
+			return Ok(());
+		}
+
+		if let Some(op) = unary(op) {
+			self.emit(Inst::iabc(op, a, b as u8, 0));
+
+			return Ok(());
+		}
+
+		match op {
+			Opcode53::Move => self.emit(Inst::iabc(Opcode::Move, a, b as u8, 0)),
+			// a constant past what `Bx` holds goes through `EXTRAARG` like
+			// 5.4 would have written it
+			Opcode53::LoadK if inst.bx() > MAX_ARG_BX => {
+				self.emit(Inst::iabc(Opcode::LoadKX, a, 0, 0));
+				self.emit(Inst::iax(Opcode::ExtraArg, inst.bx()));
+			}
+			Opcode53::LoadK => self.emit(Inst::iabx(Opcode::LoadK, a, inst.bx())),
+			Opcode53::LoadKX => self.emit(Inst::iabc(Opcode::LoadKX, a, 0, 0)),
+			Opcode53::LoadBool => {
+				let op = if b == 0 {
+					Opcode::LoadFalse
+				} else {
+					Opcode::LoadTrue
+				};
+
+				self.emit(Inst::iabc(op, a, 0, 0));
+
+				// the skip is a jump of its own, whatever follows may have
+				// grown past a single instruction
+				if c != 0 {
+					self.jump_to(Opcode::Jmp, 0, pc + 2);
+				}
+			}
+			Opcode53::LoadNil => self.emit(Inst::iabc(Opcode::LoadNil, a, b as u8, 0)),
+			Opcode53::GetUpval => self.emit(Inst::iabc(Opcode::GetUpval, a, b as u8, 0)),
+			Opcode53::SetUpval => self.emit(Inst::iabc(Opcode::SetUpval, a, b as u8, 0)),
+			Opcode53::GetTabUp => match self.short_key(c) {
+				Some(k) => self.emit(Inst::iabc(Opcode::GetTabUp, a, b as u8, k)),
+				None => {
+					self.uses_scratch = true;
+					self.emit(Inst::iabc(Opcode::GetUpval, self.scratch, b as u8, 0));
+					self.get(a, self.scratch, c);
+				}
+			},
+			Opcode53::GetTable => self.get(a, b as u8, c),
+			Opcode53::SetTabUp => match self.short_key(b) {
+				Some(k) => {
+					let inst = Inst::iabc(Opcode::SetTabUp, a, k, c as u8);
+
+					self.emit(inst.set_k(is_constant(c)));
+				}
+				None => {
+					self.uses_scratch = true;
+					self.emit(Inst::iabc(Opcode::GetUpval, self.scratch, a, 0));
+					self.set(self.scratch, b, c);
+				}
+			},
+			Opcode53::SetTable => self.set(a, b, c),
+			Opcode53::NewTable => self.new_table(a, b, c),
+			Opcode53::Method => {
+				let inst = Inst::iabc(Opcode::Method, a, b as u8, c as u8);
+
+				self.emit(inst.set_k(is_constant(c)));
+			}
+			Opcode53::Concat => {
+				let count = (c + 1).checked_sub(b).ok_or(OUT_OF_RANGE)?;
+
+				self.emit(Inst::iabc(Opcode::Concat, b as u8, count as u8, 0));
+
+				if u16::from(a) != b {
+					self.emit(Inst::iabc(Opcode::Move, a, b as u8, 0));
+				}
+			}
+			Opcode53::Jmp => {
+				let target = jump_target(pc, inst.sbx());
+
+				if a == 0 {
+					self.jump_to(Opcode::Jmp, 0, target);
+				} else {
+					self.tramp_list.push(Trampoline {
+						at: self.inst_list.len(),
+						close: a - 1,
+						target,
+						line: self.line,
+					});
+
+					self.emit(Inst::isj(Opcode::Jmp, 0));
+				}
+			}
+			Opcode53::Eq => self.eq(a != 0, b, c),
+			Opcode53::Lt | Opcode53::Le => {
+				let op = if op == Opcode53::Lt {
+					Opcode::Lt
+				} else {
+					Opcode::Le
+				};
+				let b = self.register(b, 0);
+				let c = self.register(c, 1);
+
+				self.emit(Inst::iabc(op, b, c, 0).set_k(a != 0));
+			}
+			Opcode53::Test => self.emit(Inst::iabc(Opcode::Test, a, 0, 0).set_k(c != 0)),
+			Opcode53::TestSet => {
+				let inst = Inst::iabc(Opcode::TestSet, a, b as u8, 0);
+
+				self.emit(inst.set_k(c != 0));
+			}
+			Opcode53::Call => self.emit(Inst::iabc(Opcode::Call, a, b as u8, c as u8)),
+			// 5.3 always closes upvalues on the way out when there are closures
+			Opcode53::TailCall => {
+				let inst = Inst::iabc(Opcode::TailCall, a, b as u8, self.ret_c);
+
+				self.emit(inst.set_k(self.close));
+			}
+			Opcode53::Return => {
+				let inst = Inst::iabc(Opcode::Return, a, b as u8, self.ret_c);
+
+				self.emit(inst.set_k(self.close));
+			}
+			// the loop layout is the same, only the offsets are counted
+			// from different places
+			Opcode53::ForLoop | Opcode53::ForPrep => {
+				let op = if op == Opcode53::ForLoop {
+					Opcode::ForLoop
+				} else {
+					Opcode::ForPrep
+				};
+
+				self.jump_to(op, a, jump_target(pc, inst.sbx()));
+			}
+			// 5.4 keeps a fourth control register, so the call is spelled
+			// out on the 5.3 layout instead
+			Opcode53::TForCall => {
+				let base = offset(a, 3)?;
+
+				for i in 0..3 {
+					self.emit(Inst::iabc(Opcode::Move, offset(base, i)?, a + i, 0));
+				}
+
+				self.emit(Inst::iabc(Opcode::Call, base, 3, offset(c as u8, 1)?));
+			}
+			// if R(A+1) ~= nil then { R(A) := R(A+1); pc += sBx }
+			Opcode53::TForLoop => {
+				let value = offset(a, 1)?;
+
+				self.uses_scratch = true;
+				self.emit(Inst::iabc(Opcode::LoadNil, self.scratch, 0, 0));
+				self.emit(Inst::iabc(Opcode::Eq, value, self.scratch, 0).set_k(true));
+				self.emit(Inst::isj(Opcode::Jmp, 2));
+				self.emit(Inst::iabc(Opcode::Move, a, value, 0));
+				self.jump_to(Opcode::Jmp, 0, jump_target(pc, inst.sbx()));
+			}
+			Opcode53::SetList => self.set_list(a, b, c.into()),
+			Opcode53::Closure => self.emit(Inst::iabx(Opcode::Closure, a, bx(inst.bx().into())?)),
+			Opcode53::Vararg => self.emit(Inst::iabc(Opcode::Vararg, a, 0, b as u8)),
+			Opcode53::ExtraArg if inst.ax() > MAX_ARG_AX => return Err(OUT_OF_RANGE),
+			Opcode53::ExtraArg => self.emit(Inst::iax(Opcode::ExtraArg, inst.ax())),
+			_ => self.emit(Inst::default()),
+		}
+
+		Ok(())
+	}
+
+	// returns where each 5.3 instruction ended up, plus one past the end
+	fn lift(&mut self) -> Result<Vec<usize>, LoadError> {
+		let code = &self.proto.inst_list;
+		let mut pc_map = Vec::with_capacity(code.len() + 1);
+		let mut pc = 0;
+
+		if self.proto.is_vararg != 0 {
+			self.line = self.proto.line_list.first().copied().unwrap_or_default();
+			self.emit(Inst::iabc(Opcode::VarargPrep, self.proto.num_param, 0, 0));
+		}
+
+		while pc < code.len() {
+			let inst = code[pc];
+
+			pc_map.push(self.inst_list.len());
+			self.line = self.proto.line_list.get(pc).copied().unwrap_or_default();
+
+			// a zero block count is taken from the `EXTRAARG` right after
+			if inst.opcode() == Opcode53::SetList && inst.c() == 0 {
+				let block = code.get(pc + 1).map_or(0, |v| v.ax());
+
+				self.set_list(inst.a(), inst.b(), block);
+
+				pc_map.push(self.inst_list.len());
+				pc += 2;
+			} else {
+				self.lift_one(pc, inst)?;
+
+				pc += 1;
+			}
+		}
+
+		pc_map.truncate(code.len());
+		pc_map.push(self.inst_list.len());
+
+		Ok(pc_map)
+	}
+
+	fn patch(&mut self, pc_map: &[usize]) -> Result<(), LoadError> {
+		for tramp in take(&mut self.tramp_list) {
+			let here = self.inst_list.len();
+
+			self.line = tramp.line;
+			self.emit(Inst::iabc(Opcode::Close, tramp.close, 0, 0));
+			self.jump_to(Opcode::Jmp, 0, tramp.target);
+
+			self.inst_list[tramp.at] = Inst::isj(Opcode::Jmp, (here - tramp.at - 1) as i32);
+		}
+
+		let end = self.inst_list.len();
+
+		for fix in &self.fixup_list {
+			let to = pc_map.get(fix.target).copied().unwrap_or(end) as i64;
+			let at = fix.at as i64;
+			let inst = self.inst_list[fix.at];
+
+			// the loop offsets are unsigned, so each only goes one way
+			self.inst_list[fix.at] = match inst.opcode() {
+				Opcode::ForPrep => Inst::iabx(Opcode::ForPrep, inst.a(), bx(to - at - 1)?),
+				Opcode::ForLoop => Inst::iabx(Opcode::ForLoop, inst.a(), bx(at + 1 - to)?),
+				_ => {
+					let sj = i32::try_from(to - at - 1)
+						.ok()
+						.filter(|v| (-OFFSET_SJ..=OFFSET_SJ + 1).contains(v))
+						.ok_or(OUT_OF_RANGE)?;
+
+					Inst::isj(Opcode::Jmp, sj)
+				}
+			};
+		}
+
+		Ok(())
+	}
+}
+
+fn encode_lines(line_defined: u32, line_list: &[u32]) -> (Vec<i8>, Vec<AbsLine>) {
+	let mut rel_line_list = Vec::with_capacity(line_list.len());
+	let mut abs_line_list = Vec::new();
+	let mut previous = line_defined;
+	let mut with_abs = 0;
+
+	for (pc, &line) in line_list.iter().enumerate() {
+		let diff = i64::from(line) - i64::from(previous);
+
+		if diff.abs() >= LIMIT_LINE_DIFF || with_abs >= MAX_WITH_ABS {
+			abs_line_list.push(AbsLine::from((pc as u32, line)));
+			rel_line_list.push(ABS_LINE_INFO);
+			with_abs = 1;
+		} else {
+			rel_line_list.push(diff as i8);
+			with_abs += 1;
+		}
+
+		previous = line;
+	}
+
+	(rel_line_list, abs_line_list)
+}
+
+// rewrites a 5.3 prototype into an equivalent 5.4 one, instructions may grow
+// into several and every jump and debug range is moved along with them
+pub fn translate(proto: Proto) -> Result<Proto54, LoadError> {
+	let mut lift = Lifter {
+		proto: &proto,
+		scratch: proto.num_stack,
+		uses_scratch: false,
+		ret_c: if proto.is_vararg != 0 {
+			proto.num_param + 1
+		} else {
+			0
+		},
+		close: !proto.child_list.is_empty(),
+		line: proto.line_defined,
+		inst_list: Vec::new(),
+		line_list: Vec::new(),
+		fixup_list: Vec::new(),
+		tramp_list: Vec::new(),
+	};
+
+	let pc_map = lift.lift()?;
+
+	lift.patch(&pc_map)?;
+
+	let num_stack = if lift.uses_scratch {
+		proto
+			.num_stack
+			.checked_add(2)
+			.ok_or(LoadError::Untranslatable(
+				"no registers left for temporaries",
+			))?
+	} else {
+		proto.num_stack
+	};
+
+	let (rel_line_list, abs_line_list) = if proto.line_list.is_empty() {
+		(Vec::new(), Vec::new())
+	} else {
+		encode_lines(proto.line_defined, &lift.line_list)
+	};
+
+	let remap = |pc: u32| {
+		let pc = pc_map.get(pc as usize).or_else(|| pc_map.last());
+
+		pc.copied().unwrap_or_default() as u32
+	};
+
+	let local_list = proto
+		.local_list
+		.iter()
+		.map(|v| Local {
+			name: v.name.clone(),
+			start_pc: remap(v.start_pc),
+			end_pc: remap(v.end_pc),
+		})
+		.collect();
+
+	let inst_list = lift.inst_list;
+	let child_list = proto
+		.child_list
+		.into_iter()
+		.map(translate)
+		.collect::<Result<_, _>>()?;
+
+	Ok(Proto54 {
+		layout: Layout::default(),
+		source: proto.source,
+		is_vararg: proto.is_vararg,
+		num_stack,
+		num_param: proto.num_param,
+		line_defined: proto.line_defined,
+		last_line_defined: proto.last_line_defined,
+		value_list: proto.value_list,
+		inst_list,
+		child_list,
+		upval_list: proto.upval_list,
+		rel_line_list,
+		abs_line_list,
+		local_list,
+	})
+}
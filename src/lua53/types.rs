@@ -0,0 +1,148 @@
+use crate::common::types::{Instruction, Local, Upvalue, Value};
+use bit_field::BitField;
+use num_enum::{FromPrimitive, IntoPrimitive, TryFromPrimitive};
+
+pub const LUA_VERSION: u8 = 0x53;
+
+// `RK(x)` operands refer to a constant when this bit is set
+pub const BIT_RK: u16 = 1 << 8;
+
+// `SETLIST` flushes this many table items per instruction
+pub const FIELDS_PER_FLUSH: u32 = 50;
+
+const MAX_ARG_SBX: i32 = ((1 << 18) - 1) >> 1;
+
+#[derive(Clone, Copy)]
+pub struct Inst {
+	pub inner: Instruction,
+}
+
+impl Inst {
+	pub fn opcode(self) -> Opcode {
+		Opcode::from(self.inner.get_bits(0..6) as u8)
+	}
+
+	pub fn a(self) -> u8 {
+		self.inner.get_bits(6..14) as u8
+	}
+
+	pub fn b(self) -> u16 {
+		self.inner.get_bits(23..32) as u16
+	}
+
+	pub fn c(self) -> u16 {
+		self.inner.get_bits(14..23) as u16
+	}
+
+	pub fn bx(self) -> u32 {
+		self.inner.get_bits(14..32)
+	}
+
+	pub fn sbx(self) -> i32 {
+		self.bx() as i32 - MAX_ARG_SBX
+	}
+
+	pub fn ax(self) -> u32 {
+		self.inner.get_bits(6..32)
+	}
+}
+
+#[derive(TryFromPrimitive, IntoPrimitive, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Constant {
+	Nil = 0x00,
+	Boolean = 0x01,
+	Number = 0x03,
+	Integer = 0x13,
+	ShortString = 0x04,
+	LongString = 0x14,
+}
+
+#[derive(FromPrimitive, IntoPrimitive, PartialEq, Eq, Clone, Copy)]
+#[repr(u8)]
+pub enum Opcode {
+	Move = 0,
+	LoadK,
+	LoadKX,
+	LoadBool,
+	LoadNil,
+	GetUpval,
+
+	GetTabUp,
+	GetTable,
+
+	SetTabUp,
+	SetUpval,
+	SetTable,
+
+	NewTable,
+
+	Method,
+
+	Add,
+	Sub,
+	Mul,
+	Mod,
+	Pow,
+	Div,
+	IDiv,
+
+	Band,
+	Bor,
+	Bxor,
+	Shl,
+	Shr,
+
+	Unm,
+	Bnot,
+	Not,
+	Len,
+
+	Concat,
+
+	Jmp,
+	Eq,
+	Lt,
+	Le,
+
+	Test,
+	TestSet,
+
+	Call,
+	TailCall,
+
+	Return,
+
+	ForLoop,
+	ForPrep,
+
+	TForCall,
+	TForLoop,
+
+	SetList,
+
+	Closure,
+
+	Vararg,
+
+	ExtraArg,
+
+	#[num_enum(default)]
+	Invalid,
+}
+
+// unlike 5.4, line info is kept as one absolute line per instruction
+pub struct Proto {
+	pub source: Option<String>,
+	pub is_vararg: u8,
+	pub num_stack: u8,
+	pub num_param: u8,
+	pub line_defined: u32,
+	pub last_line_defined: u32,
+	pub value_list: Vec<Value>,
+	pub inst_list: Vec<Inst>,
+	pub child_list: Vec<Proto>,
+	pub upval_list: Vec<Upvalue>,
+	pub line_list: Vec<u32>,
+	pub local_list: Vec<Local>,
+}
@@ -0,0 +1,303 @@
+use super::successor_map;
+use crate::{
+	common::types::{Function, Value},
+	lua54::common::inst::{Block, Reg, RegOrK, IR},
+};
+use std::{
+	collections::{BTreeSet, HashMap, HashSet},
+	sync::Arc,
+};
+
+// protos by dotted child path in the order the listing prints them, each with
+// the proto whose `CLOSURE` makes it, and the calls seen between them
+pub struct CallGraph {
+	pub path_list: Vec<String>,
+	pub name_list: Vec<Arc<str>>,
+	pub parent_list: Vec<Option<usize>>,
+	pub call_list: Vec<(usize, usize)>,
+}
+
+type Callees = BTreeSet<usize>;
+type State = HashMap<u8, Callees>;
+
+// where the value behind an upvalue lives, a register of some proto or one of
+// the upvalues the main function is handed, `_ENV` among them
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+enum Cell {
+	Register(usize, u8),
+	Upvalue(u8),
+}
+
+struct Node<'a> {
+	func: &'a Function<Block>,
+	parent: Option<usize>,
+	child_list: Vec<usize>,
+	// registers a child captures, whatever they ever hold is what it reads
+	captured: HashSet<u8>,
+}
+
+// every proto once, parents right before their children
+fn flatten(func: &Function<Block>) -> (Vec<Node<'_>>, Vec<String>, Vec<Arc<str>>) {
+	let mut node_list = Vec::new();
+	let mut path_list = Vec::new();
+	let mut name_list = Vec::new();
+	let mut stack = vec![(func, None, String::new(), Arc::from("main"))];
+
+	while let Some((func, parent, path, name)) = stack.pop() {
+		let index = node_list.len();
+
+		if let Some(parent) = parent {
+			let node: &mut Node = &mut node_list[parent];
+
+			node.child_list.push(index);
+		}
+
+		let captured = func
+			.child_list
+			.iter()
+			.flat_map(|(_, child)| &child.upval_list)
+			.filter(|(_, upval)| upval.in_stack)
+			.map(|(_, upval)| upval.index)
+			.collect();
+
+		node_list.push(Node {
+			func,
+			parent,
+			child_list: Vec::new(),
+			captured,
+		});
+		path_list.push(if path.is_empty() {
+			"main".to_string()
+		} else {
+			path.clone()
+		});
+		name_list.push(name);
+
+		for (child, (name, func)) in func.child_list.iter().enumerate().rev() {
+			let path = match path.as_str() {
+				"" => child.to_string(),
+				_ => format!("{}.{}", path, child),
+			};
+
+			stack.push((func, Some(index), path, Arc::clone(name)));
+		}
+	}
+
+	(node_list, path_list, name_list)
+}
+
+fn add_all(to: &mut Callees, from: &Callees) -> bool {
+	let len = to.len();
+
+	to.extend(from);
+	to.len() != len
+}
+
+#[derive(Default)]
+struct Scan {
+	cell_map: HashMap<Cell, Callees>,
+	// fields of tables held in an upvalue, the globals being those of `_ENV`
+	field_map: HashMap<(Cell, String), Callees>,
+	call_set: BTreeSet<(usize, usize)>,
+	changed: bool,
+}
+
+impl Scan {
+	fn upvalue_cell(node_list: &[Node], index: usize, name: &str) -> Option<Cell> {
+		let func = node_list[index].func;
+		let upval = func.upval_list.iter().position(|v| v.0.as_ref() == name)?;
+
+		Self::cell_at(node_list, index, upval as u8)
+	}
+
+	fn cell_at(node_list: &[Node], index: usize, upval: u8) -> Option<Cell> {
+		let node = &node_list[index];
+		let (_, info) = node.func.upval_list.get(usize::from(upval))?;
+
+		match node.parent {
+			Some(parent) if info.in_stack => Some(Cell::Register(parent, info.index)),
+			Some(parent) => Self::cell_at(node_list, parent, info.index),
+			None => Some(Cell::Upvalue(upval)),
+		}
+	}
+
+	// only string keys name a field the same way in every proto
+	fn field_key(func: &Function<Block>, name: &str) -> Option<String> {
+		match func.value_list.iter().find(|v| v.0.as_ref() == name) {
+			Some((_, Value::String(text))) => Some(text.to_string()),
+			_ => None,
+		}
+	}
+
+	fn add_cell(&mut self, cell: Cell, callees: &Callees) {
+		if callees.is_empty() {
+			return;
+		}
+
+		self.changed |= add_all(self.cell_map.entry(cell).or_default(), callees);
+	}
+
+	fn add_field(&mut self, key: (Cell, String), callees: &Callees) {
+		if callees.is_empty() {
+			return;
+		}
+
+		self.changed |= add_all(self.field_map.entry(key).or_default(), callees);
+	}
+
+	fn field(&self, node_list: &[Node], index: usize, upval: &str, key: &str) -> Callees {
+		let func = node_list[index].func;
+		let cell = Self::upvalue_cell(node_list, index, upval);
+		let key = Self::field_key(func, key);
+
+		match (cell, key) {
+			(Some(cell), Some(key)) => self.field_map.get(&(cell, key)).cloned(),
+			_ => None,
+		}
+		.unwrap_or_default()
+	}
+
+	fn step(&mut self, node_list: &[Node], index: usize, ir: &IR, state: &mut State) {
+		let node = &node_list[index];
+		let held = |state: &State, reg: &Reg| {
+			let Reg::R(reg) = reg;
+
+			state.get(reg).cloned().unwrap_or_default()
+		};
+
+		match ir {
+			IR::Call(func, ..) | IR::TailCall(func, ..) | IR::TForCall(func, _) => {
+				for callee in held(state, func) {
+					self.call_set.insert((index, callee));
+				}
+			}
+			IR::SetUpval(a, upval) => {
+				if let Some(cell) = Self::upvalue_cell(node_list, index, upval) {
+					self.add_cell(cell, &held(state, a));
+				}
+			}
+			IR::SetTabUp(upval, key, RegOrK::R(a)) => {
+				let cell = Self::upvalue_cell(node_list, index, upval);
+				let key = Self::field_key(node.func, key);
+
+				if let (Some(cell), Some(key)) = (cell, key) {
+					self.add_field((cell, key), &held(state, &Reg::R(*a)));
+				}
+			}
+			_ => {}
+		}
+
+		let value = match ir {
+			IR::Move(a, b) => Some((a, held(state, b))),
+			IR::Closure(a, name) => {
+				let child = node.func.child_list.iter().position(|v| v.0 == *name);
+
+				Some((a, child.map(|v| node.child_list[v]).into_iter().collect()))
+			}
+			IR::GetUpval(a, upval) => {
+				let cell = Self::upvalue_cell(node_list, index, upval);
+
+				Some((
+					a,
+					cell.and_then(|v| self.cell_map.get(&v).cloned())
+						.unwrap_or_default(),
+				))
+			}
+			IR::GetTabUp(a, upval, key) => Some((a, self.field(node_list, index, upval, key))),
+			_ => None,
+		};
+
+		for reg in ir.defs_uses().0 {
+			state.remove(&reg);
+		}
+
+		if let Some((Reg::R(a), callees)) = value {
+			if !callees.is_empty() {
+				state.insert(*a, callees);
+			}
+		}
+
+		for (&reg, callees) in state.iter() {
+			if node.captured.contains(&reg) {
+				self.changed |= add_all(
+					self.cell_map.entry(Cell::Register(index, reg)).or_default(),
+					callees,
+				);
+			}
+		}
+	}
+
+	// what each register may hold, forward from block `0` until nothing grows
+	fn scan(&mut self, node_list: &[Node], index: usize) {
+		let func = node_list[index].func;
+		let succ_map = successor_map(func);
+		let block_map: HashMap<u32, &Block> =
+			func.block_list.iter().map(|v| (v.label, v)).collect();
+		let mut in_map: HashMap<u32, State> = HashMap::new();
+		let mut work_list = Vec::new();
+
+		if block_map.contains_key(&0) {
+			in_map.insert(0, State::new());
+			work_list.push(0);
+		}
+
+		while let Some(label) = work_list.pop() {
+			let blk = block_map[&label];
+			let mut state = in_map[&label].clone();
+
+			for ir in &blk.body {
+				self.step(node_list, index, ir, &mut state);
+			}
+
+			for reg in blk.edge.defs_uses().0 {
+				state.remove(&reg);
+			}
+
+			// a label nothing is defined for leads nowhere
+			for next in succ_map[&label]
+				.iter()
+				.filter(|v| block_map.contains_key(v))
+			{
+				let mut grew = !in_map.contains_key(next);
+				let next_state = in_map.entry(*next).or_default();
+
+				for (&reg, callees) in &state {
+					grew |= add_all(next_state.entry(reg).or_default(), callees);
+				}
+
+				if grew && !work_list.contains(next) {
+					work_list.push(*next);
+				}
+			}
+		}
+	}
+}
+
+// calls are followed through registers, upvalues and string keyed fields of
+// tables in upvalues, which covers locals and globals but not tables built in
+// a register, a call through one of those just isn't seen
+pub fn call_graph(func: &Function<Block>) -> CallGraph {
+	let (node_list, path_list, name_list) = flatten(func);
+	let mut scan = Scan::default();
+
+	// an upvalue or a global written in one proto can be read by one scanned
+	// before it, so every proto is scanned again until none of them grows
+	loop {
+		scan.changed = false;
+
+		for index in 0..node_list.len() {
+			scan.scan(&node_list, index);
+		}
+
+		if !scan.changed {
+			break;
+		}
+	}
+
+	CallGraph {
+		path_list,
+		name_list,
+		parent_list: node_list.iter().map(|v| v.parent).collect(),
+		call_list: scan.call_set.into_iter().collect(),
+	}
+}
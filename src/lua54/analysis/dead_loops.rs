@@ -0,0 +1,91 @@
+use super::{postorder, successor_map};
+use crate::{
+	common::types::Function,
+	lua54::common::inst::{Block, Control},
+};
+use std::collections::{HashMap, HashSet};
+
+// a jump to a label nothing is defined for goes somewhere this can't see, so
+// it counts as a way out along with every return
+fn is_exit(blk: &Block, succ_map: &HashMap<u32, Vec<u32>>) -> bool {
+	let is_return = matches!(
+		blk.edge,
+		Control::Return(..) | Control::Return0(_) | Control::Return1(_)
+	);
+
+	is_return
+		|| succ_map[&blk.label]
+			.iter()
+			.any(|v| !succ_map.contains_key(v))
+}
+
+// every block `from` leads to, walking `edge_map` only through `allowed`
+fn walk(
+	edge_map: &HashMap<u32, Vec<u32>>,
+	from: u32,
+	allowed: &HashSet<u32>,
+	seen: &mut HashSet<u32>,
+) -> Vec<u32> {
+	let mut found = Vec::new();
+	let mut stack = vec![from];
+
+	while let Some(label) = stack.pop() {
+		if !allowed.contains(&label) || !seen.insert(label) {
+			continue;
+		}
+
+		found.push(label);
+		stack.extend(edge_map.get(&label).into_iter().flatten());
+	}
+
+	found
+}
+
+// each strongly connected set of blocks reachable from `entry` that loops with
+// no way to reach a return, one block only counting when it jumps to itself,
+// which is what a decoding mistake or a trap left in on purpose looks like
+pub fn find_dead_loops(func: &Function<Block>, entry: u32) -> Vec<Vec<u32>> {
+	let succ_map = successor_map(func);
+	let mut pred_map: HashMap<u32, Vec<u32>> = HashMap::new();
+
+	for (&label, succ_list) in &succ_map {
+		for &next in succ_list {
+			pred_map.entry(next).or_default().push(label);
+		}
+	}
+
+	let all: HashSet<u32> = succ_map.keys().copied().collect();
+	let mut can_exit = HashSet::new();
+
+	for blk in func.block_list.iter().filter(|v| is_exit(v, &succ_map)) {
+		walk(&pred_map, blk.label, &all, &mut can_exit);
+	}
+
+	// reverse postorder visits each component before any it leads to, so
+	// walking the predecessors from there picks out one component at a time
+	let mut order = postorder(&succ_map, entry);
+
+	order.retain(|v| !can_exit.contains(v));
+	order.reverse();
+
+	let trapped: HashSet<u32> = order.iter().copied().collect();
+	let mut seen = HashSet::new();
+	let mut loop_list = Vec::new();
+
+	for label in order {
+		if seen.contains(&label) {
+			continue;
+		}
+
+		let mut member_list = walk(&pred_map, label, &trapped, &mut seen);
+		let is_cycle = member_list.len() > 1 || succ_map[&label].contains(&label);
+
+		if is_cycle {
+			member_list.sort_unstable();
+			loop_list.push(member_list);
+		}
+	}
+
+	loop_list.sort_unstable();
+	loop_list
+}
@@ -0,0 +1,100 @@
+use super::{postorder, successor_map};
+use crate::{common::types::Function, lua54::common::inst::Block};
+use std::collections::HashMap;
+
+// Cooper, Harvey and Kennedy's "A Simple, Fast Dominance Algorithm", the entry
+// is its own immediate dominator and a missing entry dominates nothing
+fn immediate_dominators(succ_map: &HashMap<u32, Vec<u32>>, entry: u32) -> HashMap<u32, u32> {
+	if !succ_map.contains_key(&entry) {
+		return HashMap::new();
+	}
+
+	let order = postorder(succ_map, entry);
+	let number: HashMap<u32, usize> = order.iter().enumerate().map(|(i, v)| (*v, i)).collect();
+	let mut pred_map: HashMap<u32, Vec<u32>> = HashMap::new();
+
+	for &label in &order {
+		for &next in &succ_map[&label] {
+			pred_map.entry(next).or_default().push(label);
+		}
+	}
+
+	let mut idom = HashMap::new();
+	let mut changed = true;
+
+	idom.insert(entry, entry);
+
+	let intersect = |idom: &HashMap<u32, u32>, mut a: u32, mut b: u32| {
+		while a != b {
+			while number[&a] < number[&b] {
+				a = idom[&a];
+			}
+
+			while number[&b] < number[&a] {
+				b = idom[&b];
+			}
+		}
+
+		a
+	};
+
+	while changed {
+		changed = false;
+
+		for &label in order.iter().rev().skip(1) {
+			let mut pred_list = pred_map[&label].iter().filter(|v| idom.contains_key(v));
+			let first = *pred_list
+				.next()
+				.expect("reachable block without a predecessor");
+			let new_idom = pred_list.fold(first, |acc, &v| intersect(&idom, v, acc));
+
+			if idom.insert(label, new_idom) != Some(new_idom) {
+				changed = true;
+			}
+		}
+	}
+
+	idom
+}
+
+pub fn dominates(idom: &HashMap<u32, u32>, a: u32, mut b: u32) -> bool {
+	loop {
+		if a == b {
+			return true;
+		}
+
+		match idom.get(&b) {
+			Some(&next) if next != b => b = next,
+			_ => return false,
+		}
+	}
+}
+
+// each block reachable from `entry` mapped to its immediate dominator, with the
+// entry as the root and mapped to itself
+pub fn dominators(func: &Function<Block>, entry: u32) -> HashMap<u32, u32> {
+	immediate_dominators(&successor_map(func), entry)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	// 0 branches to 1 and 2, which both go on to 3
+	#[test]
+	fn diamond_merge_is_dominated_by_entry() {
+		let succ_map: HashMap<u32, Vec<u32>> =
+			vec![(0, vec![1, 2]), (1, vec![3]), (2, vec![3]), (3, vec![])]
+				.into_iter()
+				.collect();
+		let idom = immediate_dominators(&succ_map, 0);
+
+		assert_eq!(idom[&0], 0);
+		assert_eq!(idom[&1], 0);
+		assert_eq!(idom[&2], 0);
+		assert_eq!(idom[&3], 0);
+		assert!(dominates(&idom, 0, 3));
+		assert!(!dominates(&idom, 1, 3));
+		assert!(!dominates(&idom, 2, 3));
+	}
+}
@@ -0,0 +1,130 @@
+use super::successor_map;
+use crate::{
+	common::types::Function,
+	lua54::common::inst::{Block, IR},
+};
+use std::collections::{BTreeSet, HashMap, HashSet, VecDeque};
+
+#[derive(Clone, Default, PartialEq)]
+pub struct LiveSet {
+	pub live_in: BTreeSet<u8>,
+	pub live_out: BTreeSet<u8>,
+}
+
+// what the block reads before writing it, and what it writes
+fn block_summary(blk: &Block) -> (BTreeSet<u8>, BTreeSet<u8>) {
+	let mut use_set = BTreeSet::new();
+	let mut def_set = BTreeSet::new();
+	let edge = blk.edge.defs_uses();
+	let body = blk.body.iter().rev().map(IR::defs_uses);
+
+	for (def_list, use_list) in std::iter::once(edge).chain(body) {
+		for reg in def_list {
+			use_set.remove(&reg);
+			def_set.insert(reg);
+		}
+
+		use_set.extend(use_list);
+	}
+
+	(use_set, def_set)
+}
+
+// registers a child proto captures stay open upvalues that any call could
+// read, so they are never taken to be dead
+fn captured_registers(func: &Function<Block>) -> BTreeSet<u8> {
+	func.child_list
+		.iter()
+		.flat_map(|(_, child)| &child.upval_list)
+		.filter(|(_, upval)| upval.in_stack)
+		.map(|(_, upval)| upval.index)
+		.collect()
+}
+
+// registers live on entry to and exit from each block, by the usual backward
+// dataflow over the edges `successor_map` lists, nested protos are left out
+pub fn liveness(func: &Function<Block>) -> HashMap<u32, LiveSet> {
+	let succ_map = successor_map(func);
+	let captured = captured_registers(func);
+	let summary_map: HashMap<u32, _> = func
+		.block_list
+		.iter()
+		.map(|v| (v.label, block_summary(v)))
+		.collect();
+	let mut pred_map: HashMap<u32, Vec<u32>> = HashMap::new();
+
+	for (&label, succ_list) in &succ_map {
+		for &next in succ_list {
+			pred_map.entry(next).or_default().push(label);
+		}
+	}
+
+	let mut live_map: HashMap<u32, LiveSet> = func
+		.block_list
+		.iter()
+		.map(|v| (v.label, LiveSet::default()))
+		.collect();
+	let mut work_list: VecDeque<u32> = func.block_list.iter().map(|v| v.label).collect();
+	let mut queued: HashSet<u32> = work_list.iter().copied().collect();
+
+	while let Some(label) = work_list.pop_front() {
+		queued.remove(&label);
+
+		// a label nothing is defined for leads nowhere
+		let mut live_out = captured.clone();
+
+		for next in &succ_map[&label] {
+			if let Some(live) = live_map.get(next) {
+				live_out.extend(&live.live_in);
+			}
+		}
+
+		let (use_set, def_set) = &summary_map[&label];
+		let mut live_in: BTreeSet<u8> = live_out.difference(def_set).copied().collect();
+
+		live_in.extend(use_set);
+		live_in.extend(&captured);
+
+		let live = live_map.get_mut(&label).unwrap();
+		let changed = live.live_in != live_in;
+
+		live.live_in = live_in;
+		live.live_out = live_out;
+
+		if changed {
+			for &prev in pred_map.get(&label).into_iter().flatten() {
+				if queued.insert(prev) {
+					work_list.push_back(prev);
+				}
+			}
+		}
+	}
+
+	live_map
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::lua54::common::inst::Reg;
+
+	const DIAMOND: &str = include_str!("../../../tests/fixtures/diamond.ron");
+
+	// r1 loaded in the entry is written again on both sides before the return
+	// reads it, so that first load is dead
+	#[test]
+	fn dead_store_is_not_live_out() {
+		let mut func: Function<Block> = ron::from_str(DIAMOND).unwrap();
+
+		func.block_list[0].body.push(IR::LoadI(Reg::R(1), 7));
+
+		let live_map = liveness(&func);
+
+		assert!(live_map[&0].live_in.contains(&0));
+		assert!(!live_map[&0].live_in.contains(&1));
+		assert!(!live_map[&0].live_out.contains(&1));
+		assert!(live_map[&1].live_out.contains(&1));
+		assert!(live_map[&2].live_out.contains(&1));
+		assert!(live_map[&3].live_in.contains(&1));
+	}
+}
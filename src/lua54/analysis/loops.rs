@@ -0,0 +1,62 @@
+use super::{
+	dominators::{dominates, dominators},
+	successor_map,
+};
+use crate::{common::types::Function, lua54::common::inst::Block};
+use std::collections::{HashMap, HashSet};
+
+// a header, the block whose edge jumps back to it, and every block that can
+// reach that edge without going through the header first
+pub struct NaturalLoop {
+	pub header: u32,
+	pub back_edge: u32,
+	pub member_list: Vec<u32>,
+}
+
+// one loop per back edge, so a header with two back edges shows up twice
+pub fn find_loops(func: &Function<Block>, entry: u32) -> Vec<NaturalLoop> {
+	let succ_map = successor_map(func);
+	let idom = dominators(func, entry);
+	let mut pred_map: HashMap<u32, Vec<u32>> = HashMap::new();
+
+	// unreachable blocks may jump into a loop but are never part of it
+	for &label in idom.keys() {
+		for &next in &succ_map[&label] {
+			pred_map.entry(next).or_default().push(label);
+		}
+	}
+
+	let mut loop_list = Vec::new();
+	let mut label_list: Vec<u32> = idom.keys().copied().collect();
+
+	label_list.sort_unstable();
+
+	for &label in &label_list {
+		for &header in &succ_map[&label] {
+			if !dominates(&idom, header, label) {
+				continue;
+			}
+
+			let mut member_set = HashSet::from([header]);
+			let mut stack = vec![label];
+
+			while let Some(next) = stack.pop() {
+				if member_set.insert(next) {
+					stack.extend(pred_map.get(&next).into_iter().flatten());
+				}
+			}
+
+			let mut member_list: Vec<u32> = member_set.into_iter().collect();
+
+			member_list.sort_unstable();
+			loop_list.push(NaturalLoop {
+				header,
+				back_edge: label,
+				member_list,
+			});
+		}
+	}
+
+	loop_list.sort_by_key(|v| (v.header, v.back_edge));
+	loop_list
+}
@@ -0,0 +1,57 @@
+use super::common::inst::{Block, Control, Loop, Target};
+use crate::common::types::Function;
+use std::collections::{HashMap, HashSet};
+
+pub mod calls;
+pub mod dead_loops;
+pub mod dominators;
+pub mod liveness;
+pub mod loops;
+
+// `get_target_labels` minus the body label of `TFORPREP`, which only says where
+// the body is laid out since the instruction itself always jumps
+pub fn successor_labels(blk: &Block) -> Vec<u32> {
+	match &blk.edge {
+		Control::Loop(Loop::IteratorPrep(_), _, Target::Label(label)) => vec![*label],
+		Control::Loop(Loop::IteratorPrep(_), _, _) => Vec::new(),
+		_ => blk.get_target_labels(),
+	}
+}
+
+pub fn successor_map(func: &Function<Block>) -> HashMap<u32, Vec<u32>> {
+	func.block_list
+		.iter()
+		.map(|v| (v.label, successor_labels(v)))
+		.collect()
+}
+
+// blocks reachable from `entry` in postorder, which is label `0` for anything
+// headed back to the assembler
+pub fn postorder(succ_map: &HashMap<u32, Vec<u32>>, entry: u32) -> Vec<u32> {
+	let mut order = Vec::new();
+	let mut visited = HashSet::new();
+	let mut stack = vec![(entry, 0)];
+
+	if !succ_map.contains_key(&entry) {
+		return order;
+	}
+
+	visited.insert(entry);
+
+	while let Some((label, index)) = stack.pop() {
+		let next = succ_map[&label].get(index).copied();
+
+		match next {
+			Some(next) => {
+				stack.push((label, index + 1));
+
+				if succ_map.contains_key(&next) && visited.insert(next) {
+					stack.push((next, 0));
+				}
+			}
+			None => order.push(label),
+		}
+	}
+
+	order
+}
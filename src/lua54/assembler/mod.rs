@@ -1 +1,3 @@
 mod reasm;
+
+pub(crate) use reasm::{block_spans, Encoder};
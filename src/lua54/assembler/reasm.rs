@@ -1,16 +1,20 @@
 use crate::{
-	common::types::Function,
+	common::types::{Function, Named},
 	lua54::common::{
 		inst::{Block, Condition, Control, Loop, RegOrK, Target, IR},
 		types::{Inst, Opcode, Proto},
 	},
 };
-use std::{cmp::Ordering, collections::HashMap, convert::TryInto, rc::Rc};
+use std::{cmp::Ordering, collections::HashMap, convert::TryInto, ops::Range, sync::Arc};
+
+// largest constant index that fits `Bx` and `Ax` respectively
+const MAX_ARG_BX: u32 = (1 << 17) - 1;
+const MAX_ARG_AX: u32 = (1 << 25) - 1;
 
 struct Translator {
-	func_map: HashMap<Rc<str>, u32>,
-	upvalue_map: HashMap<Rc<str>, u32>,
-	value_map: HashMap<Rc<str>, u32>,
+	func_map: HashMap<Arc<str>, u32>,
+	upvalue_map: HashMap<Arc<str>, u32>,
+	value_map: HashMap<Arc<str>, u32>,
 }
 
 enum Remap {
@@ -76,23 +80,30 @@ impl Controller {
 		*self.inst_list.last_mut().unwrap() = inst;
 	}
 
-	fn add_odd_loop(&mut self, inst: Inst, jump: Target, id: u32) {
+	fn add_odd_loop(&mut self, inst: Inst, jump: Target, mut fall: Option<Target>, id: u32) {
 		let has_id = self.label_map.contains_key(&id);
 
 		match inst.opcode() {
-			Opcode::ForLoop | Opcode::TForLoop if !has_id => {
+			// `TForLoop` has no trampoline as it must directly follow `TForCall`
+			Opcode::ForLoop if !has_id => {
 				self.add_jump(Target::Undefined(1));
 				self.add_jump(jump);
 				self.add_targeted(inst, Target::Undefined(-2));
 			}
-			Opcode::ForPrep | Opcode::TForPrep if has_id => {
+			// running the loop continues past `ForPrep`, skipping it lands 2 past
+			Opcode::ForPrep if has_id => {
 				self.add_targeted(inst, Target::Undefined(1));
+				self.add_jump(fall.take().unwrap_or(Target::Undefined(1)));
 				self.add_jump(jump);
 			}
 			_ => {
 				self.add_targeted(inst, jump);
 			}
 		}
+
+		if let Some(fall) = fall {
+			self.add_jump(fall);
+		}
 	}
 
 	fn redirect_jump_list(&mut self) {
@@ -129,7 +140,7 @@ impl Controller {
 	}
 }
 
-fn wrap_vec(vec: Vec<Rc<str>>) -> HashMap<Rc<str>, u32> {
+fn wrap_vec(vec: Vec<Arc<str>>) -> HashMap<Arc<str>, u32> {
 	vec.into_iter()
 		.enumerate()
 		.map(|(i, v)| (v, i as u32))
@@ -165,7 +176,11 @@ fn has_skip_target(target: &Target, trail: Option<&Block>) -> bool {
 }
 
 impl Translator {
-	fn new(child_name: Vec<Rc<str>>, upval_name: Vec<Rc<str>>, value_name: Vec<Rc<str>>) -> Self {
+	fn new(
+		child_name: Vec<Arc<str>>,
+		upval_name: Vec<Arc<str>>,
+		value_name: Vec<Arc<str>>,
+	) -> Self {
 		let func_map = wrap_vec(child_name);
 		let upvalue_map = wrap_vec(upval_name);
 		let value_map = wrap_vec(value_name);
@@ -197,6 +212,16 @@ impl Translator {
 			.unwrap_or_else(|| panic!("`{}` is not a valid value", name))
 	}
 
+	fn get_ext_val_index(&self, name: &str) -> u32 {
+		let index = self.get_val_index(name);
+
+		if index > MAX_ARG_AX {
+			panic!("`{}` has too large an index (> {})", name, MAX_ARG_AX);
+		}
+
+		index
+	}
+
 	fn get_reg_val_index(&self, name: &str) -> u8 {
 		self.get_val_index(name)
 			.try_into()
@@ -210,6 +235,17 @@ impl Translator {
 		}
 	}
 
+	// a `LoadK` with an index past `Bx` is written as `LoadKX` and the index in
+	// an `ExtraArg`, which is what the compiler does too
+	fn widen_ir(&self, ir: IR) -> Vec<IR> {
+		match ir {
+			IR::LoadK(a, b) if self.get_val_index(&b) > MAX_ARG_BX => {
+				vec![IR::LoadKX(a), IR::ExtraValue(b)]
+			}
+			ir => vec![ir],
+		}
+	}
+
 	fn translate_ir(&self, ir: IR) -> Inst {
 		match ir {
 			IR::Move(a, b) => Inst::iabc(Opcode::Move, a, b, 0),
@@ -308,8 +344,8 @@ impl Translator {
 			IR::Vararg(a, b) => Inst::iabc(Opcode::Vararg, a, b, 0),
 			IR::VarargPrep(a) => Inst::iabc(Opcode::VarargPrep, a, 0, 0),
 			IR::ExtraInteger(a) => Inst::iax(Opcode::ExtraArg, a),
-			IR::ExtraValue(a) => Inst::iax(Opcode::ExtraArg, self.get_val_index(&a)),
-			IR::Invalid(inner) => Inst { inner },
+			IR::ExtraValue(a) => Inst::iax(Opcode::ExtraArg, self.get_ext_val_index(&a)),
+			IR::Invalid(inst) => inst.encode(),
 		}
 	}
 
@@ -338,7 +374,8 @@ impl Translator {
 		}
 	}
 
-	fn translate_control(&self, ctrl: Control, trail: Option<&Block>) -> Remap {
+	// `after` is the label laid out past `trail`, where a skipped jump lands
+	fn translate_control(&self, ctrl: Control, trail: Option<&Block>, after: Option<u32>) -> Remap {
 		let next = trail.map(|v| v.label);
 
 		match ctrl {
@@ -357,21 +394,25 @@ impl Translator {
 			Control::Condition(cond, a, b) => {
 				let mut cmp = self.translate_condition(cond);
 				let (jump, fall) = match (has_fallthrough(&a, next), has_fallthrough(&b, next)) {
+					// a skipped jump lands on `trail`, so the jump emitted
+					// must go to whichever target does not follow
 					(true, false) => {
-						cmp = cmp.set_k(true);
-
 						// if the trailing block is a single jump then adopt it
-						if has_jump(trail) {
+						if has_jump(trail) && has_fallthrough(&b, after) {
+							cmp = cmp.set_k(true);
+
 							(None, None)
 						} else {
-							(Some(a), None)
+							(Some(b), None)
 						}
 					}
 					(false, true) => {
-						if has_jump(trail) {
+						if has_jump(trail) && has_fallthrough(&a, after) {
 							(None, None)
 						} else {
-							(Some(b), None)
+							cmp = cmp.set_k(true);
+
+							(Some(a), None)
 						}
 					}
 					(true, true) => (Some(b), None),
@@ -390,11 +431,12 @@ impl Translator {
 
 				Remap::Loop { inst, jump, fall }
 			}
-			Control::Return0 => Remap::Return {
-				inst: Opcode::Return0.into(),
+			// `B` is unused by the VM for these but luac still fills it in
+			Control::Return0(a) => Remap::Return {
+				inst: Inst::iabc(Opcode::Return0, a, 1, 0),
 			},
 			Control::Return1(a) => Remap::Return {
-				inst: Inst::iabc(Opcode::Return1, a, 0, 0),
+				inst: Inst::iabc(Opcode::Return1, a, 2, 0),
 			},
 			Control::Return(a, b, c, k) => Remap::Return {
 				inst: Inst::iabc(Opcode::Return, a, b, c).set_k(k),
@@ -409,12 +451,15 @@ impl Translator {
 		}
 	}
 
-	fn translate(self, block_list: Vec<Block>) -> Vec<Inst> {
+	// the instructions, and the ones each block was turned into
+	fn translate(self, block_list: Vec<Block>) -> (Vec<Inst>, HashMap<u32, Range<usize>>) {
 		let mut control = Controller::new();
-		let mut iter = block_list.into_iter().peekable();
+		let label_list: Vec<_> = block_list.iter().map(|v| v.label).collect();
+		let mut iter = block_list.into_iter().enumerate().peekable();
+		let mut span_map = HashMap::new();
 
 		// `0` entry point must be present
-		match iter.peek().map(|v| v.label) {
+		match iter.peek().map(|v| v.1.label) {
 			Some(0) | None => {}
 			Some(_) => {
 				// jump to entry
@@ -422,15 +467,22 @@ impl Translator {
 			}
 		}
 
-		while let Some(blk) = iter.next() {
-			control
-				.label_map
-				.insert(blk.label, control.inst_list.len() as i32);
-			control
-				.inst_list
-				.extend(blk.body.into_iter().map(|v| self.translate_ir(v)));
+		while let Some((index, blk)) = iter.next() {
+			let label = blk.label;
+			let start = control.inst_list.len();
+
+			control.label_map.insert(label, start as i32);
+			control.inst_list.extend(
+				blk.body
+					.into_iter()
+					.flat_map(|v| self.widen_ir(v))
+					.map(|v| self.translate_ir(v)),
+			);
 
-			match self.translate_control(blk.edge, iter.peek()) {
+			let trail = iter.peek().map(|v| &v.1);
+			let after = label_list.get(index + 2).copied();
+
+			match self.translate_control(blk.edge, trail, after) {
 				Remap::Fallthrough => {}
 				Remap::LFalseSkip { reg, jump } => match jump {
 					Some(jump) => {
@@ -443,13 +495,13 @@ impl Translator {
 				},
 				Remap::Loop { inst, jump, fall } => {
 					if let Target::Label(id) = jump {
-						control.add_odd_loop(inst, jump, id);
+						control.add_odd_loop(inst, jump, fall, id);
 					} else {
 						control.add_targeted(inst, jump);
-					}
 
-					if let Some(fall) = fall {
-						control.add_jump(fall);
+						if let Some(fall) = fall {
+							control.add_jump(fall);
+						}
 					}
 				}
 				Remap::Condition { cmp, jump, fall } => {
@@ -470,19 +522,86 @@ impl Translator {
 					control.add(inst);
 				}
 			}
+
+			span_map.insert(label, start..control.inst_list.len());
 		}
 
 		control.redirect_jump_list();
 		control.inst_list.shrink_to_fit();
-		control.inst_list
+
+		(control.inst_list, span_map)
+	}
+}
+
+fn name_list<T>(list: &Named<T>) -> Vec<Arc<str>> {
+	list.iter().map(|v| Arc::clone(&v.0)).collect()
+}
+
+// where each block of `func` itself lands once assembled, which for a function
+// fresh out of the disassembler is where it was in the chunk
+pub(crate) fn block_spans(func: &Function<Block>) -> HashMap<u32, Range<usize>> {
+	let translator = Translator::new(
+		name_list(&func.child_list),
+		name_list(&func.upval_list),
+		name_list(&func.value_list),
+	);
+
+	translator.translate(func.block_list.clone()).1
+}
+
+// instructions encoded one at a time against the names of a function, for
+// showing them the way `luac -l` does
+pub(crate) struct Encoder {
+	translator: Translator,
+}
+
+impl Encoder {
+	pub(crate) fn new(func: &Function<Block>) -> Self {
+		let translator = Translator::new(
+			name_list(&func.child_list),
+			name_list(&func.upval_list),
+			name_list(&func.value_list),
+		);
+
+		Self { translator }
+	}
+
+	// nothing when it names something the function lacks, or an entry too far
+	// down its list for the operand to reach
+	pub(crate) fn encode(&self, ir: &IR) -> Option<Inst> {
+		let translator = &self.translator;
+		let reach = |map: &HashMap<Arc<str>, u32>, name: &Arc<str>, max: u32| {
+			map.get(name).is_some_and(|v| *v <= max)
+		};
+		let max_k = match ir {
+			IR::LoadK(..) => MAX_ARG_BX,
+			IR::ExtraValue(_) => MAX_ARG_AX,
+			_ => u8::MAX.into(),
+		};
+		let known = ir
+			.constants()
+			.into_iter()
+			.all(|v| reach(&translator.value_map, v, max_k))
+			&& ir
+				.upvalues()
+				.into_iter()
+				.all(|v| reach(&translator.upvalue_map, v, u8::MAX.into()))
+			&& match ir {
+				IR::Closure(_, name) => reach(&translator.func_map, name, MAX_ARG_BX),
+				_ => true,
+			};
+
+		known.then(|| translator.translate_ir(ir.clone()))
 	}
 }
 
+// an empty list is a stripped function and stays empty
 fn align_rel_line_list(line_list: &mut Vec<i8>, len: usize) {
 	match line_list.len().cmp(&len) {
+		_ if line_list.is_empty() => {}
 		Ordering::Equal => {}
 		Ordering::Less => {
-			let iter = std::iter::repeat(0).take(len - line_list.len());
+			let iter = std::iter::repeat_n(0, len - line_list.len());
 
 			line_list.extend(iter);
 		}
@@ -498,7 +617,7 @@ impl From<Function<Block>> for Proto {
 		let (upval_name, upval_list) = func.upval_list.into_iter().unzip();
 		let (value_name, value_list) = func.value_list.into_iter().unzip();
 
-		let inst_list =
+		let (inst_list, _) =
 			Translator::new(child_name, upval_name, value_name).translate(func.block_list);
 
 		let source = func.source;
@@ -519,6 +638,7 @@ impl From<Function<Block>> for Proto {
 		align_rel_line_list(&mut rel_line_list, inst_list.len());
 
 		Proto {
+			layout: func.layout,
 			source,
 			is_vararg,
 			num_stack,
@@ -535,3 +655,47 @@ impl From<Function<Block>> for Proto {
 		}
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{assemble, common::types::Value, disassemble, lua54::common::inst::Reg};
+
+	const VSECURE: &[u8] = include_bytes!("../../../tests/fixtures/vsecure.luac");
+
+	// a constant past `Bx` goes out as `LoadKX` and an `ExtraArg`, and comes
+	// back in as the same pair naming the same constant
+	#[test]
+	fn wide_constant_uses_extra_arg() {
+		let mut func = disassemble(VSECURE).unwrap();
+		let first = func.value_list.len() as i64;
+
+		func.value_list.extend(
+			(first..=i64::from(MAX_ARG_BX) + 1)
+				.map(|v| (format!("vWide_{}", v).into(), Value::Integer(v))),
+		);
+
+		let last = Arc::clone(&func.value_list.last().unwrap().0);
+
+		func.block_list[0]
+			.body
+			.insert(0, IR::LoadK(Reg::R(0), last));
+
+		let data = assemble(func).unwrap();
+		let func = disassemble(&data).unwrap();
+
+		match &func.block_list[0].body[..2] {
+			[IR::LoadKX(Reg::R(0)), IR::ExtraValue(name)] => {
+				let index = func.value_list.iter().position(|v| v.0 == *name).unwrap();
+
+				assert_eq!(index as u32, MAX_ARG_BX + 1);
+				assert!(
+					matches!(func.value_list[index].1, Value::Integer(v) if v == i64::from(MAX_ARG_BX) + 1)
+				);
+			}
+			_ => panic!("no `LoadKX` pair at the start of the entry block"),
+		}
+
+		assert!(assemble(func).unwrap() == data);
+	}
+}
@@ -0,0 +1,83 @@
+use super::types::{Inst, Opcode};
+use bit_field::BitField;
+use serde::{Deserialize, Serialize};
+
+// the operand layout of each instruction format, named as in `lopcodes.h`
+#[derive(Clone, Deserialize, Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub enum Operands {
+	ABC { a: u8, k: bool, b: u8, c: u8 },
+	ABx { a: u8, bx: u32 },
+	AsBx { a: u8, sbx: i32 },
+	Ax { ax: u32 },
+	SJ { sj: i32 },
+}
+
+#[derive(Clone, Deserialize, Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct DecodedInstruction {
+	pub opcode: Opcode,
+	// only kept for opcodes outside the table, which all read as `Invalid`
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub number: Option<u8>,
+	pub operands: Operands,
+}
+
+impl DecodedInstruction {
+	// unknown opcodes have no format of their own and are split as `iABC`,
+	// which covers every bit all the same
+	pub fn decode(inst: Inst) -> Self {
+		let opcode = inst.opcode();
+		let number = match opcode {
+			Opcode::Invalid => Some(inst.inner.get_bits(0..7) as u8),
+			_ => None,
+		};
+		let operands = match opcode {
+			Opcode::LoadI | Opcode::LoadF => Operands::AsBx {
+				a: inst.a(),
+				sbx: inst.sbx(),
+			},
+			Opcode::LoadK
+			| Opcode::LoadKX
+			| Opcode::ForLoop
+			| Opcode::ForPrep
+			| Opcode::TForPrep
+			| Opcode::TForLoop
+			| Opcode::Closure => Operands::ABx {
+				a: inst.a(),
+				bx: inst.bx(),
+			},
+			Opcode::Jmp => Operands::SJ { sj: inst.sj() },
+			Opcode::ExtraArg => Operands::Ax { ax: inst.ax() },
+			_ => Operands::ABC {
+				a: inst.a(),
+				k: inst.k(),
+				b: inst.b(),
+				c: inst.c(),
+			},
+		};
+
+		Self {
+			opcode,
+			number,
+			operands,
+		}
+	}
+
+	pub fn encode(&self) -> Inst {
+		let op = self.opcode;
+		let mut inst = match self.operands {
+			Operands::ABC { a, k, b, c } => Inst::iabc(op, a, b, c).set_k(k),
+			Operands::ABx { a, bx } => Inst::iabx(op, a, bx),
+			Operands::AsBx { a, sbx } => Inst::iasbx(op, a, sbx),
+			Operands::Ax { ax } => Inst::iax(op, ax),
+			Operands::SJ { sj } => Inst::isj(op, sj),
+		};
+
+		if let Some(number) = self.number {
+			inst.inner.set_bits(0..7, number.into());
+		}
+
+		inst
+	}
+}
@@ -1,9 +1,11 @@
-use crate::common::types::Instruction;
+use super::{decode::DecodedInstruction, types::Opcode};
+use crate::common::types::{Function, Named};
 use num_enum::{FromPrimitive, IntoPrimitive};
 use serde::{Deserialize, Serialize};
-use std::rc::Rc;
+use std::sync::Arc;
 
-#[derive(Deserialize, Serialize)]
+#[derive(Clone, Deserialize, Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub enum Reg {
 	R(u8),
 }
@@ -22,10 +24,11 @@ impl From<Reg> for u8 {
 	}
 }
 
-#[derive(Deserialize, Serialize)]
+#[derive(Clone, Deserialize, Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub enum RegOrK {
 	R(u8),
-	K(Rc<str>),
+	K(Arc<str>),
 }
 
 impl From<u8> for RegOrK {
@@ -34,13 +37,14 @@ impl From<u8> for RegOrK {
 	}
 }
 
-impl From<Rc<str>> for RegOrK {
-	fn from(k: Rc<str>) -> Self {
+impl From<Arc<str>> for RegOrK {
+	fn from(k: Arc<str>) -> Self {
 		Self::K(k)
 	}
 }
 
-#[derive(FromPrimitive, IntoPrimitive, Deserialize, Serialize)]
+#[derive(Clone, FromPrimitive, IntoPrimitive, Deserialize, Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 #[repr(u8)]
 pub enum MetaMethod {
 	Index = 0,
@@ -73,7 +77,8 @@ pub enum MetaMethod {
 	Invalid,
 }
 
-#[derive(Deserialize, Serialize)]
+#[derive(Clone, Deserialize, Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub enum Group {
 	Many,
 	Exactly(u8),
@@ -97,28 +102,29 @@ impl From<Group> for u8 {
 	}
 }
 
-#[derive(Deserialize, Serialize)]
+#[derive(Clone, Deserialize, Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub enum IR {
 	Move(Reg, Reg),
 	LoadI(Reg, i32),
 	LoadF(Reg, i32),
-	LoadK(Reg, Rc<str>),
+	LoadK(Reg, Arc<str>),
 	LoadKX(Reg),
 	LoadFalse(Reg),
 	LoadTrue(Reg),
 	LoadNil(Reg, u8),
-	GetUpval(Reg, Rc<str>),
-	SetUpval(Reg, Rc<str>),
+	GetUpval(Reg, Arc<str>),
+	SetUpval(Reg, Arc<str>),
 
-	GetTabUp(Reg, Rc<str>, Rc<str>),
+	GetTabUp(Reg, Arc<str>, Arc<str>),
 	GetTable(Reg, Reg, Reg),
 	GetI(Reg, Reg, u8),
-	GetField(Reg, Reg, Rc<str>),
+	GetField(Reg, Reg, Arc<str>),
 
-	SetTabUp(Rc<str>, Rc<str>, RegOrK),
+	SetTabUp(Arc<str>, Arc<str>, RegOrK),
 	SetTable(Reg, Reg, RegOrK),
 	SetI(Reg, u8, RegOrK),
-	SetField(Reg, Rc<str>, RegOrK),
+	SetField(Reg, Arc<str>, RegOrK),
 
 	NewTable(Reg, u8, u8, bool),
 
@@ -126,17 +132,17 @@ pub enum IR {
 
 	AddI(Reg, Reg, i8),
 
-	AddK(Reg, Reg, Rc<str>),
-	SubK(Reg, Reg, Rc<str>),
-	MulK(Reg, Reg, Rc<str>),
-	ModK(Reg, Reg, Rc<str>),
-	PowK(Reg, Reg, Rc<str>),
-	DivK(Reg, Reg, Rc<str>),
-	IDivK(Reg, Reg, Rc<str>),
+	AddK(Reg, Reg, Arc<str>),
+	SubK(Reg, Reg, Arc<str>),
+	MulK(Reg, Reg, Arc<str>),
+	ModK(Reg, Reg, Arc<str>),
+	PowK(Reg, Reg, Arc<str>),
+	DivK(Reg, Reg, Arc<str>),
+	IDivK(Reg, Reg, Arc<str>),
 
-	BandK(Reg, Reg, Rc<str>),
-	BorK(Reg, Reg, Rc<str>),
-	BxorK(Reg, Reg, Rc<str>),
+	BandK(Reg, Reg, Arc<str>),
+	BorK(Reg, Reg, Arc<str>),
+	BxorK(Reg, Reg, Arc<str>),
 
 	ShrI(Reg, Reg, i8),
 	ShlI(Reg, Reg, i8),
@@ -157,7 +163,7 @@ pub enum IR {
 
 	MmBin(Reg, Reg, MetaMethod),
 	MmBinI(Reg, i8, MetaMethod, bool),
-	MmBinK(Reg, Rc<str>, MetaMethod, bool),
+	MmBinK(Reg, Arc<str>, MetaMethod, bool),
 
 	Unm(Reg, Reg),
 	Bnot(Reg, Reg),
@@ -176,24 +182,307 @@ pub enum IR {
 
 	SetList(Reg, u8, u8, bool),
 
-	Closure(Reg, Rc<str>),
+	Closure(Reg, Arc<str>),
 
 	Vararg(Reg, Group),
 	VarargPrep(Reg),
 
 	ExtraInteger(u32),
-	ExtraValue(Rc<str>),
+	ExtraValue(Arc<str>),
 
-	Invalid(Instruction),
+	// unknown to the table, kept split up so the RON still says what it holds
+	Invalid(DecodedInstruction),
 }
 
-#[derive(Deserialize, Serialize)]
+// `count` registers up from `from`, cut off past the last one there can be
+fn reg_range(from: &Reg, count: usize) -> Vec<u8> {
+	let Reg::R(a) = from;
+
+	(usize::from(*a)..usize::from(*a) + count)
+		.take_while(|v| *v <= usize::from(u8::MAX))
+		.map(|v| v as u8)
+		.collect()
+}
+
+// the `Many` forms reach up to the top set by the instruction before them,
+// which is written here as every register from `from` up so the one that set
+// it kills what the one that reads it uses
+fn reg_open(from: &Reg) -> Vec<u8> {
+	let Reg::R(a) = from;
+
+	(*a..=u8::MAX).collect()
+}
+
+fn reg_or_k(rk: &RegOrK) -> Vec<u8> {
+	match rk {
+		RegOrK::R(x) => vec![*x],
+		RegOrK::K(_) => Vec::new(),
+	}
+}
+
+fn k_of(rk: &RegOrK) -> Option<&Arc<str>> {
+	match rk {
+		RegOrK::R(_) => None,
+		RegOrK::K(k) => Some(k),
+	}
+}
+
+fn k_of_mut(rk: &mut RegOrK) -> Option<&mut Arc<str>> {
+	match rk {
+		RegOrK::R(_) => None,
+		RegOrK::K(k) => Some(k),
+	}
+}
+
+impl IR {
+	// what it is encoded as, the `EXTRAARG` forms both being one opcode
+	pub fn opcode(&self) -> Opcode {
+		match self {
+			IR::Move(..) => Opcode::Move,
+			IR::LoadI(..) => Opcode::LoadI,
+			IR::LoadF(..) => Opcode::LoadF,
+			IR::LoadK(..) => Opcode::LoadK,
+			IR::LoadKX(..) => Opcode::LoadKX,
+			IR::LoadFalse(..) => Opcode::LoadFalse,
+			IR::LoadTrue(..) => Opcode::LoadTrue,
+			IR::LoadNil(..) => Opcode::LoadNil,
+			IR::GetUpval(..) => Opcode::GetUpval,
+			IR::SetUpval(..) => Opcode::SetUpval,
+			IR::GetTabUp(..) => Opcode::GetTabUp,
+			IR::GetTable(..) => Opcode::GetTable,
+			IR::GetI(..) => Opcode::GetI,
+			IR::GetField(..) => Opcode::GetField,
+			IR::SetTabUp(..) => Opcode::SetTabUp,
+			IR::SetTable(..) => Opcode::SetTable,
+			IR::SetI(..) => Opcode::SetI,
+			IR::SetField(..) => Opcode::SetField,
+			IR::NewTable(..) => Opcode::NewTable,
+			IR::Method(..) => Opcode::Method,
+			IR::AddI(..) => Opcode::AddI,
+			IR::AddK(..) => Opcode::AddK,
+			IR::SubK(..) => Opcode::SubK,
+			IR::MulK(..) => Opcode::MulK,
+			IR::ModK(..) => Opcode::ModK,
+			IR::PowK(..) => Opcode::PowK,
+			IR::DivK(..) => Opcode::DivK,
+			IR::IDivK(..) => Opcode::IDivK,
+			IR::BandK(..) => Opcode::BandK,
+			IR::BorK(..) => Opcode::BorK,
+			IR::BxorK(..) => Opcode::BxorK,
+			IR::ShrI(..) => Opcode::ShrI,
+			IR::ShlI(..) => Opcode::ShlI,
+			IR::Add(..) => Opcode::Add,
+			IR::Sub(..) => Opcode::Sub,
+			IR::Mul(..) => Opcode::Mul,
+			IR::Mod(..) => Opcode::Mod,
+			IR::Pow(..) => Opcode::Pow,
+			IR::Div(..) => Opcode::Div,
+			IR::IDiv(..) => Opcode::IDiv,
+			IR::Band(..) => Opcode::Band,
+			IR::Bor(..) => Opcode::Bor,
+			IR::Bxor(..) => Opcode::Bxor,
+			IR::Shl(..) => Opcode::Shl,
+			IR::Shr(..) => Opcode::Shr,
+			IR::MmBin(..) => Opcode::MmBin,
+			IR::MmBinI(..) => Opcode::MmBinI,
+			IR::MmBinK(..) => Opcode::MmBinK,
+			IR::Unm(..) => Opcode::Unm,
+			IR::Bnot(..) => Opcode::Bnot,
+			IR::Not(..) => Opcode::Not,
+			IR::Len(..) => Opcode::Len,
+			IR::Concat(..) => Opcode::Concat,
+			IR::Close(..) => Opcode::Close,
+			IR::Tbc(..) => Opcode::Tbc,
+			IR::Call(..) => Opcode::Call,
+			IR::TailCall(..) => Opcode::TailCall,
+			IR::TForCall(..) => Opcode::TForCall,
+			IR::SetList(..) => Opcode::SetList,
+			IR::Closure(..) => Opcode::Closure,
+			IR::Vararg(..) => Opcode::Vararg,
+			IR::VarargPrep(..) => Opcode::VarargPrep,
+			IR::ExtraInteger(_) | IR::ExtraValue(_) => Opcode::ExtraArg,
+			IR::Invalid(inst) => inst.opcode,
+		}
+	}
+
+	// the registers written and the registers read, as in `lvm.c`, anything
+	// only written under a condition counts as not written
+	pub fn defs_uses(&self) -> (Vec<u8>, Vec<u8>) {
+		let one = |reg: &Reg| reg_range(reg, 1);
+
+		match self {
+			IR::Move(a, b)
+			| IR::GetI(a, b, _)
+			| IR::GetField(a, b, _)
+			| IR::AddI(a, b, _)
+			| IR::AddK(a, b, _)
+			| IR::SubK(a, b, _)
+			| IR::MulK(a, b, _)
+			| IR::ModK(a, b, _)
+			| IR::PowK(a, b, _)
+			| IR::DivK(a, b, _)
+			| IR::IDivK(a, b, _)
+			| IR::BandK(a, b, _)
+			| IR::BorK(a, b, _)
+			| IR::BxorK(a, b, _)
+			| IR::ShrI(a, b, _)
+			| IR::ShlI(a, b, _)
+			| IR::Unm(a, b)
+			| IR::Bnot(a, b)
+			| IR::Not(a, b)
+			| IR::Len(a, b) => (one(a), one(b)),
+			IR::GetTable(a, b, c)
+			| IR::Add(a, b, c)
+			| IR::Sub(a, b, c)
+			| IR::Mul(a, b, c)
+			| IR::Mod(a, b, c)
+			| IR::Pow(a, b, c)
+			| IR::Div(a, b, c)
+			| IR::IDiv(a, b, c)
+			| IR::Band(a, b, c)
+			| IR::Bor(a, b, c)
+			| IR::Bxor(a, b, c)
+			| IR::Shl(a, b, c)
+			| IR::Shr(a, b, c) => (one(a), [one(b), one(c)].concat()),
+			IR::LoadI(a, _)
+			| IR::LoadF(a, _)
+			| IR::LoadK(a, _)
+			| IR::LoadKX(a)
+			| IR::LoadFalse(a)
+			| IR::LoadTrue(a)
+			| IR::GetUpval(a, _)
+			| IR::GetTabUp(a, ..)
+			| IR::NewTable(a, ..)
+			| IR::Closure(a, _) => (one(a), Vec::new()),
+			IR::LoadNil(a, n) => (reg_range(a, usize::from(*n) + 1), Vec::new()),
+			IR::SetUpval(a, _) | IR::MmBinI(a, ..) | IR::MmBinK(a, ..) | IR::Tbc(a) => {
+				(Vec::new(), one(a))
+			}
+			IR::SetTabUp(_, _, rk) => (Vec::new(), reg_or_k(rk)),
+			IR::SetTable(a, b, rk) => (Vec::new(), [one(a), one(b), reg_or_k(rk)].concat()),
+			IR::SetI(a, _, rk) | IR::SetField(a, _, rk) => {
+				(Vec::new(), [one(a), reg_or_k(rk)].concat())
+			}
+			// `SELF` puts the object right above the method
+			IR::Method(a, b, rk) => (reg_range(a, 2), [one(b), reg_or_k(rk)].concat()),
+			// the result of the arithmetic before it is already written
+			IR::MmBin(a, b, _) => (Vec::new(), [one(a), one(b)].concat()),
+			IR::Concat(a, Reg::R(n), _) => (one(a), reg_range(a, usize::from(*n))),
+			IR::Call(a, arg, ret) => {
+				let def_list = match ret {
+					Group::Many => reg_open(a),
+					Group::Exactly(n) => reg_range(a, usize::from(*n)),
+				};
+				let use_list = match arg {
+					Group::Many => reg_open(a),
+					Group::Exactly(n) => reg_range(a, usize::from(*n) + 1),
+				};
+
+				(def_list, use_list)
+			}
+			IR::TailCall(a, arg, _) => match arg {
+				Group::Many => (Vec::new(), reg_open(a)),
+				Group::Exactly(n) => (Vec::new(), reg_range(a, usize::from(*n) + 1)),
+			},
+			IR::TForCall(Reg::R(a), n) => {
+				let base = Reg::R(a.saturating_add(4));
+
+				(reg_range(&base, usize::from(*n)), reg_range(&Reg::R(*a), 3))
+			}
+			IR::SetList(a, 0, ..) => (Vec::new(), reg_open(a)),
+			IR::SetList(a, n, ..) => (Vec::new(), reg_range(a, usize::from(*n) + 1)),
+			IR::Vararg(a, Group::Many) => (reg_open(a), Vec::new()),
+			IR::Vararg(a, Group::Exactly(n)) => (reg_range(a, usize::from(*n)), Vec::new()),
+			// upvalues are closed by `CLOSE` and fixed parameters moved by
+			// `VARARGPREP` without their values being read
+			IR::Close(_) | IR::VarargPrep(_) | IR::ExtraInteger(_) | IR::ExtraValue(_) => {
+				(Vec::new(), Vec::new())
+			}
+			// nothing is known of it, so it might read anything
+			IR::Invalid(_) => (Vec::new(), reg_open(&Reg::R(0))),
+		}
+	}
+
+	// the names of the constants it reads, in operand order, upvalue and proto
+	// names being kept apart from those
+	pub fn constants(&self) -> Vec<&Arc<str>> {
+		match self {
+			IR::LoadK(_, k)
+			| IR::GetTabUp(_, _, k)
+			| IR::GetField(_, _, k)
+			| IR::AddK(_, _, k)
+			| IR::SubK(_, _, k)
+			| IR::MulK(_, _, k)
+			| IR::ModK(_, _, k)
+			| IR::PowK(_, _, k)
+			| IR::DivK(_, _, k)
+			| IR::IDivK(_, _, k)
+			| IR::BandK(_, _, k)
+			| IR::BorK(_, _, k)
+			| IR::BxorK(_, _, k)
+			| IR::MmBinK(_, k, ..)
+			| IR::ExtraValue(k) => vec![k],
+			IR::SetTabUp(_, k, value) | IR::SetField(_, k, value) => {
+				std::iter::once(k).chain(k_of(value)).collect()
+			}
+			IR::SetTable(_, _, value) | IR::SetI(_, _, value) | IR::Method(_, _, value) => {
+				k_of(value).into_iter().collect()
+			}
+			_ => Vec::new(),
+		}
+	}
+
+	// the names of the upvalues it reads or writes
+	pub fn upvalues(&self) -> Vec<&Arc<str>> {
+		match self {
+			IR::GetUpval(_, u)
+			| IR::SetUpval(_, u)
+			| IR::GetTabUp(_, u, _)
+			| IR::SetTabUp(u, ..) => {
+				vec![u]
+			}
+			_ => Vec::new(),
+		}
+	}
+
+	// the same names as `constants`, to be pointed at other entries
+	pub fn constants_mut(&mut self) -> Vec<&mut Arc<str>> {
+		match self {
+			IR::LoadK(_, k)
+			| IR::GetTabUp(_, _, k)
+			| IR::GetField(_, _, k)
+			| IR::AddK(_, _, k)
+			| IR::SubK(_, _, k)
+			| IR::MulK(_, _, k)
+			| IR::ModK(_, _, k)
+			| IR::PowK(_, _, k)
+			| IR::DivK(_, _, k)
+			| IR::IDivK(_, _, k)
+			| IR::BandK(_, _, k)
+			| IR::BorK(_, _, k)
+			| IR::BxorK(_, _, k)
+			| IR::MmBinK(_, k, ..)
+			| IR::ExtraValue(k) => vec![k],
+			IR::SetTabUp(_, k, value) | IR::SetField(_, k, value) => {
+				std::iter::once(k).chain(k_of_mut(value)).collect()
+			}
+			IR::SetTable(_, _, value) | IR::SetI(_, _, value) | IR::Method(_, _, value) => {
+				k_of_mut(value).into_iter().collect()
+			}
+			_ => Vec::new(),
+		}
+	}
+}
+
+#[derive(Clone, Deserialize, Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub enum Target {
 	Label(u32),
 	Undefined(i32),
 }
 
-#[derive(Deserialize, Serialize)]
+#[derive(Clone, Deserialize, Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub enum Loop {
 	Iterator(Reg),
 	IteratorPrep(Reg),
@@ -201,7 +490,8 @@ pub enum Loop {
 	NumericPrep(Reg),
 }
 
-#[derive(Deserialize, Serialize)]
+#[derive(Clone, Deserialize, Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub enum Condition {
 	// unop - comparison
 	Test(Reg),
@@ -210,7 +500,7 @@ pub enum Condition {
 	// binop - comparison
 	Eq(Reg, Reg),
 	EqI(Reg, i8),
-	EqK(Reg, Rc<str>),
+	EqK(Reg, Arc<str>),
 	GeI(Reg, i8),
 	GtI(Reg, i8),
 	Le(Reg, Reg),
@@ -219,18 +509,77 @@ pub enum Condition {
 	LtI(Reg, i8),
 }
 
-#[derive(Deserialize, Serialize)]
+#[derive(Clone, Deserialize, Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub enum Control {
 	LFalseSkip(Reg, Target),
 	Condition(Condition, Target, Target),
 	Loop(Loop, Target, Target),
 	Return(Reg, Group, u8, bool),
-	Return0,
+	Return0(Reg),
 	Return1(Reg),
 	Unconditional(Target),
 }
 
-#[derive(Deserialize, Serialize)]
+impl Control {
+	// like `IR::defs_uses`, except the loop registers written only on the side
+	// that is taken, which count as written since the way out never reads them
+	pub fn defs_uses(&self) -> (Vec<u8>, Vec<u8>) {
+		let one = |reg: &Reg| reg_range(reg, 1);
+
+		match self {
+			Control::LFalseSkip(a, _) => (one(a), Vec::new()),
+			Control::Condition(cond, ..) => match cond {
+				Condition::Test(a)
+				| Condition::EqI(a, _)
+				| Condition::EqK(a, _)
+				| Condition::GeI(a, _)
+				| Condition::GtI(a, _)
+				| Condition::LeI(a, _)
+				| Condition::LtI(a, _) => (Vec::new(), one(a)),
+				// `TESTSET` only writes on one side, which the other still reads
+				Condition::TestSet(_, b) => (Vec::new(), one(b)),
+				Condition::Eq(a, b) | Condition::Le(a, b) | Condition::Lt(a, b) => {
+					(Vec::new(), [one(a), one(b)].concat())
+				}
+			},
+			Control::Loop(kind, ..) => match kind {
+				Loop::NumericPrep(a) => (reg_range(a, 4), reg_range(a, 3)),
+				Loop::Numeric(Reg::R(a)) => {
+					let def_list = vec![*a, a.saturating_add(1), a.saturating_add(3)];
+
+					(def_list, reg_range(&Reg::R(*a), 3))
+				}
+				Loop::IteratorPrep(Reg::R(a)) => (Vec::new(), vec![a.saturating_add(3)]),
+				Loop::Iterator(Reg::R(a)) => (vec![a.saturating_add(2)], vec![a.saturating_add(4)]),
+			},
+			Control::Return(a, Group::Many, ..) => (Vec::new(), reg_open(a)),
+			Control::Return(a, Group::Exactly(n), ..) => {
+				(Vec::new(), reg_range(a, usize::from(*n)))
+			}
+			Control::Return1(a) => (Vec::new(), one(a)),
+			Control::Return0(_) | Control::Unconditional(_) => (Vec::new(), Vec::new()),
+		}
+	}
+
+	// like `IR::constants`, only `EQK` has one
+	pub fn constants(&self) -> Vec<&Arc<str>> {
+		match self {
+			Control::Condition(Condition::EqK(_, k), ..) => vec![k],
+			_ => Vec::new(),
+		}
+	}
+
+	pub fn constants_mut(&mut self) -> Vec<&mut Arc<str>> {
+		match self {
+			Control::Condition(Condition::EqK(_, k), ..) => vec![k],
+			_ => Vec::new(),
+		}
+	}
+}
+
+#[derive(Clone, Deserialize, Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct Block {
 	pub label: u32,
 	pub body: Vec<IR>,
@@ -241,4 +590,237 @@ impl Block {
 	pub fn new(label: u32, body: Vec<IR>, edge: Control) -> Self {
 		Self { label, body, edge }
 	}
+
+	// where in `value_list` the constants the body and the edge read sit, each
+	// once and in order, a name the list doesn't have is left out
+	pub fn referenced_constants<T>(&self, value_list: &Named<T>) -> Vec<usize> {
+		let mut index_list: Vec<usize> = self
+			.body
+			.iter()
+			.flat_map(IR::constants)
+			.chain(self.edge.constants())
+			.filter_map(|k| value_list.iter().position(|v| v.0 == *k))
+			.collect();
+
+		index_list.sort_unstable();
+		index_list.dedup();
+		index_list
+	}
+
+	// every constant name the body and the edge read, in order
+	pub fn constants_mut(&mut self) -> Vec<&mut Arc<str>> {
+		let edge = self.edge.constants_mut();
+
+		self.body
+			.iter_mut()
+			.flat_map(IR::constants_mut)
+			.chain(edge)
+			.collect()
+	}
+
+	pub fn is_unconditionnal(&self) -> bool {
+		matches!(self.edge, Control::Unconditional(_))
+	}
+
+	// every target of the edge, a branch's taken side first like the labels
+	// `get_target_labels` lists
+	pub fn targets_mut(&mut self) -> Vec<&mut Target> {
+		match &mut self.edge {
+			Control::Condition(_, on_true, on_false) | Control::Loop(_, on_false, on_true) => {
+				vec![on_true, on_false]
+			}
+			Control::LFalseSkip(_, target) | Control::Unconditional(target) => vec![target],
+			_ => Vec::new(),
+		}
+	}
+
+	// points every edge aimed at `from` to `to` instead, returns if any changed
+	pub fn redirect_target(&mut self, from: u32, to: u32) -> bool {
+		// `TForLoop` has to jump back to the loop body itself
+		let skip = matches!(self.edge, Control::Loop(Loop::Iterator(_), ..)) as usize;
+		let mut changed = false;
+
+		for target in self.targets_mut().into_iter().skip(skip) {
+			if let Target::Label(label) = target {
+				if *label == from {
+					*label = to;
+					changed = true;
+				}
+			}
+		}
+
+		changed
+	}
+
+	pub fn get_target_labels(&self) -> Vec<u32> {
+		let mut ret: Vec<u32> = Vec::new();
+
+		match &self.edge {
+			Control::Condition(_, on_true, on_false) => {
+				if let Target::Label(to_label) = on_true {
+					ret.push(*to_label);
+				}
+
+				if let Target::Label(to_label) = on_false {
+					ret.push(*to_label);
+				}
+			}
+
+			Control::Unconditional(Target::Label(to_label)) => {
+				ret.push(*to_label);
+			}
+
+			Control::Loop(_, on_false, on_true) => {
+				if let Target::Label(to_label) = on_true {
+					ret.push(*to_label);
+				}
+
+				if let Target::Label(to_label) = on_false {
+					ret.push(*to_label);
+				}
+			}
+
+			Control::LFalseSkip(_, Target::Label(to_label)) => {
+				ret.push(*to_label);
+			}
+			_ => {}
+		}
+
+		ret
+	}
+}
+
+impl Function<Block> {
+	// the bodies of every block in order, edges left out
+	pub fn instructions(&self) -> impl Iterator<Item = &IR> {
+		self.block_list.iter().flat_map(|v| &v.body)
+	}
+
+	pub fn instructions_mut(&mut self) -> impl Iterator<Item = &mut IR> {
+		self.block_list.iter_mut().flat_map(|v| &mut v.body)
+	}
+
+	// the same for this function and every proto nested in it, each paired
+	// with the path `--proto` would take to pick its proto out
+	pub fn all_instructions(&self) -> Box<dyn Iterator<Item = (String, &IR)> + '_> {
+		self.instructions_under(String::new())
+	}
+
+	fn instructions_under(&self, path: String) -> Box<dyn Iterator<Item = (String, &IR)> + '_> {
+		let own_path = path.clone();
+		let own_iter = self.instructions().map(move |ir| (own_path.clone(), ir));
+		let child_iter = self
+			.child_list
+			.iter()
+			.enumerate()
+			.flat_map(move |(index, (_, child))| {
+				let child_path = if path.is_empty() {
+					index.to_string()
+				} else {
+					format!("{}.{}", path, index)
+				};
+
+				child.instructions_under(child_path)
+			});
+
+		Box::new(own_iter.chain(child_iter))
+	}
+}
+
+// puts a block together one piece at a time, the label is the entry's unless
+// told otherwise
+#[derive(Default)]
+pub struct BlockBuilder {
+	label: u32,
+	body: Vec<IR>,
+	edge: Option<Control>,
+}
+
+impl BlockBuilder {
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	pub fn label(mut self, label: u32) -> Self {
+		self.label = label;
+		self
+	}
+
+	pub fn push_instr(mut self, ir: IR) -> Self {
+		self.body.push(ir);
+		self
+	}
+
+	pub fn edge(mut self, edge: Control) -> Self {
+		self.edge = Some(edge);
+		self
+	}
+
+	// every block ends in an edge, leaving it out is a bug in the caller
+	pub fn build(self) -> Block {
+		match self.edge {
+			Some(edge) => Block::new(self.label, self.body, edge),
+			None => panic!("block {} was built without an edge", self.label),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use ron::ser::{to_string_pretty, PrettyConfig};
+
+	// one block per edge, as `-d` writes it, against what is checked in
+	fn check_golden(blk: Block, golden: &str) {
+		let text = to_string_pretty(&blk, PrettyConfig::new()).unwrap();
+
+		assert_eq!(text, golden.trim_end());
+
+		let blk: Block = ron::from_str(golden).unwrap();
+
+		assert_eq!(to_string_pretty(&blk, PrettyConfig::new()).unwrap(), text);
+	}
+
+	#[test]
+	fn control_golden_ron() {
+		let label = Target::Label;
+		let golden_list = vec![
+			(
+				Control::Unconditional(label(1)),
+				include_str!("../../../tests/fixtures/control/unconditional.ron"),
+			),
+			(
+				Control::Condition(Condition::Test(Reg::R(0)), label(2), label(3)),
+				include_str!("../../../tests/fixtures/control/condition.ron"),
+			),
+			(
+				Control::Loop(Loop::Numeric(Reg::R(1)), label(4), label(2)),
+				include_str!("../../../tests/fixtures/control/loop.ron"),
+			),
+			(
+				Control::LFalseSkip(Reg::R(0), label(4)),
+				include_str!("../../../tests/fixtures/control/lfalse_skip.ron"),
+			),
+			(
+				Control::Return(Reg::R(0), Group::Exactly(1), 0, false),
+				include_str!("../../../tests/fixtures/control/return.ron"),
+			),
+			(
+				Control::Return0(Reg::R(0)),
+				include_str!("../../../tests/fixtures/control/return0.ron"),
+			),
+			(
+				Control::Return1(Reg::R(0)),
+				include_str!("../../../tests/fixtures/control/return1.ron"),
+			),
+			(
+				Control::Unconditional(Target::Undefined(-2)),
+				include_str!("../../../tests/fixtures/control/undefined.ron"),
+			),
+		];
+
+		for (index, (edge, golden)) in golden_list.into_iter().enumerate() {
+			check_golden(Block::new(index as u32, Vec::new(), edge), golden);
+		}
+	}
 }
@@ -1,3 +1,4 @@
+pub mod decode;
 pub mod inst;
 pub mod types;
 pub mod varint;
@@ -1,12 +1,15 @@
 use crate::{
-	common::types::{Instruction, Integer, Local, Number, Upvalue, Value},
+	common::types::{Instruction, Integer, Layout, Local, Number, Upvalue, Value},
 	ext_operand, ext_s_operand,
 };
 use bit_field::BitField;
 use num_enum::{FromPrimitive, IntoPrimitive, TryFromPrimitive};
-use std::convert::TryFrom;
+use serde::{Deserialize, Serialize};
+use std::{convert::TryFrom, fmt};
 
-pub const LUA_MAGIC: &[u8] = b"\x1BLua\x54\x00";
+pub const LUA_SIGNATURE: &[u8] = b"\x1BLua";
+pub const LUA_VERSION: u8 = 0x54;
+pub const LUA_FORMAT: u8 = 0;
 pub const LUA_DATA: &[u8] = b"\x19\x93\r\n\x1a\n";
 pub const LUA_INT: Integer = 0x5678;
 pub const LUA_NUM: Number = 370.5;
@@ -98,7 +101,8 @@ pub enum Constant {
 	LongString = 0b10100,
 }
 
-#[derive(FromPrimitive, IntoPrimitive, PartialEq, Eq)]
+#[derive(FromPrimitive, IntoPrimitive, PartialEq, Eq, Clone, Copy, Deserialize, Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 #[repr(u8)]
 pub enum Opcode {
 	Move = 0,
@@ -213,6 +217,165 @@ pub enum Opcode {
 	Invalid,
 }
 
+impl Opcode {
+	// as `lopnames.h` spells them, which is what `luac -l` prints
+	pub fn name(self) -> &'static str {
+		match self {
+			Opcode::Move => "MOVE",
+			Opcode::LoadI => "LOADI",
+			Opcode::LoadF => "LOADF",
+			Opcode::LoadK => "LOADK",
+			Opcode::LoadKX => "LOADKX",
+			Opcode::LoadFalse => "LOADFALSE",
+			Opcode::LFalseSkip => "LFALSESKIP",
+			Opcode::LoadTrue => "LOADTRUE",
+			Opcode::LoadNil => "LOADNIL",
+			Opcode::GetUpval => "GETUPVAL",
+			Opcode::SetUpval => "SETUPVAL",
+			Opcode::GetTabUp => "GETTABUP",
+			Opcode::GetTable => "GETTABLE",
+			Opcode::GetI => "GETI",
+			Opcode::GetField => "GETFIELD",
+			Opcode::SetTabUp => "SETTABUP",
+			Opcode::SetTable => "SETTABLE",
+			Opcode::SetI => "SETI",
+			Opcode::SetField => "SETFIELD",
+			Opcode::NewTable => "NEWTABLE",
+			Opcode::Method => "SELF",
+			Opcode::AddI => "ADDI",
+			Opcode::AddK => "ADDK",
+			Opcode::SubK => "SUBK",
+			Opcode::MulK => "MULK",
+			Opcode::ModK => "MODK",
+			Opcode::PowK => "POWK",
+			Opcode::DivK => "DIVK",
+			Opcode::IDivK => "IDIVK",
+			Opcode::BandK => "BANDK",
+			Opcode::BorK => "BORK",
+			Opcode::BxorK => "BXORK",
+			Opcode::ShrI => "SHRI",
+			Opcode::ShlI => "SHLI",
+			Opcode::Add => "ADD",
+			Opcode::Sub => "SUB",
+			Opcode::Mul => "MUL",
+			Opcode::Mod => "MOD",
+			Opcode::Pow => "POW",
+			Opcode::Div => "DIV",
+			Opcode::IDiv => "IDIV",
+			Opcode::Band => "BAND",
+			Opcode::Bor => "BOR",
+			Opcode::Bxor => "BXOR",
+			Opcode::Shl => "SHL",
+			Opcode::Shr => "SHR",
+			Opcode::MmBin => "MMBIN",
+			Opcode::MmBinI => "MMBINI",
+			Opcode::MmBinK => "MMBINK",
+			Opcode::Unm => "UNM",
+			Opcode::Bnot => "BNOT",
+			Opcode::Not => "NOT",
+			Opcode::Len => "LEN",
+			Opcode::Concat => "CONCAT",
+			Opcode::Close => "CLOSE",
+			Opcode::Tbc => "TBC",
+			Opcode::Jmp => "JMP",
+			Opcode::Eq => "EQ",
+			Opcode::Lt => "LT",
+			Opcode::Le => "LE",
+			Opcode::EqK => "EQK",
+			Opcode::EqI => "EQI",
+			Opcode::LtI => "LTI",
+			Opcode::LeI => "LEI",
+			Opcode::GtI => "GTI",
+			Opcode::GeI => "GEI",
+			Opcode::Test => "TEST",
+			Opcode::TestSet => "TESTSET",
+			Opcode::Call => "CALL",
+			Opcode::TailCall => "TAILCALL",
+			Opcode::Return => "RETURN",
+			Opcode::Return0 => "RETURN0",
+			Opcode::Return1 => "RETURN1",
+			Opcode::ForLoop => "FORLOOP",
+			Opcode::ForPrep => "FORPREP",
+			Opcode::TForPrep => "TFORPREP",
+			Opcode::TForCall => "TFORCALL",
+			Opcode::TForLoop => "TFORLOOP",
+			Opcode::SetList => "SETLIST",
+			Opcode::Closure => "CLOSURE",
+			Opcode::Vararg => "VARARG",
+			Opcode::VarargPrep => "VARARGPREP",
+			Opcode::ExtraArg => "EXTRAARG",
+			Opcode::Invalid => "INVALID",
+		}
+	}
+}
+
+// the operands in the order and form `luac -l` prints them, less the comments
+// it works out from the rest of the proto, unknown opcodes show their number
+impl fmt::Display for Inst {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		let op = self.opcode();
+		let (a, b, c) = (self.a(), self.b(), self.c());
+		let k = u8::from(self.k());
+		let suffix = if self.k() { "k" } else { "" };
+
+		let operands = match op {
+			Opcode::Move
+			| Opcode::LoadNil
+			| Opcode::GetUpval
+			| Opcode::SetUpval
+			| Opcode::Unm
+			| Opcode::Bnot
+			| Opcode::Not
+			| Opcode::Len
+			| Opcode::Concat => format!("{} {}", a, b),
+			Opcode::LoadI | Opcode::LoadF => format!("{} {}", a, self.sbx()),
+			Opcode::LoadK
+			| Opcode::ForLoop
+			| Opcode::ForPrep
+			| Opcode::TForPrep
+			| Opcode::TForLoop
+			| Opcode::Closure => format!("{} {}", a, self.bx()),
+			Opcode::LoadKX
+			| Opcode::LoadFalse
+			| Opcode::LFalseSkip
+			| Opcode::LoadTrue
+			| Opcode::Close
+			| Opcode::Tbc
+			| Opcode::Return1
+			| Opcode::VarargPrep => a.to_string(),
+			Opcode::SetTabUp
+			| Opcode::SetTable
+			| Opcode::SetI
+			| Opcode::SetField
+			| Opcode::Method
+			| Opcode::TailCall
+			| Opcode::Return => format!("{} {} {}{}", a, b, c, suffix),
+			Opcode::AddI | Opcode::ShrI | Opcode::ShlI => format!("{} {} {}", a, b, self.sc()),
+			Opcode::MmBinI => format!("{} {} {} {}", a, self.sb(), c, k),
+			Opcode::MmBinK => format!("{} {} {} {}", a, b, c, k),
+			Opcode::Jmp => self.sj().to_string(),
+			Opcode::Eq | Opcode::Lt | Opcode::Le | Opcode::EqK | Opcode::TestSet => {
+				format!("{} {} {}", a, b, k)
+			}
+			Opcode::EqI | Opcode::LtI | Opcode::LeI | Opcode::GtI | Opcode::GeI => {
+				format!("{} {} {}", a, self.sb(), k)
+			}
+			Opcode::Test => format!("{} {}", a, k),
+			Opcode::Return0 => String::new(),
+			Opcode::TForCall | Opcode::Vararg => format!("{} {}", a, c),
+			Opcode::ExtraArg => self.ax().to_string(),
+			Opcode::Invalid => format!("{} {} {} {}{}", self.inner.get_bits(0..7), a, b, c, suffix),
+			_ => format!("{} {} {}", a, b, c),
+		};
+
+		if operands.is_empty() {
+			write!(f, "{}", op.name())
+		} else {
+			write!(f, "{:<9} {}", op.name(), operands)
+		}
+	}
+}
+
 pub struct AbsLine {
 	pub pc: u32,
 	pub line: u32,
@@ -228,6 +391,8 @@ impl From<(u32, u32)> for AbsLine {
 }
 
 pub struct Proto {
+	// only the main function's is used
+	pub layout: Layout,
 	pub source: Option<String>,
 	pub is_vararg: u8,
 	pub num_stack: u8,
@@ -242,3 +407,153 @@ pub struct Proto {
 	pub abs_line_list: Vec<AbsLine>,
 	pub local_list: Vec<Local>,
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	// `lopnames.h` in opcode order
+	const NAME_LIST: [&str; 83] = [
+		"MOVE",
+		"LOADI",
+		"LOADF",
+		"LOADK",
+		"LOADKX",
+		"LOADFALSE",
+		"LFALSESKIP",
+		"LOADTRUE",
+		"LOADNIL",
+		"GETUPVAL",
+		"SETUPVAL",
+		"GETTABUP",
+		"GETTABLE",
+		"GETI",
+		"GETFIELD",
+		"SETTABUP",
+		"SETTABLE",
+		"SETI",
+		"SETFIELD",
+		"NEWTABLE",
+		"SELF",
+		"ADDI",
+		"ADDK",
+		"SUBK",
+		"MULK",
+		"MODK",
+		"POWK",
+		"DIVK",
+		"IDIVK",
+		"BANDK",
+		"BORK",
+		"BXORK",
+		"SHRI",
+		"SHLI",
+		"ADD",
+		"SUB",
+		"MUL",
+		"MOD",
+		"POW",
+		"DIV",
+		"IDIV",
+		"BAND",
+		"BOR",
+		"BXOR",
+		"SHL",
+		"SHR",
+		"MMBIN",
+		"MMBINI",
+		"MMBINK",
+		"UNM",
+		"BNOT",
+		"NOT",
+		"LEN",
+		"CONCAT",
+		"CLOSE",
+		"TBC",
+		"JMP",
+		"EQ",
+		"LT",
+		"LE",
+		"EQK",
+		"EQI",
+		"LTI",
+		"LEI",
+		"GTI",
+		"GEI",
+		"TEST",
+		"TESTSET",
+		"CALL",
+		"TAILCALL",
+		"RETURN",
+		"RETURN0",
+		"RETURN1",
+		"FORLOOP",
+		"FORPREP",
+		"TFORPREP",
+		"TFORCALL",
+		"TFORLOOP",
+		"SETLIST",
+		"CLOSURE",
+		"VARARG",
+		"VARARGPREP",
+		"EXTRAARG",
+	];
+
+	#[test]
+	fn opcode_names_follow_lopnames() {
+		for (number, name) in NAME_LIST.iter().enumerate() {
+			let op = Opcode::from(number as u8);
+
+			assert_eq!(u8::from(op), number as u8);
+			assert_eq!(op.name(), *name, "opcode {}", number);
+			assert_eq!(Inst::from(op).opcode().name(), *name);
+		}
+
+		for number in NAME_LIST.len()..128 {
+			assert!(Opcode::from(number as u8) == Opcode::Invalid);
+		}
+	}
+
+	// the operands `luac -l` prints for instructions taken from its listings of
+	// small scripts, the name padded to the same width
+	#[test]
+	fn operands_follow_luac() {
+		let case_list = [
+			(Inst::iabc(Opcode::VarargPrep, 0, 0, 0), "VARARGPREP 0"),
+			(Inst::iabc(Opcode::NewTable, 0, 0, 0), "NEWTABLE  0 0 0"),
+			(
+				Inst::iabc(Opcode::SetField, 0, 0, 1).set_k(true),
+				"SETFIELD  0 0 1k",
+			),
+			(Inst::iabc(Opcode::GetTabUp, 1, 0, 2), "GETTABUP  1 0 2"),
+			(Inst::iabc(Opcode::GetField, 2, 0, 0), "GETFIELD  2 0 0"),
+			(Inst::iabsc(Opcode::AddI, 2, 2, 1), "ADDI      2 2 1"),
+			(
+				Inst::iasbc(Opcode::MmBinI, 2, 1, 6).set_k(false),
+				"MMBINI    2 1 6 0",
+			),
+			(Inst::iabc(Opcode::Call, 1, 2, 1), "CALL      1 2 1"),
+			(Inst::iasbx(Opcode::LoadI, 0, -1), "LOADI     0 -1"),
+			(Inst::iabx(Opcode::ForPrep, 0, 1), "FORPREP   0 1"),
+			(Inst::iabx(Opcode::ForLoop, 0, 2), "FORLOOP   0 2"),
+			(Inst::iasbc(Opcode::EqI, 0, 1, 0), "EQI       0 1 0"),
+			(Inst::isj(Opcode::Jmp, -3), "JMP       -3"),
+			(
+				Inst::iabc(Opcode::Test, 0, 0, 0).set_k(true),
+				"TEST      0 1",
+			),
+			(Inst::iabc(Opcode::TForCall, 3, 0, 2), "TFORCALL  3 2"),
+			(Inst::iabc(Opcode::Vararg, 0, 0, 0), "VARARG    0 0"),
+			(
+				Inst::iabc(Opcode::Return, 0, 1, 1).set_k(true),
+				"RETURN    0 1 1k",
+			),
+			(Inst::iabc(Opcode::Return0, 0, 0, 0), "RETURN0"),
+			(Inst::iax(Opcode::ExtraArg, 70000), "EXTRAARG  70000"),
+		];
+
+		for (inst, text) in case_list.iter() {
+			assert_eq!(inst.to_string(), *text);
+		}
+	}
+}
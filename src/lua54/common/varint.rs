@@ -8,12 +8,11 @@ use std::{
 	convert::TryFrom,
 	io::{Result, Write},
 	iter::once,
-	mem::size_of,
 };
 
 pub type Unsigned = u64;
 
-const TAIL_LEN: usize = size_of::<Unsigned>() * 8 / 7 - 1;
+const TAIL_LEN: usize = Unsigned::BITS as usize / 7 - 1;
 const UNSG_LEN: usize = TAIL_LEN + 1;
 
 pub fn dump_unsigned(mut val: Unsigned, w: &mut dyn Write) -> Result<()> {
@@ -35,7 +34,7 @@ pub fn dump_unsigned(mut val: Unsigned, w: &mut dyn Write) -> Result<()> {
 	w.write_all(&result[start..])
 }
 
-pub fn load_unsigned(input: &[u8]) -> Res<Unsigned> {
+pub fn load_unsigned(input: &[u8]) -> Res<'_, Unsigned> {
 	let (input, tail) = take_while_m_n(0, TAIL_LEN, |v| v & 0x80 == 0)(input)?;
 	let (input, head) = map(verify(u8, |v| v & 0x80 != 0), |v| v & 0x7F)(input)?;
 	let result = tail
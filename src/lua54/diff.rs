@@ -0,0 +1,143 @@
+use super::common::inst::Block;
+use crate::common::types::Function;
+use serde::Serialize;
+use std::{collections::BTreeMap, fmt};
+
+// compared as their compact RON, which is what both files were read from
+fn text<T: Serialize>(data: &T) -> String {
+	ron::ser::to_string(data).unwrap_or_default()
+}
+
+pub enum Change {
+	BlockRemoved(u32),
+	BlockAdded(u32),
+	BodyLength(u32, usize, usize),
+	Edge(u32, String, String),
+	ValueRemoved(usize, String),
+	ValueAdded(usize, String),
+	Value(usize, String, String),
+	ProtoRemoved,
+	ProtoAdded,
+}
+
+// changes are listed per proto, by the same dotted path `--proto` takes
+#[derive(Default)]
+pub struct DiffReport {
+	pub change_list: Vec<(String, Change)>,
+}
+
+impl DiffReport {
+	fn add_function(&mut self, path: &str, a: &Function<Block>, b: &Function<Block>) {
+		let map_a: BTreeMap<u32, &Block> = a.block_list.iter().map(|v| (v.label, v)).collect();
+		let map_b: BTreeMap<u32, &Block> = b.block_list.iter().map(|v| (v.label, v)).collect();
+		let mut push = |change| self.change_list.push((path.to_string(), change));
+
+		// blocks are matched by label, so their order never shows up
+		for (&label, blk_a) in &map_a {
+			let blk_b = match map_b.get(&label) {
+				Some(blk) => blk,
+				None => {
+					push(Change::BlockRemoved(label));
+					continue;
+				}
+			};
+
+			if blk_a.body.len() != blk_b.body.len() {
+				push(Change::BodyLength(
+					label,
+					blk_a.body.len(),
+					blk_b.body.len(),
+				));
+			}
+
+			let (edge_a, edge_b) = (text(&blk_a.edge), text(&blk_b.edge));
+
+			if edge_a != edge_b {
+				push(Change::Edge(label, edge_a, edge_b));
+			}
+		}
+
+		for &label in map_b.keys().filter(|v| !map_a.contains_key(v)) {
+			push(Change::BlockAdded(label));
+		}
+
+		// constants are referred to by index, names are left out
+		let value_count = a.value_list.len().max(b.value_list.len());
+
+		for index in 0..value_count {
+			let value_a = a.value_list.get(index).map(|v| text(&v.1));
+			let value_b = b.value_list.get(index).map(|v| text(&v.1));
+
+			match (value_a, value_b) {
+				(Some(va), Some(vb)) if va != vb => push(Change::Value(index, va, vb)),
+				(Some(va), None) => push(Change::ValueRemoved(index, va)),
+				(None, Some(vb)) => push(Change::ValueAdded(index, vb)),
+				_ => {}
+			}
+		}
+
+		let child_count = a.child_list.len().max(b.child_list.len());
+
+		for index in 0..child_count {
+			let child_path = if path.is_empty() {
+				index.to_string()
+			} else {
+				format!("{}.{}", path, index)
+			};
+
+			match (a.child_list.get(index), b.child_list.get(index)) {
+				(Some((_, ca)), Some((_, cb))) => self.add_function(&child_path, ca, cb),
+				(Some(_), None) => self.change_list.push((child_path, Change::ProtoRemoved)),
+				(None, Some(_)) => self.change_list.push((child_path, Change::ProtoAdded)),
+				(None, None) => {}
+			}
+		}
+	}
+}
+
+// structural differences going from `a` to `b`, nested protos included
+pub fn diff_functions(a: &Function<Block>, b: &Function<Block>) -> DiffReport {
+	let mut report = DiffReport::default();
+
+	report.add_function("", a, b);
+
+	report
+}
+
+impl fmt::Display for Change {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Change::BlockRemoved(label) => write!(f, "block {} only in the first", label),
+			Change::BlockAdded(label) => write!(f, "block {} only in the second", label),
+			Change::BodyLength(label, a, b) => {
+				write!(f, "block {} body has {} instructions, then {}", label, a, b)
+			}
+			Change::Edge(label, a, b) => write!(f, "block {} edge {} -> {}", label, a, b),
+			Change::ValueRemoved(index, a) => {
+				write!(f, "constant {} {} only in the first", index, a)
+			}
+			Change::ValueAdded(index, b) => {
+				write!(f, "constant {} {} only in the second", index, b)
+			}
+			Change::Value(index, a, b) => write!(f, "constant {} {} -> {}", index, a, b),
+			Change::ProtoRemoved => write!(f, "proto only in the first"),
+			Change::ProtoAdded => write!(f, "proto only in the second"),
+		}
+	}
+}
+
+impl fmt::Display for DiffReport {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		if self.change_list.is_empty() {
+			return writeln!(f, "no differences");
+		}
+
+		for (path, change) in &self.change_list {
+			let path = if path.is_empty() { "main" } else { path };
+
+			writeln!(f, "{}: {}", path, change)?;
+		}
+
+		Ok(())
+	}
+}
@@ -1,15 +1,19 @@
 use super::splitter::{Block as PreBlock, Splitter};
 use crate::{
 	common::{
-		types::{Function, LineInfo, StackInfo},
+		types::{Function, Instruction, LineInfo, Named, StackInfo},
 		unique::{name_child_list, name_upvalue_list, name_value_list},
 	},
-	lua54::common::{
-		inst::{Block, Condition, Control, Loop, Reg, RegOrK, Target, IR},
-		types::{Inst, Opcode, Proto},
+	lua54::{
+		common::{
+			decode::DecodedInstruction,
+			inst::{Block, Condition, Control, Loop, Reg, RegOrK, Target, IR},
+			types::{Inst, Opcode, Proto},
+		},
+		loader::LoadError,
 	},
 };
-use std::rc::Rc;
+use std::{convert::TryFrom, sync::Arc};
 
 fn swap_if_k(inst: Inst, opt1: Target, opt2: Target) -> (Target, Target) {
 	if inst.k() {
@@ -19,14 +23,26 @@ fn swap_if_k(inst: Inst, opt1: Target, opt2: Target) -> (Target, Target) {
 	}
 }
 
+// an operand naming an entry its list doesn't have, which only a corrupt or
+// hand made chunk can hold
+fn get_name(list: &[Arc<str>], kind: &'static str, index: u32) -> Result<Arc<str>, LoadError> {
+	list.get(index as usize)
+		.map(Arc::clone)
+		.ok_or(LoadError::BadIndex { kind, index })
+}
+
 struct Translator {
-	child_list: Vec<Rc<str>>,
-	upval_list: Vec<Rc<str>>,
-	value_list: Vec<Rc<str>>,
+	child_list: Vec<Arc<str>>,
+	upval_list: Vec<Arc<str>>,
+	value_list: Vec<Arc<str>>,
 }
 
 impl Translator {
-	fn new(child_list: Vec<Rc<str>>, upval_list: Vec<Rc<str>>, value_list: Vec<Rc<str>>) -> Self {
+	fn new(
+		child_list: Vec<Arc<str>>,
+		upval_list: Vec<Arc<str>>,
+		value_list: Vec<Arc<str>>,
+	) -> Self {
 		Self {
 			child_list,
 			upval_list,
@@ -34,7 +50,7 @@ impl Translator {
 		}
 	}
 
-	fn translate(self, pre_list: Vec<PreBlock>) -> Vec<Block> {
+	fn translate(self, pre_list: Vec<PreBlock>) -> Result<Vec<Block>, LoadError> {
 		pre_list
 			.into_iter()
 			.enumerate()
@@ -42,28 +58,28 @@ impl Translator {
 			.collect()
 	}
 
-	fn get_val_name<T>(&self, index: T) -> Rc<str>
+	fn get_val_name<T>(&self, index: T) -> Result<Arc<str>, LoadError>
 	where
 		T: Into<u32>,
 	{
-		Rc::clone(&self.value_list[index.into() as usize])
+		get_name(&self.value_list, "constant", index.into())
 	}
 
-	fn get_upval_name(&self, index: u8) -> Rc<str> {
-		Rc::clone(&self.upval_list[index as usize])
+	fn get_upval_name(&self, index: u8) -> Result<Arc<str>, LoadError> {
+		get_name(&self.upval_list, "upvalue", index.into())
 	}
 
-	fn get_child_name(&self, index: u32) -> Rc<str> {
-		Rc::clone(&self.child_list[index as usize])
+	fn get_child_name(&self, index: u32) -> Result<Arc<str>, LoadError> {
+		get_name(&self.child_list, "child", index)
 	}
 
-	fn get_rk_value(&self, inst: Inst) -> RegOrK {
+	fn get_rk_value(&self, inst: Inst) -> Result<RegOrK, LoadError> {
 		let c = inst.c();
 
 		if inst.k() {
-			self.get_val_name(c).into()
+			Ok(self.get_val_name(c)?.into())
 		} else {
-			c.into()
+			Ok(c.into())
 		}
 	}
 
@@ -94,15 +110,15 @@ impl Translator {
 		inst: Inst,
 		on_true: Target,
 		on_false: Target,
-		func: fn(Reg, Rc<str>) -> Condition,
-	) -> Control {
+		func: fn(Reg, Arc<str>) -> Condition,
+	) -> Result<Control, LoadError> {
 		let (on_true, on_false) = swap_if_k(inst, on_true, on_false);
 
-		Control::Condition(
-			func(inst.a().into(), self.get_val_name(inst.b())),
+		Ok(Control::Condition(
+			func(inst.a().into(), self.get_val_name(inst.b())?),
 			on_true,
 			on_false,
-		)
+		))
 	}
 
 	fn gen_loop(inst: Inst, on_true: Target, on_false: Target, func: fn(Reg) -> Loop) -> Control {
@@ -112,9 +128,14 @@ impl Translator {
 		Control::Loop(func(inst.a().into()), on_false, on_true)
 	}
 
-	fn translate_control(&self, last: Inst, on_true: Target, on_false: Target) -> Control {
+	fn translate_control(
+		&self,
+		last: Inst,
+		on_true: Target,
+		on_false: Target,
+	) -> Result<Control, LoadError> {
 		// farewell, the void is calling
-		match last.opcode() {
+		let control = match last.opcode() {
 			Opcode::TForLoop => Self::gen_loop(last, on_true, on_false, Loop::Iterator),
 			Opcode::TForPrep => Self::gen_loop(last, on_true, on_false, Loop::IteratorPrep),
 			Opcode::ForLoop => Self::gen_loop(last, on_true, on_false, Loop::Numeric),
@@ -136,7 +157,7 @@ impl Translator {
 			}
 			Opcode::Eq => Self::gen_condition_normal(last, on_true, on_false, Condition::Eq),
 			Opcode::EqI => Self::gen_condition_imm(last, on_true, on_false, Condition::EqI),
-			Opcode::EqK => self.gen_condition_const(last, on_true, on_false, Condition::EqK),
+			Opcode::EqK => self.gen_condition_const(last, on_true, on_false, Condition::EqK)?,
 			Opcode::GeI => Self::gen_condition_imm(last, on_true, on_false, Condition::GeI),
 			Opcode::GtI => Self::gen_condition_imm(last, on_true, on_false, Condition::GtI),
 			Opcode::Le => Self::gen_condition_normal(last, on_true, on_false, Condition::Le),
@@ -144,11 +165,13 @@ impl Translator {
 			Opcode::Lt => Self::gen_condition_normal(last, on_true, on_false, Condition::Lt),
 			Opcode::LtI => Self::gen_condition_imm(last, on_true, on_false, Condition::LtI),
 			Opcode::Return => Control::Return(last.a().into(), last.b().into(), last.c(), last.k()),
-			Opcode::Return0 => Control::Return0,
+			Opcode::Return0 => Control::Return0(last.a().into()),
 			Opcode::Return1 => Control::Return1(last.a().into()),
 			// Opcode::Jmp included
 			_ => Control::Unconditional(on_true),
-		}
+		};
+
+		Ok(control)
 	}
 
 	fn gen_unop_normal(inst: Inst, func: fn(Reg, Reg) -> IR) -> IR {
@@ -159,19 +182,23 @@ impl Translator {
 		func(inst.a().into(), inst.b().into(), inst.sc())
 	}
 
-	fn gen_binop_const(&self, inst: Inst, func: fn(Reg, Reg, Rc<str>) -> IR) -> IR {
-		func(
+	fn gen_binop_const(
+		&self,
+		inst: Inst,
+		func: fn(Reg, Reg, Arc<str>) -> IR,
+	) -> Result<IR, LoadError> {
+		Ok(func(
 			inst.a().into(),
 			inst.b().into(),
-			self.get_val_name(inst.c()),
-		)
+			self.get_val_name(inst.c())?,
+		))
 	}
 
 	fn gen_binop_normal(inst: Inst, func: fn(Reg, Reg, Reg) -> IR) -> IR {
 		func(inst.a().into(), inst.b().into(), inst.c().into())
 	}
 
-	fn translate_code(&self, code: &[Inst]) -> Vec<IR> {
+	fn translate_code(&self, code: &[Inst]) -> Result<Vec<IR>, LoadError> {
 		let mut list = Vec::new();
 		let mut iter = code.iter().copied().peekable();
 
@@ -180,73 +207,69 @@ impl Translator {
 				Opcode::Move => IR::Move(inst.a().into(), inst.b().into()),
 				Opcode::LoadI => IR::LoadI(inst.a().into(), inst.sbx()),
 				Opcode::LoadF => IR::LoadF(inst.a().into(), inst.sbx()),
-				Opcode::LoadK => IR::LoadK(inst.a().into(), self.get_val_name(inst.bx())),
+				Opcode::LoadK => IR::LoadK(inst.a().into(), self.get_val_name(inst.bx())?),
+				// the constant index is in the `ExtraArg` that follows
 				Opcode::LoadKX => {
-					let post = iter.peek().copied().unwrap_or_default();
+					list.push(IR::LoadKX(inst.a().into()));
 
-					if post.opcode() == Opcode::ExtraArg {
-						iter.next().unwrap();
+					match iter.next_if(|v| v.opcode() == Opcode::ExtraArg) {
+						Some(post) => IR::ExtraValue(self.get_val_name(post.ax())?),
+						None => continue,
 					}
-
-					list.push(IR::LoadKX(inst.a().into()));
-					IR::ExtraValue(self.get_val_name(post.ax()))
 				}
 				Opcode::LoadFalse => IR::LoadFalse(inst.a().into()),
 				Opcode::LoadTrue => IR::LoadTrue(inst.a().into()),
 				Opcode::LoadNil => IR::LoadNil(inst.a().into(), inst.b()),
-				Opcode::GetUpval => IR::GetUpval(inst.a().into(), self.get_upval_name(inst.b())),
-				Opcode::SetUpval => IR::SetUpval(inst.a().into(), self.get_upval_name(inst.b())),
+				Opcode::GetUpval => IR::GetUpval(inst.a().into(), self.get_upval_name(inst.b())?),
+				Opcode::SetUpval => IR::SetUpval(inst.a().into(), self.get_upval_name(inst.b())?),
 				Opcode::GetTabUp => IR::GetTabUp(
 					inst.a().into(),
-					self.get_upval_name(inst.b()),
-					self.get_val_name(inst.c()),
+					self.get_upval_name(inst.b())?,
+					self.get_val_name(inst.c())?,
 				),
 				Opcode::GetTable => IR::GetTable(inst.a().into(), inst.b().into(), inst.c().into()),
 				Opcode::GetI => IR::GetI(inst.a().into(), inst.b().into(), inst.c()),
 				Opcode::GetField => IR::GetField(
 					inst.a().into(),
 					inst.b().into(),
-					self.get_val_name(inst.c()),
+					self.get_val_name(inst.c())?,
 				),
 				Opcode::SetTabUp => IR::SetTabUp(
-					self.get_upval_name(inst.a()),
-					self.get_val_name(inst.b()),
-					self.get_rk_value(inst),
+					self.get_upval_name(inst.a())?,
+					self.get_val_name(inst.b())?,
+					self.get_rk_value(inst)?,
 				),
 				Opcode::SetTable => {
-					IR::SetTable(inst.a().into(), inst.b().into(), self.get_rk_value(inst))
+					IR::SetTable(inst.a().into(), inst.b().into(), self.get_rk_value(inst)?)
 				}
-				Opcode::SetI => IR::SetI(inst.a().into(), inst.b(), self.get_rk_value(inst)),
+				Opcode::SetI => IR::SetI(inst.a().into(), inst.b(), self.get_rk_value(inst)?),
 				Opcode::SetField => IR::SetField(
 					inst.a().into(),
-					self.get_val_name(inst.b()),
-					self.get_rk_value(inst),
+					self.get_val_name(inst.b())?,
+					self.get_rk_value(inst)?,
 				),
 				Opcode::NewTable => {
-					let post = iter.peek().copied().unwrap_or_default();
-
 					list.push(IR::NewTable(inst.a().into(), inst.b(), inst.c(), inst.k()));
 
-					if post.opcode() == Opcode::ExtraArg {
-						continue;
+					match iter.next_if(|v| v.opcode() == Opcode::ExtraArg) {
+						Some(post) => IR::ExtraInteger(post.ax()),
+						None => continue,
 					}
-
-					IR::ExtraInteger(post.ax())
 				}
 				Opcode::Method => {
-					IR::Method(inst.a().into(), inst.b().into(), self.get_rk_value(inst))
+					IR::Method(inst.a().into(), inst.b().into(), self.get_rk_value(inst)?)
 				}
 				Opcode::AddI => Self::gen_binop_imm(inst, IR::AddI),
-				Opcode::AddK => self.gen_binop_const(inst, IR::AddK),
-				Opcode::SubK => self.gen_binop_const(inst, IR::SubK),
-				Opcode::MulK => self.gen_binop_const(inst, IR::MulK),
-				Opcode::ModK => self.gen_binop_const(inst, IR::ModK),
-				Opcode::PowK => self.gen_binop_const(inst, IR::PowK),
-				Opcode::DivK => self.gen_binop_const(inst, IR::DivK),
-				Opcode::IDivK => self.gen_binop_const(inst, IR::IDivK),
-				Opcode::BandK => self.gen_binop_const(inst, IR::BandK),
-				Opcode::BorK => self.gen_binop_const(inst, IR::BorK),
-				Opcode::BxorK => self.gen_binop_const(inst, IR::BxorK),
+				Opcode::AddK => self.gen_binop_const(inst, IR::AddK)?,
+				Opcode::SubK => self.gen_binop_const(inst, IR::SubK)?,
+				Opcode::MulK => self.gen_binop_const(inst, IR::MulK)?,
+				Opcode::ModK => self.gen_binop_const(inst, IR::ModK)?,
+				Opcode::PowK => self.gen_binop_const(inst, IR::PowK)?,
+				Opcode::DivK => self.gen_binop_const(inst, IR::DivK)?,
+				Opcode::IDivK => self.gen_binop_const(inst, IR::IDivK)?,
+				Opcode::BandK => self.gen_binop_const(inst, IR::BandK)?,
+				Opcode::BorK => self.gen_binop_const(inst, IR::BorK)?,
+				Opcode::BxorK => self.gen_binop_const(inst, IR::BxorK)?,
 				Opcode::ShrI => Self::gen_binop_imm(inst, IR::ShrI),
 				Opcode::ShlI => Self::gen_binop_imm(inst, IR::ShlI),
 				Opcode::Add => Self::gen_binop_normal(inst, IR::Add),
@@ -265,7 +288,7 @@ impl Translator {
 				Opcode::MmBinI => IR::MmBinI(inst.a().into(), inst.sb(), inst.c().into(), inst.k()),
 				Opcode::MmBinK => IR::MmBinK(
 					inst.a().into(),
-					self.get_val_name(inst.b()),
+					self.get_val_name(inst.b())?,
 					inst.c().into(),
 					inst.k(),
 				),
@@ -280,22 +303,20 @@ impl Translator {
 				Opcode::TailCall => IR::TailCall(inst.a().into(), inst.b().into(), inst.c().into()),
 				Opcode::TForCall => IR::TForCall(inst.a().into(), inst.c()),
 				Opcode::SetList => {
-					let post = iter.peek().copied().unwrap_or_default();
 					let is_ext = inst.k();
 
 					list.push(IR::SetList(inst.a().into(), inst.b(), inst.c(), is_ext));
 
-					if is_ext && post.opcode() != Opcode::ExtraArg {
-						IR::ExtraInteger(post.ax())
-					} else {
-						continue;
+					match iter.next_if(|v| is_ext && v.opcode() == Opcode::ExtraArg) {
+						Some(post) => IR::ExtraInteger(post.ax()),
+						None => continue,
 					}
 				}
-				Opcode::Closure => IR::Closure(inst.a().into(), self.get_child_name(inst.bx())),
+				Opcode::Closure => IR::Closure(inst.a().into(), self.get_child_name(inst.bx())?),
 				Opcode::Vararg => IR::Vararg(inst.a().into(), inst.b().into()),
 				Opcode::VarargPrep => IR::VarargPrep(inst.a().into()),
 				Opcode::ExtraArg => IR::ExtraInteger(inst.ax()),
-				Opcode::Invalid => IR::Invalid(inst.inner),
+				Opcode::Invalid => IR::Invalid(DecodedInstruction::decode(inst)),
 				_ => {
 					continue;
 				}
@@ -304,15 +325,19 @@ impl Translator {
 			list.push(op);
 		}
 
-		list
+		Ok(list)
 	}
 
-	fn translate_block(&self, label: u32, blk: PreBlock) -> Block {
-		let last = blk.code.last().copied().unwrap();
-		let code = self.translate_code(&blk.code);
-		let control = self.translate_control(last, blk.target, Target::Label(label + 1));
+	fn translate_block(&self, label: u32, blk: PreBlock) -> Result<Block, LoadError> {
+		let last = blk
+			.code
+			.last()
+			.copied()
+			.ok_or(LoadError::EmptyBlock { label })?;
+		let code = self.translate_code(&blk.code)?;
+		let control = self.translate_control(last, blk.target, Target::Label(label + 1))?;
 
-		Block::new(label, code, control)
+		Ok(Block::new(label, code, control))
 	}
 }
 
@@ -337,36 +362,66 @@ fn copy_line_info(func: &Proto) -> LineInfo {
 	}
 }
 
-impl From<Proto> for Function<Block> {
-	fn from(func: Proto) -> Self {
+fn names<T>(list: &Named<T>) -> Vec<Arc<str>> {
+	list.iter().map(|v| Arc::clone(&v.0)).collect()
+}
+
+fn split_blocks<B>(code: Vec<Inst>, func: &Function<B>) -> Result<Vec<Block>, LoadError> {
+	let pre_list = Splitter::new().split(code);
+	let translator = Translator::new(
+		names(&func.child_list),
+		names(&func.upval_list),
+		names(&func.value_list),
+	);
+
+	translator.translate(pre_list)
+}
+
+// blocks out of a flat stream, cut wherever a jump leaves or lands and wired
+// the way the chunk would run, with constant, upvalue and child operands named
+// after `func`'s lists, one indexing past its list fails the whole function
+pub fn build_blocks<B>(code: &[Instruction], func: &Function<B>) -> Result<Vec<Block>, LoadError> {
+	let code = code.iter().map(|&inner| Inst { inner }).collect();
+
+	split_blocks(code, func)
+}
+
+// fails when an operand names a constant, upvalue or child the proto doesn't
+// have, which only shows once the code is translated
+impl TryFrom<Proto> for Function<Block> {
+	type Error = LoadError;
+
+	fn try_from(func: Proto) -> Result<Self, LoadError> {
 		let stack_info = copy_stack_info(&func);
 		let line_info = copy_line_info(&func);
 
 		let source = func.source;
 		let local_list = func.local_list;
 
-		let child_list = func.child_list.into_iter().map(Self::from).collect();
+		let child_list = func
+			.child_list
+			.into_iter()
+			.map(Self::try_from)
+			.collect::<Result<_, _>>()?;
 
 		let child_list = name_child_list(child_list);
 		let upval_list = name_upvalue_list(func.upval_list);
 		let value_list = name_value_list(func.value_list);
 
-		let child_name = child_list.iter().map(|v| Rc::clone(&v.0)).collect();
-		let upval_name = upval_list.iter().map(|v| Rc::clone(&v.0)).collect();
-		let value_name = value_list.iter().map(|v| Rc::clone(&v.0)).collect();
-
-		let pre_list = Splitter::new().split(func.inst_list);
-		let block_list = Translator::new(child_name, upval_name, value_name).translate(pre_list);
-
-		Function {
+		let mut result = Function {
+			layout: func.layout,
 			source,
 			stack_info,
 			line_info,
 			value_list,
 			local_list,
 			upval_list,
-			block_list,
+			block_list: Vec::new(),
 			child_list,
-		}
+		};
+
+		result.block_list = split_blocks(func.inst_list, &result)?;
+
+		Ok(result)
 	}
 }
@@ -1,2 +1,4 @@
 mod disasm;
 mod splitter;
+
+pub use disasm::build_blocks;
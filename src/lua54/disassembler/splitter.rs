@@ -42,7 +42,10 @@ impl Splitter {
 	}
 
 	fn find_edges(&mut self, code: &[Inst]) {
+		// the end closes the last block even when nothing jumps out of it,
+		// which bytecode never does but a stream built by hand might
 		self.label_set.insert(0);
+		self.label_set.insert(code.len());
 
 		for (pc, inst) in code.iter().enumerate() {
 			match inst.opcode() {
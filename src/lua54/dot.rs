@@ -0,0 +1,125 @@
+use super::{
+	analysis::calls::CallGraph,
+	common::inst::{Block, Control, Loop, Target},
+};
+use crate::common::types::Function;
+use std::fmt::Write;
+
+// node names are quoted so escape anything that would end the string early
+fn quote(name: &str) -> String {
+	format!("\"{}\"", name.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+fn write_edge(out: &mut String, prefix: &str, from: u32, to: &Target, style: &str) {
+	if let Target::Label(to) = to {
+		let from = quote(&format!("{}{}", prefix, from));
+		let to = quote(&format!("{}{}", prefix, to));
+
+		if style.is_empty() {
+			writeln!(out, "\t{} -> {};", from, to).unwrap();
+		} else {
+			writeln!(out, "\t{} -> {} [{}];", from, to, style).unwrap();
+		}
+	}
+}
+
+fn write_function(out: &mut String, func: &Function<Block>, prefix: &str) {
+	for blk in &func.block_list {
+		writeln!(
+			out,
+			"\t{} [label=\"Block {}\\n{} inst\"];",
+			quote(&format!("{}{}", prefix, blk.label)),
+			blk.label,
+			blk.body.len()
+		)
+		.unwrap();
+	}
+
+	for blk in &func.block_list {
+		let from = blk.label;
+
+		match &blk.edge {
+			Control::Condition(_, on_true, on_false) => {
+				write_edge(out, prefix, from, on_true, "color=green, label=\"true\"");
+				write_edge(out, prefix, from, on_false, "color=red, label=\"false\"");
+			}
+			// `ForLoop` and `TForLoop` jump back to the body and fall out of the loop
+			Control::Loop(Loop::Numeric(_) | Loop::Iterator(_), on_false, on_true) => {
+				write_edge(out, prefix, from, on_true, "style=dashed, label=\"loop\"");
+				write_edge(out, prefix, from, on_false, "label=\"exit\"");
+			}
+			_ => {
+				for to in blk.get_target_labels() {
+					write_edge(out, prefix, from, &Target::Label(to), "");
+				}
+			}
+		}
+	}
+
+	// children get their own cluster, with node names scoped by the child name
+	for (name, child) in &func.child_list {
+		let prefix = format!("{}{}:", prefix, name);
+
+		writeln!(
+			out,
+			"\tsubgraph {} {{",
+			quote(&format!("cluster_{}", prefix))
+		)
+		.unwrap();
+		writeln!(out, "\tlabel={};", quote(name)).unwrap();
+
+		write_function(out, child, &prefix);
+
+		writeln!(out, "\t}}").unwrap();
+	}
+}
+
+pub fn to_dot(func: &Function<Block>) -> String {
+	let mut out = String::new();
+
+	out.push_str("digraph {\n");
+	out.push_str("\tnode [shape=box, fontname=monospace];\n");
+
+	write_function(&mut out, func, "");
+
+	out.push_str("}\n");
+	out
+}
+
+// one node per proto, dashed edges from the proto that makes a closure to it
+// and plain ones for the calls seen
+pub fn call_graph_dot(graph: &CallGraph) -> String {
+	let mut out = String::new();
+
+	out.push_str("digraph {\n");
+	out.push_str("\tnode [shape=box, fontname=monospace];\n");
+
+	for (path, name) in graph.path_list.iter().zip(&graph.name_list) {
+		let label = if path.as_str() == name.as_ref() {
+			quote(path)
+		} else {
+			quote(&format!("{} {}", path, name))
+		};
+
+		writeln!(out, "\t{} [label={}];", quote(path), label).unwrap();
+	}
+
+	for (child, parent) in graph.parent_list.iter().enumerate() {
+		if let Some(parent) = parent {
+			let from = quote(&graph.path_list[*parent]);
+			let to = quote(&graph.path_list[child]);
+
+			writeln!(out, "\t{} -> {} [style=dashed, color=gray];", from, to).unwrap();
+		}
+	}
+
+	for &(from, to) in &graph.call_list {
+		let from = quote(&graph.path_list[from]);
+		let to = quote(&graph.path_list[to]);
+
+		writeln!(out, "\t{} -> {};", from, to).unwrap();
+	}
+
+	out.push_str("}\n");
+	out
+}
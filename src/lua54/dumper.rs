@@ -4,10 +4,10 @@ use super::common::{
 };
 use crate::{
 	common::{
-		loader::Serde,
-		types::{Instruction, Integer, Local, Number, Upvalue, Value},
+		loader::{dump_instruction_as, dump_integer_as, dump_number_as, Serde},
+		types::{Instruction, Layout, Local, Upvalue, Value},
 	},
-	lua54::common::types::{LUA_DATA, LUA_INT, LUA_MAGIC, LUA_NUM},
+	lua54::common::types::{LUA_DATA, LUA_FORMAT, LUA_INT, LUA_NUM, LUA_SIGNATURE, LUA_VERSION},
 };
 use std::{
 	convert::TryFrom,
@@ -24,14 +24,16 @@ where
 		.ser(w)
 }
 
-fn dump_lua_header(w: &mut dyn Write) -> Result<()> {
-	w.write_all(LUA_MAGIC)?;
+fn dump_lua_header(layout: Layout, w: &mut dyn Write) -> Result<()> {
+	w.write_all(LUA_SIGNATURE)?;
+	LUA_VERSION.ser(w)?;
+	LUA_FORMAT.ser(w)?;
 	w.write_all(LUA_DATA)?;
 	dump_size_of::<Instruction>(w)?;
-	dump_size_of::<Integer>(w)?;
-	dump_size_of::<Number>(w)?;
-	LUA_INT.ser(w)?;
-	LUA_NUM.ser(w)?;
+	layout.size_integer.ser(w)?;
+	layout.size_number.ser(w)?;
+	dump_integer_as(layout, LUA_INT, w)?;
+	dump_number_as(layout, LUA_NUM, w)?;
 
 	Ok(())
 }
@@ -43,9 +45,13 @@ where
 	dump_unsigned(val.into(), w)
 }
 
-fn dump_string(val: &str, w: &mut dyn Write) -> Result<()> {
+fn dump_bytes(val: &[u8], w: &mut dyn Write) -> Result<()> {
 	dump_integer(val.len() as u64 + 1, w)?;
-	w.write_all(val.as_bytes())
+	w.write_all(val)
+}
+
+fn dump_string(val: &str, w: &mut dyn Write) -> Result<()> {
+	dump_bytes(val.as_bytes(), w)
 }
 
 fn dump_opt_string(opt: Option<&str>, w: &mut dyn Write) -> Result<()> {
@@ -63,42 +69,47 @@ where
 	list.iter().try_for_each(|v| dump(v, w))
 }
 
-fn dump_constant(value: &Value, w: &mut dyn Write) -> Result<()> {
+// strings of up to 40 bytes are short ones, as `LUAI_MAXSHORTLEN` has it
+fn dump_string_constant(val: &[u8], w: &mut dyn Write) -> Result<()> {
+	if val.len() <= 40 {
+		u8::from(Constant::ShortString).ser(w)?;
+	} else {
+		u8::from(Constant::LongString).ser(w)?;
+	}
+
+	dump_bytes(val, w)
+}
+
+fn dump_constant(layout: Layout, value: &Value, w: &mut dyn Write) -> Result<()> {
 	match value {
 		Value::Nil => u8::from(Constant::Nil).ser(w),
 		Value::False => u8::from(Constant::False).ser(w),
 		Value::True => u8::from(Constant::True).ser(w),
 		Value::Integer(i) => {
 			u8::from(Constant::Integer).ser(w)?;
-			i.ser(w)
+			dump_integer_as(layout, *i, w)
 		}
 		Value::Number(n) => {
 			u8::from(Constant::Number).ser(w)?;
-			n.ser(w)
+			dump_number_as(layout, *n, w)
 		}
 		Value::NoString => {
 			u8::from(Constant::ShortString).ser(w)?;
 			dump_integer(0_u64, w)
 		}
-		Value::String(s) => {
-			if s.len() < 40 {
-				u8::from(Constant::ShortString).ser(w)?;
-			} else {
-				u8::from(Constant::LongString).ser(w)?;
-			}
-
-			dump_string(s, w)
-		}
+		Value::String(s) => dump_string_constant(s.as_bytes(), w),
+		Value::Bytes(s) => dump_string_constant(s, w),
 	}
 }
 
 fn dump_upval(value: &Upvalue, w: &mut dyn Write) -> Result<()> {
 	let in_stack = u8::from(value.in_stack);
 	let index = value.index;
+	let kind = value.kind;
 
 	in_stack.ser(w)?;
 	index.ser(w)?;
-	0_u8.ser(w)?;
+	kind.ser(w)?;
 
 	Ok(())
 }
@@ -118,8 +129,13 @@ fn dump_local(local: &Local, w: &mut dyn Write) -> Result<()> {
 	Ok(())
 }
 
-fn dump_function(proto: &Proto, w: &mut dyn Write) -> Result<()> {
-	let upval_name_list: Vec<_> = proto.upval_list.iter().map(|v| v.name.as_deref()).collect();
+fn dump_function(layout: Layout, proto: &Proto, w: &mut dyn Write) -> Result<()> {
+	let mut upval_name_list: Vec<_> = proto.upval_list.iter().map(|v| v.name.as_deref()).collect();
+
+	// stripped functions have no names at all rather than empty ones
+	if upval_name_list.iter().all(Option::is_none) {
+		upval_name_list.clear();
+	}
 
 	dump_opt_string(proto.source.as_deref(), w)?;
 	dump_integer(proto.line_defined, w)?;
@@ -129,10 +145,14 @@ fn dump_function(proto: &Proto, w: &mut dyn Write) -> Result<()> {
 	proto.is_vararg.ser(w)?;
 	proto.num_stack.ser(w)?;
 
-	dump_list(&proto.inst_list, |v, w| v.inner.ser(w), w)?;
-	dump_list(&proto.value_list, dump_constant, w)?;
+	dump_list(
+		&proto.inst_list,
+		|v, w| dump_instruction_as(layout, v.inner, w),
+		w,
+	)?;
+	dump_list(&proto.value_list, |v, w| dump_constant(layout, v, w), w)?;
 	dump_list(&proto.upval_list, dump_upval, w)?;
-	dump_list(&proto.child_list, dump_function, w)?;
+	dump_list(&proto.child_list, |v, w| dump_function(layout, v, w), w)?;
 
 	dump_list(&proto.rel_line_list, |v, w| v.ser(w), w)?;
 	dump_list(&proto.abs_line_list, dump_abs_line, w)?;
@@ -147,9 +167,75 @@ pub fn dump_lua_module(proto: &Proto) -> Result<Vec<u8>> {
 	let len = proto.upval_list.len();
 	let nup = u8::try_from(len).expect("main function too many upvalues (> 255)");
 
-	dump_lua_header(&mut vec)?;
+	dump_lua_header(proto.layout, &mut vec)?;
 	nup.ser(&mut vec)?;
-	dump_function(proto, &mut vec)?;
+	dump_function(proto.layout, proto, &mut vec)?;
 
 	Ok(vec)
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{assemble, disassemble, lua54::loader::load_lua_module};
+	use std::{fs, path::Path, process::Command};
+
+	// the sources are compiled afresh by the `luac` on PATH, a 5.4 one
+	const SOURCE_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/lua");
+
+	// one output per source and per process, so nothing run alongside shares it
+	fn compile(source: &Path) -> Vec<u8> {
+		let stem = source.file_stem().unwrap().to_string_lossy();
+		let path = std::env::temp_dir().join(format!("lau-{}-{}.luac", std::process::id(), stem));
+		let status = Command::new("luac")
+			.arg("-o")
+			.arg(&path)
+			.arg(source)
+			.status()
+			.expect("no luac on PATH");
+
+		assert!(status.success(), "luac failed on {}", source.display());
+
+		let data = fs::read(&path).unwrap();
+
+		fs::remove_file(&path).unwrap();
+		data
+	}
+
+	// what the reference compiler writes has to come back byte for byte, both
+	// straight through the dumper and lifted to blocks and assembled again
+	#[test]
+	#[ignore = "needs a Lua 5.4 luac on PATH"]
+	fn luac_output_redumps_identically() {
+		let mut source_list: Vec<_> = fs::read_dir(SOURCE_DIR)
+			.unwrap()
+			.map(|v| v.unwrap().path())
+			.filter(|v| v.extension().is_some_and(|v| v == "lua"))
+			.collect();
+
+		source_list.sort();
+		assert!(!source_list.is_empty());
+
+		for source in source_list {
+			let data = compile(&source);
+			let name = source.display();
+			let (rest, proto) =
+				load_lua_module(&data).unwrap_or_else(|e| panic!("{}: {}", name, e));
+
+			assert!(rest.is_empty(), "{}: trailing data", name);
+			assert!(
+				dump_lua_module(&proto).unwrap() == data,
+				"{}: dumped differently",
+				name
+			);
+
+			let func = disassemble(&data).unwrap();
+
+			assert!(
+				assemble(func).unwrap() == data,
+				"{}: assembled differently",
+				name
+			);
+		}
+	}
+}
@@ -0,0 +1,66 @@
+use super::common::inst::{Block, Target, IR};
+use crate::common::types::{Function, LineInfo, Named};
+use std::{collections::HashSet, sync::Arc};
+
+fn named<T: Clone>(list: &Named<T>, name_set: &HashSet<&Arc<str>>) -> Named<T> {
+	list.iter()
+		.filter(|v| name_set.contains(&v.0))
+		.cloned()
+		.collect()
+}
+
+// the block at `label` as the one block of a function of its own, labeled `0`
+// and with every way out left as `Undefined(0)`, only the constants, upvalues
+// and children it names come along, and none of the debug info tied to
+// instruction positions
+pub fn extract_block(func: &Function<Block>, label: u32) -> Option<Function<Block>> {
+	let mut blk = func.block_list.iter().find(|v| v.label == label)?.clone();
+
+	blk.label = 0;
+
+	for target in blk.targets_mut() {
+		*target = Target::Undefined(0);
+	}
+
+	let value_list = blk
+		.referenced_constants(&func.value_list)
+		.into_iter()
+		.map(|v| func.value_list[v].clone())
+		.collect();
+	let mut upval_set = HashSet::new();
+	let mut child_set = HashSet::new();
+
+	for ir in &blk.body {
+		match ir {
+			IR::GetUpval(_, u)
+			| IR::SetUpval(_, u)
+			| IR::GetTabUp(_, u, _)
+			| IR::SetTabUp(u, ..) => {
+				upval_set.insert(u);
+			}
+			IR::Closure(_, name) => {
+				child_set.insert(name);
+			}
+			_ => {}
+		}
+	}
+
+	let upval_list = named(&func.upval_list, &upval_set);
+	let child_list = named(&func.child_list, &child_set);
+
+	Some(Function {
+		layout: func.layout,
+		source: func.source.clone(),
+		stack_info: func.stack_info.clone(),
+		line_info: LineInfo {
+			line_defined: func.line_info.line_defined,
+			line_offset: Vec::new(),
+			line_data: Vec::new(),
+		},
+		value_list,
+		local_list: Vec::new(),
+		upval_list,
+		block_list: vec![blk],
+		child_list,
+	})
+}
@@ -0,0 +1,26 @@
+use super::{
+	common::types::Proto,
+	dumper::dump_lua_module,
+	loader::{load_lua_module, LoadError},
+};
+use std::io::Result;
+
+// a bytecode version that can be read into the 5.4 prototype everything else
+// works on, and written back from it when the version supports that, shared
+// with whatever thread a pass runs on
+pub trait BytecodeFormat: Sync {
+	fn load<'a>(&self, input: &'a [u8]) -> std::result::Result<(&'a [u8], Proto), LoadError>;
+	fn dump(&self, proto: &Proto) -> Result<Vec<u8>>;
+}
+
+pub struct Lua54;
+
+impl BytecodeFormat for Lua54 {
+	fn load<'a>(&self, input: &'a [u8]) -> std::result::Result<(&'a [u8], Proto), LoadError> {
+		load_lua_module(input)
+	}
+
+	fn dump(&self, proto: &Proto) -> Result<Vec<u8>> {
+		dump_lua_module(proto)
+	}
+}
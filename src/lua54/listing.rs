@@ -0,0 +1,526 @@
+use super::{
+	analysis::{dead_loops::find_dead_loops, loops::find_loops},
+	assembler::{block_spans, Encoder},
+	common::inst::{Block, Condition, Control, Group, Loop, Reg, Target, IR},
+};
+use crate::common::types::{Function, LineInfo, Value};
+use std::{collections::HashMap, fmt::Write, ops::Range};
+
+// a relative line of this much means the line is in the absolute list instead
+const ABS_LINE_INFO: i8 = -0x80;
+
+fn reg(r: &Reg) -> String {
+	let Reg::R(x) = r;
+
+	format!("r{}", x)
+}
+
+fn target(t: &Target) -> String {
+	match t {
+		Target::Label(label) => format!("Block {}", label),
+		Target::Undefined(offset) => format!("offset {}", offset),
+	}
+}
+
+fn value(v: &Value) -> String {
+	match v {
+		Value::Integer(x) => x.to_string(),
+		Value::Number(x) => format!("{:?}", x),
+		Value::String(x) => format!("{:?}", x),
+		Value::Bytes(x) => {
+			let text: String = x
+				.iter()
+				.copied()
+				.flat_map(std::ascii::escape_default)
+				.map(char::from)
+				.collect();
+
+			format!("\"{}\"", text)
+		}
+		_ => v.as_str().to_string(),
+	}
+}
+
+// the instructions of one function as `luac -l` prints them once encoded, the
+// names they use in a comment after, one naming something the function lacks
+// is left with a `?` for operands
+pub struct InstructionText {
+	encoder: Encoder,
+}
+
+impl InstructionText {
+	pub fn new(func: &Function<Block>) -> Self {
+		Self {
+			encoder: Encoder::new(func),
+		}
+	}
+
+	pub fn text(&self, ir: &IR) -> String {
+		let proto = match ir {
+			IR::Closure(_, name) => Some(name),
+			_ => None,
+		};
+		let name_list: Vec<&str> = ir
+			.upvalues()
+			.into_iter()
+			.chain(proto)
+			.chain(ir.constants())
+			.map(|v| &**v)
+			.collect();
+		let text = match self.encoder.encode(ir) {
+			Some(inst) => inst.to_string(),
+			None => format!("{:<9} ?", ir.opcode().name()),
+		};
+
+		if name_list.is_empty() {
+			text
+		} else {
+			format!("{}\t; {}", text, name_list.join(" "))
+		}
+	}
+}
+
+// the registers handed back from `a` on, up to the top when a call or vararg
+// right before left an unknown number there
+fn return_range(a: &Reg, group: &Group) -> Option<String> {
+	let Reg::R(x) = a;
+
+	match group {
+		Group::Many => Some(format!("r{}..top", x)),
+		Group::Exactly(0) => None,
+		Group::Exactly(1) => Some(reg(a)),
+		Group::Exactly(n) => Some(format!("r{}..r{}", x, u32::from(*x) + u32::from(*n) - 1)),
+	}
+}
+
+// the comparison a branch makes, its taken side being where it holds, a
+// `TESTSET` also saying where the tested value is copied to then
+pub fn condition_text(cond: &Condition) -> String {
+	match cond {
+		Condition::Test(a) => reg(a),
+		Condition::TestSet(a, b) => format!("{} (into {})", reg(b), reg(a)),
+		Condition::Eq(a, b) => format!("{} == {}", reg(a), reg(b)),
+		Condition::EqI(a, b) => format!("{} == {}", reg(a), b),
+		Condition::EqK(a, b) => format!("{} == {}", reg(a), b),
+		Condition::GeI(a, b) => format!("{} >= {}", reg(a), b),
+		Condition::GtI(a, b) => format!("{} > {}", reg(a), b),
+		Condition::Le(a, b) => format!("{} <= {}", reg(a), reg(b)),
+		Condition::LeI(a, b) => format!("{} <= {}", reg(a), b),
+		Condition::Lt(a, b) => format!("{} < {}", reg(a), reg(b)),
+		Condition::LtI(a, b) => format!("{} < {}", reg(a), b),
+	}
+}
+
+fn control(ctrl: &Control) -> String {
+	match ctrl {
+		Control::LFalseSkip(a, jump) => format!("{} := false, JMP -> {}", reg(a), target(jump)),
+		Control::Condition(cond, on_true, on_false) => format!(
+			"IF {} THEN {} ELSE {}",
+			condition_text(cond),
+			target(on_true),
+			target(on_false)
+		),
+		Control::Loop(Loop::Numeric(a), on_exit, on_body) => format!(
+			"FORLOOP {} -> {} ELSE {}",
+			reg(a),
+			target(on_body),
+			target(on_exit)
+		),
+		Control::Loop(Loop::NumericPrep(a), on_body, on_skip) => format!(
+			"FORPREP {} -> {} ELSE {}",
+			reg(a),
+			target(on_body),
+			target(on_skip)
+		),
+		Control::Loop(Loop::Iterator(a), on_exit, on_body) => format!(
+			"TFORLOOP {} -> {} ELSE {}",
+			reg(a),
+			target(on_body),
+			target(on_exit)
+		),
+		// the prep always jumps to the iterator call
+		Control::Loop(Loop::IteratorPrep(a), _, on_call) => {
+			format!("TFORPREP {} -> {}", reg(a), target(on_call))
+		}
+		// `k` closes upvalues and to-be-closed variables first, and a `c` set
+		// means a vararg frame with one fixed parameter less than that
+		Control::Return(a, group, c, k) => {
+			let mut note_list = Vec::new();
+
+			if *k {
+				note_list.push("closes upvalues".to_string());
+			}
+
+			if *c != 0 {
+				note_list.push(format!("vararg, {} fixed", c - 1));
+			}
+
+			let mut text = "RETURN".to_string();
+
+			if let Some(range) = return_range(a, group) {
+				write!(text, " {}", range).unwrap();
+			}
+
+			if !note_list.is_empty() {
+				write!(text, " ({})", note_list.join(", ")).unwrap();
+			}
+
+			text
+		}
+		Control::Return0(_) => "RETURN".to_string(),
+		Control::Return1(a) => format!("RETURN {}", reg(a)),
+		Control::Unconditional(jump) => format!("JMP -> {}", target(jump)),
+	}
+}
+
+// loop headers are marked with every back edge that reaches them
+fn loop_notes(func: &Function<Block>, entry: u32) -> HashMap<u32, Vec<String>> {
+	let mut loop_map: HashMap<u32, Vec<String>> = HashMap::new();
+
+	for found in find_loops(func, entry) {
+		let body: Vec<String> = found.member_list.iter().map(u32::to_string).collect();
+		let text = format!(
+			"back edge from Block {}, body {}",
+			found.back_edge,
+			body.join(" ")
+		);
+
+		loop_map.entry(found.header).or_default().push(text);
+	}
+
+	loop_map
+}
+
+// the source line of each instruction, worked out like `luaG_getfuncline`
+// does, a stripped chunk has none
+fn source_lines(info: &LineInfo) -> Vec<u32> {
+	let abs_map: HashMap<usize, u32> = info
+		.line_data
+		.iter()
+		.map(|&(pc, line)| (pc as usize, line))
+		.collect();
+	let mut line = info.line_defined.0;
+
+	info.line_offset
+		.iter()
+		.enumerate()
+		.map(|(pc, &offset)| {
+			line = match abs_map.get(&pc) {
+				Some(&abs) if offset == ABS_LINE_INFO => abs,
+				_ => (i64::from(line) + i64::from(offset)) as u32,
+			};
+
+			line
+		})
+		.collect()
+}
+
+// the source lines and where each block was laid out, which is all there is
+// to say about a block past the end of either
+struct LineMap {
+	line_list: Vec<u32>,
+	span_map: HashMap<u32, Range<usize>>,
+}
+
+impl LineMap {
+	fn new(func: &Function<Block>) -> Self {
+		let line_list = source_lines(&func.line_info);
+		let span_map = if line_list.is_empty() {
+			HashMap::new()
+		} else {
+			block_spans(func)
+		};
+
+		Self {
+			line_list,
+			span_map,
+		}
+	}
+
+	// `index` past the body is the edge, which a fallthrough emits nothing for
+	fn tag(&self, label: u32, index: usize) -> String {
+		let line = self
+			.span_map
+			.get(&label)
+			.map(|v| v.start + index)
+			.filter(|v| self.span_map[&label].contains(v))
+			.and_then(|v| self.line_list.get(v));
+
+		match line {
+			Some(line) => format!("[L{}]", line),
+			None => "[L?]".to_string(),
+		}
+	}
+}
+
+fn write_block(
+	out: &mut String,
+	blk: &Block,
+	loop_map: &HashMap<u32, Vec<String>>,
+	line_map: &LineMap,
+	inst_text: &InstructionText,
+) {
+	match loop_map.get(&blk.label) {
+		Some(list) => writeln!(
+			out,
+			"Block {}: ; loop header, {}",
+			blk.label,
+			list.join("; ")
+		),
+		None => writeln!(out, "Block {}:", blk.label),
+	}
+	.unwrap();
+
+	for (index, ir) in blk.body.iter().enumerate() {
+		let tag = line_map.tag(blk.label, index);
+
+		writeln!(out, "\t{} {}", tag, inst_text.text(ir)).unwrap();
+	}
+
+	let tag = line_map.tag(blk.label, blk.body.len());
+
+	writeln!(out, "\t{} {}", tag, control(&blk.edge)).unwrap();
+}
+
+// a term is either the opcode itself or something found in the operands, so
+// `call` leaves `TAILCALL` out, and case never matters
+fn is_match(text: &str, grep: &[String]) -> bool {
+	let text = text.to_lowercase();
+	let (name, rest) = text.split_once(' ').unwrap_or((&text, ""));
+
+	grep.iter().any(|v| name == v || rest.contains(v.as_str()))
+}
+
+fn has_match(blk: &Block, grep: &[String], inst_text: &InstructionText) -> bool {
+	blk.body
+		.iter()
+		.map(|v| inst_text.text(v))
+		.chain(std::iter::once(control(&blk.edge)))
+		.any(|v| is_match(&v, grep))
+}
+
+// with terms to look for, only the blocks with a match are kept along with
+// the header of their function
+// `entry` is where `func` itself starts, its children always start at block `0`
+fn write_function(
+	out: &mut String,
+	func: &Function<Block>,
+	name: &str,
+	grep: &[String],
+	entry: u32,
+) {
+	let inst_text = InstructionText::new(func);
+
+	if !grep.is_empty() {
+		let block_list: Vec<&Block> = func
+			.block_list
+			.iter()
+			.filter(|v| has_match(v, grep, &inst_text))
+			.collect();
+
+		if !block_list.is_empty() {
+			let loop_map = loop_notes(func, entry);
+			let line_map = LineMap::new(func);
+
+			if !out.is_empty() {
+				writeln!(out).unwrap();
+			}
+
+			writeln!(out, "function {}:", name).unwrap();
+
+			for blk in block_list {
+				writeln!(out).unwrap();
+				write_block(out, blk, &loop_map, &line_map, &inst_text);
+			}
+		}
+
+		for (name, child) in &func.child_list {
+			write_function(out, child, name, grep, 0);
+		}
+
+		return;
+	}
+
+	writeln!(out, "function {}:", name).unwrap();
+
+	for (name, data) in &func.value_list {
+		writeln!(out, "\t.const {} = {}", name, value(data)).unwrap();
+	}
+
+	for (name, _) in &func.upval_list {
+		writeln!(out, "\t.upval {}", name).unwrap();
+	}
+
+	for member_list in find_dead_loops(func, entry) {
+		let body: Vec<String> = member_list.iter().map(u32::to_string).collect();
+
+		writeln!(
+			out,
+			"\t; warning: blocks {} loop with no return",
+			body.join(" ")
+		)
+		.unwrap();
+	}
+
+	let loop_map = loop_notes(func, entry);
+	let line_map = LineMap::new(func);
+
+	for blk in &func.block_list {
+		writeln!(out).unwrap();
+		write_block(out, blk, &loop_map, &line_map, &inst_text);
+	}
+
+	for (name, child) in &func.child_list {
+		writeln!(out).unwrap();
+
+		write_function(out, child, name, grep, 0);
+	}
+}
+
+// every block, or with any `grep` terms only those where one of them matches,
+// the loops of `func` being found from the block labeled `entry`
+pub fn disasm_listing(func: &Function<Block>, grep: &[String], entry: u32) -> String {
+	let grep: Vec<String> = grep.iter().map(|v| v.to_lowercase()).collect();
+	let mut out = String::new();
+
+	write_function(&mut out, func, "main", &grep, entry);
+
+	out
+}
+
+// a single block of `func` itself, children are not searched
+pub fn block_listing(func: &Function<Block>, label: u32, entry: u32) -> Option<String> {
+	let blk = func.block_list.iter().find(|v| v.label == label)?;
+	let mut out = String::new();
+
+	write_block(
+		&mut out,
+		blk,
+		&loop_notes(func, entry),
+		&LineMap::new(func),
+		&InstructionText::new(func),
+	);
+
+	Some(out)
+}
+
+fn value_tag(v: &Value) -> &'static str {
+	match v {
+		Value::Nil => "nil",
+		Value::False | Value::True => "boolean",
+		Value::Integer(_) => "integer",
+		Value::Number(_) => "number",
+		Value::NoString => "nostring",
+		Value::String(_) | Value::Bytes(_) => "string",
+	}
+}
+
+fn write_constants(out: &mut String, func: &Function<Block>, path: &str, recursive: bool) {
+	if !out.is_empty() {
+		writeln!(out).unwrap();
+	}
+
+	writeln!(
+		out,
+		"function {}:",
+		if path.is_empty() { "main" } else { path }
+	)
+	.unwrap();
+
+	for (index, (_, data)) in func.value_list.iter().enumerate() {
+		writeln!(out, "\t{}\t{}\t{}", index, value_tag(data), value(data)).unwrap();
+	}
+
+	if !recursive {
+		return;
+	}
+
+	for (index, (_, child)) in func.child_list.iter().enumerate() {
+		let child_path = if path.is_empty() {
+			index.to_string()
+		} else {
+			format!("{}.{}", path, index)
+		};
+
+		write_constants(out, child, &child_path, recursive);
+	}
+}
+
+// the constant pool by index, type and value, with `recursive` the pools of
+// nested protos follow under the path `--proto` would take to them
+pub fn dump_constants(func: &Function<Block>, recursive: bool) -> String {
+	let mut out = String::new();
+
+	write_constants(&mut out, func, "", recursive);
+
+	out
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::disassemble;
+
+	const VSECURE: &[u8] = include_bytes!("../../tests/fixtures/vsecure.luac");
+	const DIAMOND: &str = include_str!("../../tests/fixtures/diamond.ron");
+
+	// instructions read as `luac -l` has them, the names after in a comment,
+	// while the edge keeps its own notation
+	#[test]
+	fn block_reads_like_luac() {
+		let func = disassemble(VSECURE).unwrap();
+		let text = block_listing(&func, 0, 0).unwrap();
+		let line_list: Vec<&str> = text.lines().collect();
+
+		assert_eq!(line_list[1], "\t[L1] VARARGPREP 0");
+		assert_eq!(
+			line_list[2],
+			"\t[L1] SETTABUP  0 0 1k\t; uEnv_1 v_1 vThisFileWasG_1"
+		);
+		assert!(line_list.contains(&"\t[L9] GETTABUP  4 0 6\t; uEnv_1 vPairs_1"));
+		assert!(line_list.contains(&"\t[L9] CALL      4 2 5"));
+		assert_eq!(line_list.last(), Some(&"\t[L9] TFORPREP r4 -> Block 10"));
+	}
+
+	// a name the function lacks leaves the operands out instead of panicking
+	#[test]
+	fn unknown_name_is_marked() {
+		let func = disassemble(VSECURE).unwrap();
+		let inst_text = InstructionText::new(&func);
+		let ir = IR::LoadK(Reg::R(0), "vMissing".into());
+
+		assert_eq!(inst_text.text(&ir), "LOADK     ?\t; vMissing");
+	}
+
+	// blocks 1 and 2 loop, entered at 2 from block 0, so the header is
+	// whichever of the two the entry reaches first
+	#[test]
+	fn loops_follow_entry() {
+		let edge = |v| Control::Unconditional(Target::Label(v));
+		let mut func: Function<Block> = ron::from_str(DIAMOND).unwrap();
+
+		func.block_list = vec![
+			Block::new(0, Vec::new(), edge(2)),
+			Block::new(1, Vec::new(), edge(2)),
+			Block::new(
+				2,
+				Vec::new(),
+				Control::Condition(
+					Condition::Test(Reg::R(0)),
+					Target::Label(1),
+					Target::Label(3),
+				),
+			),
+			Block::new(3, Vec::new(), Control::Return0(Reg::R(0))),
+		];
+
+		let text = disasm_listing(&func, &[], 0);
+
+		assert!(text.contains("Block 2: ; loop header, back edge from Block 1, body 1 2"));
+
+		let text = disasm_listing(&func, &[], 1);
+
+		assert!(text.contains("Block 1: ; loop header, back edge from Block 2, body 1 2"));
+		assert!(!text.contains("Block 2: ;"));
+	}
+}
@@ -1,97 +1,371 @@
 use super::common::{
-	types::{AbsLine, Constant, Inst, Proto, LUA_DATA, LUA_INT, LUA_MAGIC, LUA_NUM},
+	types::{
+		AbsLine, Constant, Inst, Proto, LUA_DATA, LUA_FORMAT, LUA_INT, LUA_NUM, LUA_SIGNATURE,
+		LUA_VERSION,
+	},
 	varint::load_unsigned,
 };
-use crate::common::{
-	loader::{verify_size_of, Serde},
-	types::{Instruction, Integer, Local, Number, Res, Upvalue, Value},
+use crate::{
+	common::{
+		loader::{load_instruction_as, load_integer_as, load_number_as, Serde},
+		types::{Depth, Instruction, Layout, Local, Res, Upvalue, Value},
+	},
+	lua53::types::LUA_VERSION as LUA53_VERSION,
 };
 use nom::{
-	bytes::complete::{tag, take},
-	combinator::{map, map_res, verify},
+	bytes::complete::take,
+	combinator::{map, map_res},
+	error::{context, ErrorKind, VerboseError, VerboseErrorKind},
 	multi::length_count,
 	number::complete::u8,
 };
-use std::convert::TryFrom;
+use std::{
+	convert::TryFrom,
+	fmt,
+	mem::size_of,
+	panic::{catch_unwind, AssertUnwindSafe},
+};
+
+pub const CONSTANT_TAG: &str = "constant tag";
+pub const PROTO_NESTING: &str = "proto nesting";
+
+// as deep as Lua's own parser lets functions nest, anything past it would
+// only run the loader out of stack
+pub const MAX_NESTING: usize = 200;
+
+#[derive(Debug)]
+pub enum LoadError {
+	BadSignature,
+	UnsupportedVersion(u8),
+	TruncatedInput { offset: usize },
+	BadConstantTag(u8),
+	Malformed { offset: usize },
+	Untranslatable(&'static str),
+	BadHeader { field: &'static str, value: u8 },
+	TrailingData { len: usize },
+	TooDeep { offset: usize },
+	BadIndex { kind: &'static str, index: u32 },
+	EmptyBlock { label: u32 },
+	Panicked(String),
+}
+
+impl LoadError {
+	// the innermost error tells us where parsing stopped, and any context
+	// attached on the way out tells us what we were trying to read there
+	pub fn from_nom(data: &[u8], err: nom::Err<VerboseError<&[u8]>>) -> Self {
+		let list = match err {
+			nom::Err::Error(e) | nom::Err::Failure(e) => e.errors,
+			nom::Err::Incomplete(_) => return Self::TruncatedInput { offset: data.len() },
+		};
+
+		let (rest, kind) = match list.first() {
+			Some(v) => v,
+			None => return Self::Malformed { offset: 0 },
+		};
+
+		let offset = data.len() - rest.len();
+		let has_context = |ctx| {
+			list.iter()
+				.any(|v| matches!(v.1, VerboseErrorKind::Context(c) if c == ctx))
+		};
+
+		if has_context(PROTO_NESTING) {
+			return Self::TooDeep { offset };
+		}
+
+		if rest.is_empty() || matches!(kind, VerboseErrorKind::Nom(ErrorKind::Eof)) {
+			return Self::TruncatedInput { offset };
+		}
+
+		if has_context(CONSTANT_TAG) {
+			Self::BadConstantTag(rest[0])
+		} else {
+			Self::Malformed { offset }
+		}
+	}
+}
+
+impl fmt::Display for LoadError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Self::BadSignature => write!(f, "missing Lua signature, not a bytecode file"),
+			Self::UnsupportedVersion(v) => {
+				write!(f, "unsupported Lua version {}.{}", v >> 4, v & 0xF)
+			}
+			Self::TruncatedInput { offset } => write!(f, "input truncated at offset {}", offset),
+			Self::BadConstantTag(v) => write!(f, "invalid constant tag {:#04x}", v),
+			Self::Malformed { offset } => write!(f, "malformed bytecode at offset {}", offset),
+			Self::BadHeader { field, value } => {
+				write!(f, "unexpected {} {:#04x} in header", field, value)
+			}
+			Self::Untranslatable(why) => write!(f, "cannot translate to Lua 5.4, {}", why),
+			Self::TrailingData { len } => write!(f, "{} trailing bytes after the chunk", len),
+			Self::TooDeep { offset } => {
+				write!(f, "protos nested past {} at offset {}", MAX_NESTING, offset)
+			}
+			Self::BadIndex { kind, index } => write!(f, "no {} at index {}", kind, index),
+			Self::EmptyBlock { label } => write!(f, "block {} has no instructions", label),
+			Self::Panicked(why) => write!(f, "panicked while loading, {}", why),
+		}
+	}
+}
+
+impl std::error::Error for LoadError {}
+
+fn read_version(input: &[u8]) -> Result<u8, LoadError> {
+	let len = LUA_SIGNATURE.len();
+
+	if input.len() < len {
+		return if LUA_SIGNATURE.starts_with(input) {
+			Err(LoadError::TruncatedInput {
+				offset: input.len(),
+			})
+		} else {
+			Err(LoadError::BadSignature)
+		};
+	}
+
+	if &input[..len] != LUA_SIGNATURE {
+		return Err(LoadError::BadSignature);
+	}
+
+	match input.get(len) {
+		Some(&v) => Ok(v),
+		None => Err(LoadError::TruncatedInput { offset: len }),
+	}
+}
+
+// checked ahead of parsing so that a wrong file or version is
+// reported as such rather than as a generic parse failure
+pub fn check_signature(input: &[u8], version: u8) -> Result<(), LoadError> {
+	match read_version(input)? {
+		v if v == version => Ok(()),
+		v => Err(LoadError::UnsupportedVersion(v)),
+	}
+}
+
+pub struct LuaHeader {
+	pub version: u8,
+	pub format: u8,
+	// only 5.3 records these two
+	pub size_int: Option<u8>,
+	pub size_size_t: Option<u8>,
+	pub size_instruction: u8,
+	pub layout: Layout,
+	// where the header ends and the main function upvalue count starts
+	pub len: usize,
+}
+
+impl fmt::Display for LuaHeader {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		let endianness = if self.layout.big_endian {
+			"big"
+		} else {
+			"little"
+		};
+
+		writeln!(f, "version: {}.{}", self.version >> 4, self.version & 0xF)?;
+		writeln!(f, "format: {}", self.format)?;
+		writeln!(f, "endianness: {}", endianness)?;
+
+		if let Some(size) = self.size_int {
+			writeln!(f, "int size: {}", size)?;
+		}
+
+		if let Some(size) = self.size_size_t {
+			writeln!(f, "size_t size: {}", size)?;
+		}
+
+		writeln!(f, "instruction size: {}", self.size_instruction)?;
+		writeln!(f, "integer size: {}", self.layout.size_integer)?;
+		writeln!(f, "number size: {}", self.layout.size_number)
+	}
+}
 
-fn verify_lua_header(input: &[u8]) -> Res<()> {
-	let (input, _) = tag(LUA_MAGIC)(input)?;
-	let (input, _) = tag(LUA_DATA)(input)?;
-	let (input, _) = verify_size_of::<Instruction>(input)?;
-	let (input, _) = verify_size_of::<Integer>(input)?;
-	let (input, _) = verify_size_of::<Number>(input)?;
-	let (input, _) = verify(Integer::deser, |&v| v == LUA_INT)(input)?;
-	let (input, _) = verify(Number::deser, |&v| v == LUA_NUM)(input)?;
+// the whole header of a 5.3 or 5.4 chunk, with the byte order taken from
+// whichever way round `LUAC_INT` reads correctly
+pub fn parse_header(input: &[u8]) -> Result<LuaHeader, LoadError> {
+	let version = read_version(input)?;
 
-	Ok((input, ()))
+	if version != LUA_VERSION && version != LUA53_VERSION {
+		return Err(LoadError::UnsupportedVersion(version));
+	}
+
+	let mut offset = LUA_SIGNATURE.len() + 1;
+	let mut next = |field: &'static str, allowed: &[u8]| {
+		let value = *input
+			.get(offset)
+			.ok_or(LoadError::TruncatedInput { offset })?;
+
+		if allowed.contains(&value) {
+			offset += 1;
+
+			Ok(value)
+		} else {
+			Err(LoadError::BadHeader { field, value })
+		}
+	};
+
+	let format = next("format", &[LUA_FORMAT])?;
+
+	for &v in LUA_DATA {
+		next("LUAC_DATA byte", &[v])?;
+	}
+
+	let (size_int, size_size_t) = if version == LUA53_VERSION {
+		let size_int = next("size of int", &[4])?;
+		let size_size_t = next("size of size_t", &[4, 8])?;
+
+		(Some(size_int), Some(size_size_t))
+	} else {
+		(None, None)
+	};
+
+	let size_instruction = next("size of Instruction", &[size_of::<Instruction>() as u8])?;
+	let size_integer = next("size of lua_Integer", &[4, 8])?;
+	let size_number = next("size of lua_Number", &[4, 8])?;
+
+	let little = Layout {
+		big_endian: false,
+		size_integer,
+		size_number,
+	};
+	let big = Layout {
+		big_endian: true,
+		..little
+	};
+
+	let rest = &input[offset..];
+	let layout = [little, big]
+		.iter()
+		.copied()
+		.find(|&v| matches!(load_integer_as(v)(rest), Ok((_, LUA_INT))));
+	let layout = match layout {
+		Some(layout) => layout,
+		None if rest.len() < size_integer.into() => {
+			return Err(LoadError::TruncatedInput {
+				offset: input.len(),
+			})
+		}
+		None => {
+			return Err(LoadError::BadHeader {
+				field: "LUAC_INT byte",
+				value: rest[0],
+			})
+		}
+	};
+
+	let offset = offset + usize::from(size_integer);
+	let rest = &input[offset..];
+
+	match load_number_as(layout)(rest) {
+		Ok((_, v)) if v == LUA_NUM => {}
+		Ok(_) => {
+			return Err(LoadError::BadHeader {
+				field: "LUAC_NUM byte",
+				value: rest[0],
+			})
+		}
+		Err(_) => {
+			return Err(LoadError::TruncatedInput {
+				offset: input.len(),
+			})
+		}
+	}
+
+	Ok(LuaHeader {
+		version,
+		format,
+		size_int,
+		size_size_t,
+		size_instruction,
+		layout,
+		len: offset + usize::from(size_number),
+	})
+}
+
+// a failure rather than an error, nothing else could be tried in its place
+pub fn load_depth(depth: Depth, input: &[u8]) -> Res<'_, Depth> {
+	match depth.child() {
+		Ok(depth) => Ok((input, depth)),
+		Err(_) => Err(nom::Err::Failure(VerboseError {
+			errors: vec![(input, VerboseErrorKind::Context(PROTO_NESTING))],
+		})),
+	}
 }
 
-fn load_t<T>(input: &[u8]) -> Res<T>
+fn load_t<T>(input: &[u8]) -> Res<'_, T>
 where
 	T: TryFrom<u64>,
 {
 	map_res(load_unsigned, T::try_from)(input)
 }
 
-fn load_string_opt(input: &[u8]) -> Res<Option<String>> {
+// the size is a varint counting one past the length, NULs are just bytes
+fn load_bytes_opt(input: &[u8]) -> Res<'_, Option<&[u8]>> {
 	let (input, len) = load_t::<u32>(input)?;
 
 	if len == 0 {
 		return Ok((input, None));
 	}
 
-	let (input, inner) = take(len - 1)(input)?;
-	let value = String::from_utf8_lossy(inner).to_string();
+	map(take(len - 1), Some)(input)
+}
 
-	Ok((input, Some(value)))
+// debug names are only ever shown, so anything but UTF-8 is replaced
+fn load_string_opt(input: &[u8]) -> Res<'_, Option<String>> {
+	map(load_bytes_opt, |s| {
+		s.map(|v| String::from_utf8_lossy(v).to_string())
+	})(input)
 }
 
-fn load_string(input: &[u8]) -> Res<Value> {
-	map(load_string_opt, |s| match s {
-		Some(s) => Value::String(s),
+fn load_string(input: &[u8]) -> Res<'_, Value> {
+	map(load_bytes_opt, |s| match s {
+		Some(s) => Value::from_bytes(s),
 		None => Value::NoString,
 	})(input)
 }
 
-fn load_list<T, F>(func: F) -> impl Fn(&[u8]) -> Res<Vec<T>>
+fn load_list<T, F>(func: F) -> impl Fn(&[u8]) -> Res<'_, Vec<T>>
 where
-	F: Fn(&[u8]) -> Res<T> + Copy,
+	F: Fn(&[u8]) -> Res<'_, T> + Copy,
 {
 	move |input| length_count(load_t::<u32>, func)(input)
 }
 
-fn load_instruction(input: &[u8]) -> Res<Inst> {
-	map(Instruction::deser, |inner| Inst { inner })(input)
+fn load_instruction(layout: Layout, input: &[u8]) -> Res<'_, Inst> {
+	map(load_instruction_as(layout), |inner| Inst { inner })(input)
 }
 
-fn load_constant(input: &[u8]) -> Res<Value> {
-	let (input, tag) = map_res(u8, Constant::try_from)(input)?;
+fn load_constant(layout: Layout, input: &[u8]) -> Res<'_, Value> {
+	let (input, tag) = context(CONSTANT_TAG, map_res(u8, Constant::try_from))(input)?;
 	let (input, value) = match tag {
 		Constant::Nil => (input, Value::Nil),
 		Constant::False => (input, Value::False),
 		Constant::True => (input, Value::True),
-		Constant::Integer => map(Integer::deser, Value::Integer)(input)?,
-		Constant::Number => map(Number::deser, Value::Number)(input)?,
+		Constant::Integer => map(load_integer_as(layout), Value::Integer)(input)?,
+		Constant::Number => map(load_number_as(layout), Value::Number)(input)?,
 		Constant::ShortString | Constant::LongString => load_string(input)?,
 	};
 
 	Ok((input, value))
 }
 
-fn load_upvalue(input: &[u8]) -> Res<Upvalue> {
+fn load_upvalue(input: &[u8]) -> Res<'_, Upvalue> {
 	let (input, in_stack) = u8(input)?;
 	let (input, index) = u8(input)?;
-	let (input, _) = u8(input)?; // kind is unused
+	let (input, kind) = u8(input)?;
 	let result = Upvalue {
 		name: None,
 		in_stack: in_stack != 0,
 		index,
+		kind,
 	};
 
 	Ok((input, result))
 }
 
-fn load_abs_line_info(input: &[u8]) -> Res<AbsLine> {
+fn load_abs_line_info(input: &[u8]) -> Res<'_, AbsLine> {
 	let (input, pc) = load_t::<u32>(input)?;
 	let (input, line) = load_t::<u32>(input)?;
 	let result = AbsLine { pc, line };
@@ -99,7 +373,7 @@ fn load_abs_line_info(input: &[u8]) -> Res<AbsLine> {
 	Ok((input, result))
 }
 
-fn load_local(input: &[u8]) -> Res<Local> {
+fn load_local(input: &[u8]) -> Res<'_, Local> {
 	let (input, name) = load_string_opt(input)?;
 	let (input, start_pc) = load_t::<u32>(input)?;
 	let (input, end_pc) = load_t::<u32>(input)?;
@@ -112,7 +386,7 @@ fn load_local(input: &[u8]) -> Res<Local> {
 	Ok((input, result))
 }
 
-fn load_function(input: &[u8]) -> Res<Proto> {
+fn load_function(layout: Layout, depth: Depth, input: &[u8]) -> Res<'_, Proto> {
 	let (input, source) = load_string_opt(input)?;
 	let (input, line_defined) = load_t::<u32>(input)?;
 	let (input, last_line_defined) = load_t::<u32>(input)?;
@@ -123,10 +397,14 @@ fn load_function(input: &[u8]) -> Res<Proto> {
 	let (input, num_stack) = u8(input)?;
 
 	// essential
-	let (input, inst_list) = load_list(load_instruction)(input)?;
-	let (input, value_list) = load_list(load_constant)(input)?;
+	let (input, inst_list) = load_list(|i| load_instruction(layout, i))(input)?;
+	let (input, value_list) = load_list(|i| load_constant(layout, i))(input)?;
 	let (input, mut upval_list) = load_list(load_upvalue)(input)?;
-	let (input, child_list) = load_list(load_function)(input)?;
+	let (input, child_list) = load_list(|i| {
+		let (i, depth) = load_depth(depth, i)?;
+
+		load_function(layout, depth, i)
+	})(input)?;
 
 	// debug
 	let (input, rel_line_list) = load_list(i8::deser)(input)?;
@@ -139,6 +417,7 @@ fn load_function(input: &[u8]) -> Res<Proto> {
 	}
 
 	let result = Proto {
+		layout: Layout::default(),
 		source,
 		is_vararg,
 		num_stack,
@@ -157,9 +436,37 @@ fn load_function(input: &[u8]) -> Res<Proto> {
 	Ok((input, result))
 }
 
-pub fn load_lua_module(input: &[u8]) -> Res<Proto> {
-	let (input, _) = verify_lua_header(input)?;
+fn load_lua_chunk(layout: Layout, input: &[u8]) -> Res<'_, Proto> {
 	let (input, _) = u8(input)?; // upvalues :)?
+	let (input, mut proto) = load_function(layout, Depth::new(MAX_NESTING), input)?;
+
+	proto.layout = layout;
+
+	Ok((input, proto))
+}
+
+pub fn load_lua_module(input: &[u8]) -> Result<(&[u8], Proto), LoadError> {
+	check_signature(input, LUA_VERSION)?;
+
+	let header = parse_header(input)?;
+
+	load_lua_chunk(header.layout, &input[header.len..]).map_err(|e| LoadError::from_nom(input, e))
+}
+
+// a panic left anywhere in `f` comes back as an error like any other
+pub fn catch_panic<T>(f: impl FnOnce() -> Result<T, LoadError>) -> Result<T, LoadError> {
+	catch_unwind(AssertUnwindSafe(f)).unwrap_or_else(|e| {
+		let why = match e.downcast::<String>() {
+			Ok(v) => *v,
+			Err(e) => e.downcast_ref::<&str>().map_or("", |v| v).to_string(),
+		};
+
+		Err(LoadError::Panicked(why))
+	})
+}
 
-	load_function(input)
+// for fuzzing, where nothing should take the process down, what follows the
+// chunk is copied out so the input can go as soon as it is read
+pub fn try_load(input: &[u8]) -> Result<(Vec<u8>, Proto), LoadError> {
+	catch_panic(|| load_lua_module(input).map(|(rest, proto)| (rest.to_vec(), proto)))
 }
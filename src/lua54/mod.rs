@@ -1,5 +1,14 @@
+pub mod analysis;
 mod assembler;
 pub mod common;
-mod disassembler;
+pub mod diff;
+pub mod disassembler;
+pub mod dot;
 pub mod dumper;
+pub mod extract;
+pub mod format;
+pub mod listing;
 pub mod loader;
+pub mod stats;
+pub mod transform;
+pub mod validate;
@@ -0,0 +1,119 @@
+use super::{
+	analysis::dead_loops::find_dead_loops,
+	common::inst::{Block, Control, Loop, IR},
+};
+use crate::common::types::Function;
+use serde::Serialize;
+use std::{collections::HashMap, fmt};
+
+// the variant name out of the compact RON form, which is the mnemonic
+fn variant_name<T: Serialize>(data: &T) -> String {
+	let text = ron::ser::to_string(data).unwrap_or_default();
+	let end = text.find('(').unwrap_or(text.len());
+
+	text[..end].to_uppercase()
+}
+
+// edges are counted as the instruction ending the block would be, a plain
+// jump as `JMP` even when it ends up falling through
+fn edge_name(edge: &Control) -> String {
+	match edge {
+		Control::Condition(cond, ..) => variant_name(cond),
+		Control::Loop(Loop::Iterator(_), ..) => "TFORLOOP".to_string(),
+		Control::Loop(Loop::IteratorPrep(_), ..) => "TFORPREP".to_string(),
+		Control::Loop(Loop::Numeric(_), ..) => "FORLOOP".to_string(),
+		Control::Loop(Loop::NumericPrep(_), ..) => "FORPREP".to_string(),
+		Control::Unconditional(_) => "JMP".to_string(),
+		_ => variant_name(edge),
+	}
+}
+
+#[derive(Default)]
+pub struct Stats {
+	pub block_count: usize,
+	pub inst_count: usize,
+	pub proto_count: usize,
+	pub value_count: usize,
+	pub upval_count: usize,
+	pub opcode_map: HashMap<String, usize>,
+	// the proto path like `--proto` takes, and the blocks of each loop in it
+	// that never reaches a return
+	pub dead_loop_list: Vec<(String, Vec<u32>)>,
+}
+
+impl Stats {
+	// `entry` is where `func` itself starts, its children always start at
+	// block `0`
+	fn add_function(&mut self, func: &Function<Block>, path: &str, entry: u32) {
+		self.block_count += func.block_list.len();
+		self.value_count += func.value_list.len();
+		self.upval_count += func.upval_list.len();
+
+		let name_iter = func.instructions().map(variant_name::<IR>);
+		let edge_iter = func.block_list.iter().map(|v| edge_name(&v.edge));
+
+		for name in name_iter.chain(edge_iter) {
+			self.inst_count += 1;
+			*self.opcode_map.entry(name).or_default() += 1;
+		}
+
+		for member_list in find_dead_loops(func, entry) {
+			self.dead_loop_list.push((path.to_string(), member_list));
+		}
+
+		for (index, (_, child)) in func.child_list.iter().enumerate() {
+			let child_path = if path.is_empty() {
+				index.to_string()
+			} else {
+				format!("{}.{}", path, index)
+			};
+
+			self.proto_count += 1;
+			self.add_function(child, &child_path, 0);
+		}
+	}
+}
+
+// totals cover `func` and every proto nested in it, the loops of `func` being
+// found from the block labeled `entry`
+pub fn collect_stats(func: &Function<Block>, entry: u32) -> Stats {
+	let mut stats = Stats::default();
+
+	stats.add_function(func, "", entry);
+
+	stats
+}
+
+impl fmt::Display for Stats {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		writeln!(f, "blocks: {}", self.block_count)?;
+		writeln!(f, "instructions: {}", self.inst_count)?;
+		writeln!(f, "nested protos: {}", self.proto_count)?;
+		writeln!(f, "constants: {}", self.value_count)?;
+		writeln!(f, "upvalues: {}", self.upval_count)?;
+		writeln!(f, "opcodes:")?;
+
+		let mut opcode_list: Vec<(&String, &usize)> = self.opcode_map.iter().collect();
+
+		// most used first, ties by name so the output is stable
+		opcode_list.sort_by(|a, b| b.1.cmp(a.1).then_with(|| a.0.cmp(b.0)));
+
+		for (name, count) in opcode_list {
+			writeln!(f, "  {:<12} {}", name, count)?;
+		}
+
+		for (path, member_list) in &self.dead_loop_list {
+			let path = if path.is_empty() { "main" } else { path };
+			let body: Vec<String> = member_list.iter().map(u32::to_string).collect();
+
+			writeln!(
+				f,
+				"warning: {}: blocks {} loop with no return",
+				path,
+				body.join(" ")
+			)?;
+		}
+
+		Ok(())
+	}
+}
@@ -0,0 +1,45 @@
+use crate::{
+	common::types::Function,
+	lua54::common::inst::{Block, Control, Target},
+};
+use std::collections::HashMap;
+
+// a block that only ever jumps to a block nothing else refers to, counting
+// every label an edge mentions so layout hints keep their block too
+fn find_chain(block_list: &[Block]) -> Option<(usize, usize)> {
+	let mut ref_map: HashMap<u32, usize> = HashMap::new();
+	let index_map: HashMap<u32, usize> = block_list
+		.iter()
+		.enumerate()
+		.map(|(i, v)| (v.label, i))
+		.collect();
+
+	for label in block_list.iter().flat_map(Block::get_target_labels) {
+		*ref_map.entry(label).or_default() += 1;
+	}
+
+	block_list
+		.iter()
+		.enumerate()
+		.find_map(|(index, blk)| match blk.edge {
+			Control::Unconditional(Target::Label(to))
+				if to != blk.label && to != 0 && ref_map[&to] == 1 =>
+			{
+				index_map.get(&to).map(|&next| (index, next))
+			}
+			_ => None,
+		})
+}
+
+// merges straight line chains in this function only, the entry block is
+// never merged into another
+pub fn coalesce_blocks(func: &mut Function<Block>) {
+	while let Some((index, next)) = find_chain(&func.block_list) {
+		let index = if next < index { index - 1 } else { index };
+		let next = func.block_list.remove(next);
+		let blk = &mut func.block_list[index];
+
+		blk.body.extend(next.body);
+		blk.edge = next.edge;
+	}
+}
@@ -0,0 +1,115 @@
+use crate::{
+	common::types::Function,
+	lua54::{
+		analysis::liveness::liveness,
+		common::inst::{Block, IR},
+	},
+};
+use std::collections::BTreeSet;
+
+// only writes registers and can neither fail nor reach a metamethod, while
+// `NEWTABLE` and `LOADKX` are left alone since their `EXTRAARG` goes with them
+fn is_pure(ir: &IR) -> bool {
+	matches!(
+		ir,
+		IR::Move(..)
+			| IR::LoadI(..)
+			| IR::LoadF(..)
+			| IR::LoadK(..)
+			| IR::LoadFalse(..)
+			| IR::LoadTrue(..)
+			| IR::LoadNil(..)
+			| IR::GetUpval(..)
+			| IR::Not(..)
+			| IR::Closure(..)
+			| IR::Vararg(..)
+	)
+}
+
+// walks the body back from what is live out of it, dropping every pure
+// instruction none of whose registers are read before being written again
+fn drop_dead(blk: &mut Block, mut live: BTreeSet<u8>) -> bool {
+	let (def_list, use_list) = blk.edge.defs_uses();
+	let mut changed = false;
+	let mut index = blk.body.len();
+
+	for reg in def_list {
+		live.remove(&reg);
+	}
+
+	live.extend(use_list);
+
+	while index != 0 {
+		index -= 1;
+
+		let (def_list, use_list) = blk.body[index].defs_uses();
+
+		if is_pure(&blk.body[index]) && !def_list.iter().any(|v| live.contains(v)) {
+			blk.body.remove(index);
+			changed = true;
+			continue;
+		}
+
+		for reg in def_list {
+			live.remove(&reg);
+		}
+
+		live.extend(use_list);
+	}
+
+	changed
+}
+
+// in this function only, until dropping one store leaves no other dead, the
+// debug info is left as it is like for every pass that changes bodies
+pub fn eliminate_dead_stores(func: &mut Function<Block>) {
+	loop {
+		let mut live_map = liveness(func);
+		let mut changed = false;
+
+		for blk in &mut func.block_list {
+			let live = live_map.remove(&blk.label).unwrap_or_default();
+
+			changed |= drop_dead(blk, live.live_out);
+		}
+
+		if !changed {
+			break;
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{common::types::Value, lua54::common::inst::Reg};
+
+	const DIAMOND: &str = include_str!("../../../tests/fixtures/diamond.ron");
+
+	#[test]
+	fn overwritten_load_is_dropped() {
+		let mut func: Function<Block> = ron::from_str(DIAMOND).unwrap();
+
+		func.value_list = vec![
+			("vString_a".into(), Value::String("a".into())),
+			("vString_b".into(), Value::String("b".into())),
+		];
+		func.block_list[1].body = vec![
+			IR::LoadK(Reg::R(1), "vString_a".into()),
+			IR::LoadK(Reg::R(1), "vString_b".into()),
+		];
+
+		eliminate_dead_stores(&mut func);
+
+		match &func.block_list[1].body[..] {
+			[IR::LoadK(Reg::R(1), name)] => assert_eq!(&**name, "vString_b"),
+			_ => panic!("the dead `LoadK` is still there"),
+		}
+
+		// the other side has no dead store to drop
+		assert!(matches!(
+			func.block_list[2].body[..],
+			[IR::LoadI(Reg::R(1), 2)]
+		));
+	}
+}
@@ -0,0 +1,148 @@
+use crate::{
+	common::types::{Function, Value},
+	lua54::common::inst::Block,
+};
+use std::{
+	collections::{hash_map::Entry, HashMap},
+	sync::Arc,
+};
+
+// what makes two constants one, a float only ever equal to one with the same
+// bits so `1` and `1.0` or `0.0` and `-0.0` stay apart like they are in Lua
+#[derive(PartialEq, Eq, Hash)]
+enum Key {
+	Nil,
+	False,
+	True,
+	Integer(i64),
+	Number(u64),
+	NoString,
+	String(String),
+	Bytes(Vec<u8>),
+}
+
+impl From<&Value> for Key {
+	fn from(value: &Value) -> Self {
+		match value {
+			Value::Nil => Key::Nil,
+			Value::False => Key::False,
+			Value::True => Key::True,
+			Value::Integer(v) => Key::Integer(*v),
+			Value::Number(v) => Key::Number(v.to_bits()),
+			Value::NoString => Key::NoString,
+			Value::String(v) => Key::String(v.clone()),
+			Value::Bytes(v) => Key::Bytes(v.clone()),
+		}
+	}
+}
+
+// the first of equal constants stays where it is and everything naming a
+// later one names it instead, each child has a pool of its own to go over
+pub fn dedup_constants(func: &mut Function<Block>) {
+	let mut first_map: HashMap<Key, Arc<str>> = HashMap::new();
+	let mut rename_map: HashMap<Arc<str>, Arc<str>> = HashMap::new();
+
+	func.value_list
+		.retain(|(name, value)| match first_map.entry(Key::from(value)) {
+			Entry::Vacant(entry) => {
+				entry.insert(Arc::clone(name));
+				true
+			}
+			Entry::Occupied(entry) => {
+				rename_map.insert(Arc::clone(name), Arc::clone(entry.get()));
+				false
+			}
+		});
+
+	if rename_map.is_empty() {
+		return;
+	}
+
+	for blk in &mut func.block_list {
+		for name in blk.constants_mut() {
+			if let Some(first) = rename_map.get(name) {
+				*name = Arc::clone(first);
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{
+		assemble,
+		lua54::common::inst::{Condition, Control, Reg, RegOrK, Target, IR},
+	};
+
+	const DIAMOND: &str = include_str!("../../../tests/fixtures/diamond.ron");
+
+	// three equal strings and a pair of signed zeros, read from the body and
+	// an edge of more than one block
+	fn merged() -> Function<Block> {
+		let mut func: Function<Block> = ron::from_str(DIAMOND).unwrap();
+		let add = |name: &str, value| (Arc::from(name), value);
+
+		func.value_list = vec![
+			add("vX_1", Value::String("x".into())),
+			add("vZero_1", Value::Number(0.0)),
+			add("vX_2", Value::String("x".into())),
+			add("vZero_2", Value::Number(-0.0)),
+			add("vX_3", Value::String("x".into())),
+		];
+		func.block_list[0].edge = Control::Condition(
+			Condition::EqK(Reg::R(0), "vX_2".into()),
+			Target::Label(1),
+			Target::Label(2),
+		);
+		func.block_list[1].body = vec![
+			IR::LoadK(Reg::R(1), "vX_3".into()),
+			IR::GetField(Reg::R(1), Reg::R(0), "vX_2".into()),
+		];
+		func.block_list[2].body = vec![
+			IR::LoadK(Reg::R(1), "vZero_2".into()),
+			IR::SetField(Reg::R(0), "vX_3".into(), RegOrK::K("vZero_1".into())),
+		];
+
+		dedup_constants(&mut func);
+		func
+	}
+
+	fn read_list(func: &Function<Block>) -> Vec<&str> {
+		func.block_list
+			.iter()
+			.flat_map(|v| {
+				v.body
+					.iter()
+					.flat_map(IR::constants)
+					.chain(v.edge.constants())
+			})
+			.map(|v| &**v)
+			.collect()
+	}
+
+	#[test]
+	fn equal_strings_merge() {
+		let func = merged();
+
+		assert!(func
+			.value_list
+			.iter()
+			.all(|v| !v.0.starts_with("vX_") || &*v.0 == "vX_1"));
+		assert_eq!(
+			read_list(&func),
+			["vX_1", "vX_1", "vX_1", "vZero_2", "vX_1", "vZero_1"]
+		);
+		assert!(assemble(func).is_ok());
+	}
+
+	// `-0.0` has other bits than `0.0`, so it is not the same constant
+	#[test]
+	fn signed_zeros_stay_apart() {
+		let func = merged();
+		let name_list: Vec<&str> = func.value_list.iter().map(|v| &*v.0).collect();
+
+		assert_eq!(name_list, ["vX_1", "vZero_1", "vZero_2"]);
+		assert!(matches!(func.value_list[2].1, Value::Number(v) if v.is_sign_negative()));
+	}
+}
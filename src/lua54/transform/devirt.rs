@@ -0,0 +1,416 @@
+use super::{
+	fold::{find_value, fold_constants, known_load, Known},
+	thread::{thread_jumps, trailing_loads},
+};
+use crate::{
+	common::types::{Depth, DepthError, Function, Named, Value},
+	lua54::{
+		common::inst::{Block, Condition, Control, Group, Loop, Reg, Target, IR},
+		validate::{edge_registers, ir_registers},
+	},
+};
+use log::{debug, info};
+use std::{
+	cmp::Reverse,
+	collections::{HashMap, HashSet, VecDeque},
+	ops::RangeInclusive,
+};
+
+// fewer cases than this is only an `if` chain, not a dispatcher
+const MIN_CASES: usize = 2;
+
+// the state machine vsecure routes control flow through, a chain of empty
+// blocks testing one register against integers where each match goes to a
+// case and the block past the last test takes whatever state is left
+pub struct DispatcherInfo {
+	pub label: u32,
+	pub state_reg: u8,
+	pub case_list: Vec<(i64, u32)>,
+	pub default: u32,
+	pub chain_list: Vec<u32>,
+}
+
+struct StateTest {
+	reg: u8,
+	state: i64,
+	on_true: u32,
+	on_false: u32,
+}
+
+fn state_test(value_list: &Named<Value>, blk: &Block) -> Option<StateTest> {
+	if !blk.body.is_empty() {
+		return None;
+	}
+
+	let (cond, on_true, on_false) = match &blk.edge {
+		Control::Condition(cond, Target::Label(on_true), Target::Label(on_false)) => {
+			(cond, *on_true, *on_false)
+		}
+		_ => return None,
+	};
+	let (reg, state) = match cond {
+		Condition::EqI(Reg::R(reg), state) => (*reg, i64::from(*state)),
+		Condition::EqK(Reg::R(reg), name) => match find_value(value_list, name)? {
+			Value::Integer(state) => (*reg, *state),
+			_ => return None,
+		},
+		_ => return None,
+	};
+
+	Some(StateTest {
+		reg,
+		state,
+		on_true,
+		on_false,
+	})
+}
+
+fn state_test_map(func: &Function<Block>) -> HashMap<u32, StateTest> {
+	func.block_list
+		.iter()
+		.filter_map(|blk| Some((blk.label, state_test(&func.value_list, blk)?)))
+		.collect()
+}
+
+// the longest chain of state tests in `func` itself, one starts at a test no
+// other test of the same register falls through to
+pub fn detect_vsecure(func: &Function<Block>) -> Option<DispatcherInfo> {
+	let test_map = state_test_map(func);
+	let inner_set: HashSet<u32> = test_map
+		.values()
+		.filter(|v| matches!(test_map.get(&v.on_false), Some(next) if next.reg == v.reg))
+		.map(|v| v.on_false)
+		.collect();
+
+	test_map
+		.iter()
+		.filter(|(label, _)| !inner_set.contains(label))
+		.map(|(&label, head)| {
+			let mut case_list = Vec::new();
+			let mut chain_list = Vec::new();
+			let mut current = label;
+
+			while let Some(test) = test_map.get(&current).filter(|v| v.reg == head.reg) {
+				if chain_list.contains(&current) {
+					break;
+				}
+
+				case_list.push((test.state, test.on_true));
+				chain_list.push(current);
+				current = test.on_false;
+			}
+
+			DispatcherInfo {
+				label,
+				state_reg: head.reg,
+				case_list,
+				default: current,
+				chain_list,
+			}
+		})
+		.filter(|v| v.case_list.len() >= MIN_CASES)
+		.max_by_key(|v| (v.case_list.len(), Reverse(v.label)))
+}
+
+// a jump into the dispatcher made with the state already loaded goes straight
+// to its case, the load stays behind since nothing says the register is dead
+fn undo_dispatcher(func: &mut Function<Block>, info: &DispatcherInfo) -> usize {
+	let test_map = state_test_map(func);
+	let mut count = 0;
+
+	for blk in &mut func.block_list {
+		let known_list = trailing_loads(&func.value_list, &blk.body);
+		let state = match known_list.iter().find(|v| v.0 == info.state_reg) {
+			Some((_, Known::Integer(state))) => *state,
+			_ => continue,
+		};
+		let to = match &mut blk.edge {
+			Control::Unconditional(Target::Label(to)) => to,
+			_ => continue,
+		};
+
+		let mut seen = HashSet::new();
+		let mut current = *to;
+
+		while let Some(test) = test_map.get(&current).filter(|v| v.reg == info.state_reg) {
+			if !seen.insert(current) {
+				break;
+			}
+
+			current = if test.state == state {
+				test.on_true
+			} else {
+				test.on_false
+			};
+		}
+
+		if current != *to {
+			*to = current;
+			count += 1;
+		}
+	}
+
+	count
+}
+
+fn span(Reg::R(a): &Reg, len: u8) -> Option<RangeInclusive<u8>> {
+	let len = len.checked_sub(1)?;
+
+	Some(*a..=a.saturating_add(len))
+}
+
+// the registers an instruction reaches through a count rather than by name,
+// the open ended `Many` forms only read what the one before them just wrote
+fn ir_span(ir: &IR) -> Vec<RangeInclusive<u8>> {
+	let exactly = |group: &Group| match group {
+		Group::Many => 0,
+		Group::Exactly(n) => *n,
+	};
+
+	let span_list = match ir {
+		IR::LoadNil(a, n) => vec![span(a, n.saturating_add(1))],
+		IR::Concat(a, Reg::R(n), _) => vec![span(a, *n)],
+		IR::Call(a, arg, ret) | IR::TailCall(a, arg, ret) => {
+			vec![
+				span(a, exactly(arg).saturating_add(1)),
+				span(a, exactly(ret)),
+			]
+		}
+		IR::SetList(a, n, ..) => vec![span(a, n.saturating_add(1))],
+		IR::Vararg(a, ret) => vec![span(a, exactly(ret))],
+		IR::TForCall(a, n) => vec![span(a, n.saturating_add(4))],
+		_ => Vec::new(),
+	};
+
+	span_list.into_iter().flatten().collect()
+}
+
+fn edge_span(edge: &Control) -> Option<RangeInclusive<u8>> {
+	match edge {
+		Control::Loop(Loop::Iterator(a), ..) => span(a, 5),
+		Control::Loop(Loop::IteratorPrep(a) | Loop::Numeric(a) | Loop::NumericPrep(a), ..) => {
+			span(a, 4)
+		}
+		Control::Return(a, Group::Exactly(n), ..) => span(a, *n),
+		_ => None,
+	}
+}
+
+// once nothing reaches the dispatcher, its state register is dead when no
+// instruction but a load into it names it or reaches it through a count
+fn is_dead_state(func: &Function<Block>, info: &DispatcherInfo) -> bool {
+	let reg = info.state_reg;
+	let rest = || {
+		func.block_list
+			.iter()
+			.filter(|v| !info.chain_list.contains(&v.label))
+	};
+
+	let is_reached = rest()
+		.flat_map(Block::get_target_labels)
+		.any(|v| info.chain_list.contains(&v));
+
+	let is_used = |ir: &IR| {
+		let is_load = matches!(known_load(&func.value_list, ir), Some((to, _)) if to == reg);
+
+		!is_load
+			&& (ir_registers(ir).contains(&reg) || ir_span(ir).iter().any(|v| v.contains(&reg)))
+	};
+
+	let is_named = rest().any(|blk| {
+		blk.body.iter().any(is_used)
+			|| edge_registers(&blk.edge).contains(&reg)
+			|| edge_span(&blk.edge).is_some_and(|v| v.contains(&reg))
+	});
+
+	!is_reached && !is_named
+}
+
+// the loads the dispatcher read are dropped, which leaves the trampolines
+// empty for jump cleanup, and the register is given back to the stack when
+// it was the last one
+fn drop_state(func: &mut Function<Block>, info: &DispatcherInfo) {
+	let reg = info.state_reg;
+	let value_list = &func.value_list;
+
+	for blk in &mut func.block_list {
+		blk.body
+			.retain(|ir| !matches!(known_load(value_list, ir), Some((to, _)) if to == reg));
+	}
+
+	if u16::from(reg) + 1 == u16::from(func.stack_info.num_stack) {
+		func.stack_info.num_stack = reg;
+	}
+}
+
+// follows a chain of empty unconditional blocks to where it really lands
+fn resolve_jmp(map: &HashMap<u32, Block>, label: u32) -> u32 {
+	let mut seen = HashSet::new();
+	let mut label = label;
+
+	while let Some(blk) = map.get(&label) {
+		match blk.edge {
+			Control::Unconditional(Target::Label(next))
+				if blk.body.is_empty() && seen.insert(label) =>
+			{
+				label = next;
+			}
+			_ => break,
+		}
+	}
+
+	label
+}
+
+fn optimize_jmp(map: &mut HashMap<u32, Block>, node_id: u32) {
+	let mut visited = HashSet::new();
+	let mut queue = VecDeque::from([node_id]);
+
+	while let Some(node_id) = queue.pop_front() {
+		if !visited.insert(node_id) {
+			continue; // already visited
+		}
+
+		let target_list = match map.get(&node_id) {
+			Some(current_blk) => current_blk.get_target_labels(),
+			None => continue,
+		};
+
+		for target in target_list {
+			let target_id = resolve_jmp(map, target);
+			let current_blk = map.get_mut(&node_id).unwrap();
+
+			if target_id != target && current_blk.redirect_target(target, target_id) {
+				debug!("fake jmp from {} to {} to {}", node_id, target, target_id);
+
+				queue.push_back(target_id);
+			} else {
+				queue.push_back(target);
+			}
+		}
+	}
+}
+
+// empty jumps that nothing points at anymore are dropped, the entry always stays
+fn drop_orphan_jmp(map: &mut HashMap<u32, Block>) {
+	loop {
+		let target_set: HashSet<u32> = map.values().flat_map(Block::get_target_labels).collect();
+		let len = map.len();
+
+		map.retain(|label, blk| {
+			*label == 0
+				|| target_set.contains(label)
+				|| !blk.body.is_empty()
+				|| !blk.is_unconditionnal()
+		});
+
+		if map.len() == len {
+			break;
+		}
+	}
+}
+
+// anything the entry block can't reach is dead code left over from folding
+pub fn prune_unreachable(func: &mut Function<Block>) {
+	let block_map: HashMap<u32, &Block> = func.block_list.iter().map(|v| (v.label, v)).collect();
+	let mut reachable = HashSet::new();
+	let mut queue = VecDeque::from([0]);
+
+	while let Some(label) = queue.pop_front() {
+		if !reachable.insert(label) {
+			continue;
+		}
+
+		if let Some(blk) = block_map.get(&label) {
+			queue.extend(blk.get_target_labels());
+		}
+	}
+
+	let len = func.block_list.len();
+
+	func.block_list.retain(|v| reachable.contains(&v.label));
+
+	debug!("removed {} unreachable blocks", len - func.block_list.len());
+}
+
+// undoes what vsecure does to the control flow of `func` and its children
+pub fn devirtualize(func: &mut Function<Block>, depth: Depth) -> Result<(), DepthError> {
+	for data in &mut func.child_list {
+		devirtualize(&mut data.1, depth.child()?)?;
+	}
+
+	// we need to start from node root and process until the rest of the program from target to
+	// target
+	// we want to map labels to blocks for faster access !
+	fold_constants(func);
+	thread_jumps(func);
+
+	match detect_vsecure(func) {
+		Some(info) => {
+			let count = undo_dispatcher(func, &info);
+
+			info!(
+				"dispatcher at {} on r{} with {} cases, {} jumps resolved",
+				info.label,
+				info.state_reg,
+				info.case_list.len(),
+				count
+			);
+
+			if is_dead_state(func, &info) {
+				drop_state(func, &info);
+			}
+		}
+		None => info!("no dispatcher found"),
+	}
+
+	let order: Vec<u32> = func.block_list.iter().map(|v| v.label).collect();
+	let mut block_map: HashMap<u32, Block> =
+		func.block_list.drain(..).map(|v| (v.label, v)).collect();
+
+	optimize_jmp(&mut block_map, 0);
+	drop_orphan_jmp(&mut block_map);
+
+	// keep the original layout for whatever is left
+	func.block_list = order
+		.into_iter()
+		.filter_map(|label| block_map.remove(&label))
+		.collect();
+
+	prune_unreachable(func);
+
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::lua54::transform::flatten::flatten;
+	use ron::ser::{to_string_pretty, PrettyConfig};
+
+	// a parameter picks one of two loads that meet again at the return
+	const DIAMOND: &str = include_str!("../../../tests/fixtures/diamond.ron");
+
+	fn devirt_ron(mut func: Function<Block>) -> String {
+		devirtualize(&mut func, Depth::new(1)).unwrap();
+
+		to_string_pretty(&func, PrettyConfig::new()).unwrap()
+	}
+
+	#[test]
+	fn devirt_undoes_flatten() {
+		let func: Function<Block> = ron::from_str(DIAMOND).unwrap();
+		let mut flat = func.clone();
+
+		flatten(&mut flat);
+
+		assert!(detect_vsecure(&flat).is_some());
+		assert!(flat.block_list.len() > func.block_list.len());
+
+		let text = devirt_ron(flat);
+
+		assert_eq!(text, to_string_pretty(&func, PrettyConfig::new()).unwrap());
+		assert_eq!(text, devirt_ron(func));
+		assert!(detect_vsecure(&ron::from_str(&text).unwrap()).is_none());
+	}
+}
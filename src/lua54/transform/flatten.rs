@@ -0,0 +1,143 @@
+use crate::{
+	common::types::Function,
+	lua54::common::inst::{Block, BlockBuilder, Condition, Control, Target, IR},
+};
+use std::collections::HashMap;
+
+// `EqI` compares against a signed byte, so only this many states fit the dispatcher
+const MAX_STATE: usize = 128;
+
+struct Flattener {
+	state_reg: u8,
+	dispatch: u32,
+	next_label: u32,
+	state_map: HashMap<u32, i8>,
+	case_list: Vec<u32>,
+	extra_list: Vec<Block>,
+}
+
+impl Flattener {
+	fn state_of(&mut self, label: u32) -> Option<i8> {
+		if let Some(state) = self.state_map.get(&label) {
+			return Some(*state);
+		}
+
+		if self.case_list.len() >= MAX_STATE {
+			return None;
+		}
+
+		let state = self.case_list.len() as i8;
+
+		self.state_map.insert(label, state);
+		self.case_list.push(label);
+
+		Some(state)
+	}
+
+	fn new_label(&mut self) -> u32 {
+		let label = self.next_label;
+
+		self.next_label += 1;
+
+		label
+	}
+
+	// a jump straight to `target` becomes a trampoline that sets the state
+	fn trampoline(&mut self, target: &mut Target) {
+		if let Target::Label(label) = target {
+			if let Some(state) = self.state_of(*label) {
+				let tramp = self.new_label();
+				let blk = BlockBuilder::new()
+					.label(tramp)
+					.push_instr(IR::LoadI(self.state_reg.into(), state.into()))
+					.edge(Control::Unconditional(Target::Label(self.dispatch)))
+					.build();
+
+				self.extra_list.push(blk);
+
+				*label = tramp;
+			}
+		}
+	}
+
+	fn rewrite(&mut self, blk: &mut Block) {
+		match &mut blk.edge {
+			Control::Unconditional(Target::Label(label)) => {
+				if let Some(state) = self.state_of(*label) {
+					blk.body
+						.push(IR::LoadI(self.state_reg.into(), state.into()));
+
+					*label = self.dispatch;
+				}
+			}
+			Control::Condition(_, on_true, on_false) => {
+				self.trampoline(on_true);
+				self.trampoline(on_false);
+			}
+			Control::LFalseSkip(_, target) => {
+				self.trampoline(target);
+			}
+			// loops have layout rules of their own and stay as they are
+			_ => {}
+		}
+	}
+
+	// one `EqI` test per state, the last one needs no test at all
+	fn dispatcher(&mut self) -> Vec<Block> {
+		let len = self.case_list.len();
+		let mut label_list = vec![self.dispatch];
+
+		label_list.extend((1..len).map(|_| self.new_label()));
+
+		(0..len)
+			.map(|index| {
+				let case = Target::Label(self.case_list[index]);
+				let edge = if index + 1 == len {
+					Control::Unconditional(case)
+				} else {
+					let cond = Condition::EqI(self.state_reg.into(), index as i8);
+
+					Control::Condition(cond, case, Target::Label(label_list[index + 1]))
+				};
+
+				BlockBuilder::new()
+					.label(label_list[index])
+					.edge(edge)
+					.build()
+			})
+			.collect()
+	}
+}
+
+// routes the jumps of a function through a single dispatcher driven by a state
+// register taken past the top of the stack, the entry block stays in place
+pub fn flatten(func: &mut Function<Block>) {
+	let state_reg = func.stack_info.num_stack;
+	let dispatch = match func.block_list.iter().map(|v| v.label).max() {
+		Some(label) if state_reg != u8::MAX => label + 1,
+		_ => return,
+	};
+
+	let mut flat = Flattener {
+		state_reg,
+		dispatch,
+		next_label: dispatch + 1,
+		state_map: HashMap::new(),
+		case_list: Vec::new(),
+		extra_list: Vec::new(),
+	};
+
+	for blk in &mut func.block_list {
+		flat.rewrite(blk);
+	}
+
+	if flat.case_list.is_empty() {
+		return;
+	}
+
+	let dispatcher = flat.dispatcher();
+
+	func.stack_info.num_stack += 1;
+	func.block_list.extend(dispatcher);
+	func.block_list.append(&mut flat.extra_list);
+}
@@ -0,0 +1,199 @@
+use crate::{
+	common::{
+		types::{Function, Named, Value},
+		unique::name_added_value,
+	},
+	lua54::common::inst::{Block, MetaMethod, Reg, IR},
+};
+use std::{mem::discriminant, sync::Arc};
+
+pub enum Known {
+	Integer(i64),
+	String(String),
+}
+
+impl Known {
+	fn into_value(self) -> Value {
+		match self {
+			Known::Integer(v) => Value::Integer(v),
+			Known::String(v) => Value::String(v),
+		}
+	}
+
+	fn is_same(&self, value: &Value) -> bool {
+		match (self, value) {
+			(Known::Integer(a), Value::Integer(b)) => a == b,
+			(Known::String(a), Value::String(b)) => a == b,
+			_ => false,
+		}
+	}
+}
+
+pub fn find_value<'a>(value_list: &'a Named<Value>, name: &str) -> Option<&'a Value> {
+	value_list
+		.iter()
+		.find(|v| v.0.as_ref() == name)
+		.map(|v| &v.1)
+}
+
+// the register a constant load writes and what it loads
+pub fn known_load(value_list: &Named<Value>, ir: &IR) -> Option<(u8, Known)> {
+	match ir {
+		IR::LoadI(Reg::R(a), v) => Some((*a, Known::Integer((*v).into()))),
+		IR::LoadK(Reg::R(a), k) => match find_value(value_list, k)? {
+			Value::Integer(v) => Some((*a, Known::Integer(*v))),
+			Value::String(v) => Some((*a, Known::String(v.clone()))),
+			_ => None,
+		},
+		_ => None,
+	}
+}
+
+// integer arithmetic wraps in Lua so folding never fails
+fn fold_arith(ir: &IR, lhs: &Known, rhs: &Known) -> Option<Known> {
+	let (lhs, rhs) = match (lhs, rhs) {
+		(Known::Integer(lhs), Known::Integer(rhs)) => (*lhs, *rhs),
+		_ => return None,
+	};
+
+	let value = match ir {
+		IR::Add(..) => lhs.wrapping_add(rhs),
+		IR::Sub(..) => lhs.wrapping_sub(rhs),
+		IR::Mul(..) => lhs.wrapping_mul(rhs),
+		_ => return None,
+	};
+
+	Some(Known::Integer(value))
+}
+
+fn arith_operands(ir: &IR) -> Option<(u8, u8, u8, MetaMethod)> {
+	match *ir {
+		IR::Add(Reg::R(a), Reg::R(b), Reg::R(c)) => Some((a, b, c, MetaMethod::Add)),
+		IR::Sub(Reg::R(a), Reg::R(b), Reg::R(c)) => Some((a, b, c, MetaMethod::Sub)),
+		IR::Mul(Reg::R(a), Reg::R(b), Reg::R(c)) => Some((a, b, c, MetaMethod::Mul)),
+		_ => None,
+	}
+}
+
+struct Fold {
+	start: usize,
+	end: usize,
+	dest: u8,
+	value: Known,
+}
+
+// `LOADK; LOADK; ADD; MMBIN`, the metamethod fallback goes as well since
+// after the fold nothing would skip over it anymore
+fn match_arith(value_list: &Named<Value>, body: &[IR], index: usize) -> Option<Fold> {
+	let (a, b, c, event) = arith_operands(&body[index])?;
+	let start = index.checked_sub(2)?;
+
+	match body.get(index + 1)? {
+		IR::MmBin(Reg::R(x), Reg::R(y), mm)
+			if (*x, *y) == (b, c) && discriminant(mm) == discriminant(&event) => {}
+		_ => return None,
+	}
+
+	let first = known_load(value_list, &body[start])?;
+	let second = known_load(value_list, &body[start + 1])?;
+
+	if first.0 == second.0 {
+		return None;
+	}
+
+	let lookup = |reg: u8| {
+		if first.0 == reg {
+			Some(&first.1)
+		} else if second.0 == reg {
+			Some(&second.1)
+		} else {
+			None
+		}
+	};
+	let value = fold_arith(&body[index], lookup(b)?, lookup(c)?)?;
+
+	Some(Fold {
+		start,
+		end: index + 2,
+		dest: a,
+		value,
+	})
+}
+
+// `CONCAT` of `n` registers that were all just loaded with strings
+fn match_concat(value_list: &Named<Value>, body: &[IR], index: usize) -> Option<Fold> {
+	let (a, n) = match body[index] {
+		IR::Concat(Reg::R(a), Reg::R(n), _) => (a, usize::from(n)),
+		_ => return None,
+	};
+	let start = index.checked_sub(n)?;
+	let mut part_list = vec![None; n];
+
+	for ir in &body[start..index] {
+		let (reg, value) = known_load(value_list, ir)?;
+		let slot = usize::from(reg.checked_sub(a)?);
+
+		match (part_list.get_mut(slot)?, value) {
+			(part @ None, Known::String(v)) => *part = Some(v),
+			_ => return None,
+		}
+	}
+
+	let value = part_list.into_iter().collect::<Option<String>>()?;
+
+	Some(Fold {
+		start,
+		end: index + 1,
+		dest: a,
+		value: Known::String(value),
+	})
+}
+
+fn add_value(value_list: &mut Named<Value>, value: Known) -> Arc<str> {
+	if let Some((name, _)) = value_list.iter().find(|v| value.is_same(&v.1)) {
+		return Arc::clone(name);
+	}
+
+	let value = value.into_value();
+	let name = name_added_value(value_list, &value);
+
+	value_list.push((Arc::clone(&name), value));
+
+	name
+}
+
+fn fold_once(value_list: &mut Named<Value>, body: &mut Vec<IR>) -> bool {
+	let found = (0..body.len()).find_map(|index| {
+		match_arith(value_list, body, index).or_else(|| match_concat(value_list, body, index))
+	});
+
+	let fold = match found {
+		Some(fold) => fold,
+		None => return false,
+	};
+
+	// only loads into the destination are known to be dead, anything else
+	// could still be read by a later instruction
+	let dest = fold.dest;
+	let name = add_value(value_list, fold.value);
+	let mut kept: Vec<IR> = body
+		.drain(fold.start..fold.end)
+		.filter(|ir| match known_load(value_list, ir) {
+			Some((reg, _)) => reg != dest,
+			None => false,
+		})
+		.collect();
+
+	kept.push(IR::LoadK(dest.into(), name));
+	body.splice(fold.start..fold.start, kept);
+
+	true
+}
+
+// folds constant integer `ADD`/`SUB`/`MUL` and string `CONCAT` in this function
+// only, running it again on the result changes nothing
+pub fn fold_constants(func: &mut Function<Block>) {
+	for blk in &mut func.block_list {
+		while fold_once(&mut func.value_list, &mut blk.body) {}
+	}
+}
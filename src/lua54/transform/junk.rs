@@ -0,0 +1,98 @@
+use crate::{
+	common::types::Function,
+	lua54::common::inst::{Block, Reg, IR},
+};
+use rand::{rngs::StdRng, Rng};
+
+// whatever follows these has nothing riding on it, unlike after arithmetic
+// with its `MMBIN`, open results of calls and varargs, or an `EXTRAARG`
+fn is_settled(ir: &IR) -> bool {
+	matches!(
+		ir,
+		IR::Move(..)
+			| IR::LoadI(..)
+			| IR::LoadF(..)
+			| IR::LoadK(..)
+			| IR::LoadFalse(..)
+			| IR::LoadTrue(..)
+			| IR::LoadNil(..)
+			| IR::GetUpval(..)
+			| IR::SetUpval(..)
+			| IR::GetTabUp(..)
+			| IR::GetTable(..)
+			| IR::GetI(..)
+			| IR::GetField(..)
+			| IR::SetTabUp(..)
+			| IR::SetTable(..)
+			| IR::SetI(..)
+			| IR::SetField(..)
+			| IR::MmBin(..)
+			| IR::MmBinI(..)
+			| IR::MmBinK(..)
+			| IR::Unm(..)
+			| IR::Bnot(..)
+			| IR::Not(..)
+			| IR::Len(..)
+			| IR::Concat(..)
+			| IR::Close(..)
+			| IR::Closure(..)
+	)
+}
+
+// `TFORPREP` lands right on its `TFORCALL` and `TFORLOOP` has to follow it,
+// while until `VARARGPREP` runs the extra arguments still sit in the registers
+fn is_free_spot(body: &[IR], index: usize) -> bool {
+	let after = !matches!(body.get(index), Some(IR::TForCall(..) | IR::VarargPrep(..)));
+	let before = match index.checked_sub(1) {
+		Some(prev) => is_settled(&body[prev]),
+		None => true,
+	};
+
+	before && after
+}
+
+fn junk_sequence(reg: u8, rng: &mut StdRng) -> Vec<IR> {
+	let r = || Reg::R(reg);
+	let value = rng.gen_range(-0xFFFF..=0xFFFF);
+
+	match rng.gen_range(0..3) {
+		0 => vec![IR::LoadNil(r(), 0), IR::LoadI(r(), value)],
+		1 => vec![
+			IR::LoadI(r(), value),
+			IR::Not(r(), r()),
+			IR::LoadNil(r(), 0),
+		],
+		_ => vec![IR::LoadTrue(r()), IR::Move(r(), r()), IR::LoadFalse(r())],
+	}
+}
+
+// drops dead sequences over a register taken past the top of the stack into
+// about half of the blocks of a function, nothing ever reads it back
+pub fn insert_junk(func: &mut Function<Block>, rng: &mut StdRng) {
+	let junk_reg = func.stack_info.num_stack;
+	let mut inserted = false;
+
+	if junk_reg == u8::MAX {
+		return;
+	}
+
+	for blk in &mut func.block_list {
+		let spot_list: Vec<usize> = (0..=blk.body.len())
+			.filter(|&i| is_free_spot(&blk.body, i))
+			.collect();
+
+		if spot_list.is_empty() || !rng.gen_bool(0.5) {
+			continue;
+		}
+
+		let index = spot_list[rng.gen_range(0..spot_list.len())];
+		let junk = junk_sequence(junk_reg, rng);
+
+		blk.body.splice(index..index, junk);
+		inserted = true;
+	}
+
+	if inserted {
+		func.stack_info.num_stack += 1;
+	}
+}
@@ -0,0 +1,19 @@
+pub mod coalesce;
+pub mod dce;
+pub mod dedup;
+pub mod devirt;
+pub mod flatten;
+pub mod fold;
+pub mod junk;
+pub mod mutate;
+pub mod opaque;
+pub mod peephole;
+pub mod pipeline;
+pub mod renumber;
+pub mod split;
+pub mod strip;
+pub mod thread;
+pub mod topo;
+pub mod unroll;
+pub mod upvals;
+pub mod values;
@@ -0,0 +1,308 @@
+use super::{
+	coalesce::coalesce_blocks,
+	dce::eliminate_dead_stores,
+	dedup::dedup_constants,
+	flatten::flatten,
+	junk::insert_junk,
+	opaque::insert_opaque,
+	peephole::peephole,
+	renumber::renumber_blocks,
+	split::split_at_calls,
+	topo::topo_sort,
+	unroll::unroll_loops,
+	upvals::{permute_upvals, sort_upvals},
+	values::{permute_values, sort_values},
+};
+use crate::{
+	common::types::{Depth, DepthError, Function, Layout, Named},
+	lua54::common::inst::{Block, Control, Loop, Target},
+};
+use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+use rayon::prelude::*;
+use std::{collections::HashMap, fmt, hash::Hash, sync::Arc};
+
+// below this many children spreading them over threads costs more than it saves
+const PARALLEL_THRESHOLD: usize = 4;
+
+#[derive(Clone)]
+pub enum Mutation {
+	Random,
+	Sorted,
+	Flatten,
+	Coalesce,
+	TopoSort,
+	OpaquePredicate,
+	JunkInsert,
+	Renumber,
+	DeadStore,
+	Peephole,
+	PermuteUpvals,
+	Dedup,
+	Unroll { factor: usize },
+	SplitCalls,
+}
+
+// how many of a list was moved, out of how many there were before and after,
+// anything added or removed counts as neither moved nor kept in place
+#[derive(Default)]
+pub struct Moved {
+	pub moved: usize,
+	pub before: usize,
+	pub after: usize,
+}
+
+impl Moved {
+	fn new<K: Eq + Hash>(before: &[K], after: &[K]) -> Self {
+		let index_map: HashMap<&K, usize> = before.iter().enumerate().map(|v| (v.1, v.0)).collect();
+		let moved = after
+			.iter()
+			.enumerate()
+			.filter(|(index, key)| index_map.get(key).is_some_and(|v| v != index))
+			.count();
+
+		Self {
+			moved,
+			before: before.len(),
+			after: after.len(),
+		}
+	}
+
+	fn is_unchanged(&self) -> bool {
+		self.moved == 0 && self.before == self.after
+	}
+}
+
+// one function's lists compared before and after every step, the path being
+// what `--proto` would have taken to pick it out of the input
+pub struct FunctionReport {
+	pub path: String,
+	pub block: Moved,
+	pub child: Moved,
+	pub upval: Moved,
+	pub value: Moved,
+}
+
+#[derive(Default)]
+pub struct MutationReport {
+	pub function_list: Vec<FunctionReport>,
+}
+
+// blocks go by label and the rest by name, which every step keeps unique
+fn labels(func: &Function<Block>) -> Vec<u32> {
+	func.block_list.iter().map(|v| v.label).collect()
+}
+
+fn names<T>(list: &Named<T>) -> Vec<Arc<str>> {
+	list.iter().map(|v| Arc::clone(&v.0)).collect()
+}
+
+// runs every step on the children first, then on `func` itself, each child
+// gets a generator seeded off `rng` in order so threads never change the result
+pub fn mutate(
+	func: &mut Function<Block>,
+	opt: &[Mutation],
+	rng: &mut StdRng,
+	depth: Depth,
+) -> Result<MutationReport, DepthError> {
+	let layout = func.layout;
+
+	mutate_as(func, opt, rng, depth, layout)
+}
+
+// only the main function holds the layout of the chunk, so it is handed down
+// to the children along with everything else
+fn mutate_as(
+	func: &mut Function<Block>,
+	opt: &[Mutation],
+	rng: &mut StdRng,
+	depth: Depth,
+	layout: Layout,
+) -> Result<MutationReport, DepthError> {
+	let seed_list: Vec<u64> = func.child_list.iter().map(|_| rng.gen()).collect();
+	let mutate_child = |(data, seed): (&mut (Arc<str>, Function<Block>), u64)| {
+		mutate_as(
+			&mut data.1,
+			opt,
+			&mut StdRng::seed_from_u64(seed),
+			depth.child()?,
+			layout,
+		)
+	};
+
+	let child_report_list: Vec<MutationReport> = if func.child_list.len() < PARALLEL_THRESHOLD {
+		func.child_list
+			.iter_mut()
+			.zip(seed_list)
+			.map(mutate_child)
+			.collect::<Result<_, _>>()?
+	} else {
+		func.child_list
+			.par_iter_mut()
+			.zip(seed_list)
+			.map(mutate_child)
+			.collect::<Result<_, _>>()?
+	};
+
+	let block = labels(func);
+	let child = names(&func.child_list);
+	let upval = names(&func.upval_list);
+	let value = names(&func.value_list);
+
+	for step in opt.iter() {
+		match step {
+			Mutation::Random => {
+				func.block_list.shuffle(rng);
+				keep_iterator_order(&mut func.block_list);
+				func.child_list.shuffle(rng);
+				permute_upvals(func, rng, depth.is_main());
+				permute_values(func, rng);
+			}
+			Mutation::Sorted => {
+				func.block_list.sort_by_key(|v| v.label);
+				func.child_list.sort_by_key(|v| Arc::clone(&v.0));
+				sort_upvals(func, depth.is_main());
+				sort_values(func);
+			}
+			Mutation::Flatten => {
+				flatten(func);
+			}
+			Mutation::Coalesce => {
+				coalesce_blocks(func);
+			}
+			Mutation::TopoSort => {
+				topo_sort(func);
+				keep_iterator_order(&mut func.block_list);
+			}
+			Mutation::OpaquePredicate => {
+				insert_opaque(func, layout.size_integer, rng);
+			}
+			Mutation::JunkInsert => {
+				insert_junk(func, rng);
+			}
+			Mutation::Renumber => {
+				renumber_blocks(func);
+			}
+			Mutation::DeadStore => {
+				eliminate_dead_stores(func);
+			}
+			Mutation::Peephole => {
+				peephole(func);
+			}
+			Mutation::PermuteUpvals => {
+				permute_upvals(func, rng, depth.is_main());
+			}
+			Mutation::Dedup => {
+				dedup_constants(func);
+			}
+			Mutation::Unroll { factor } => {
+				unroll_loops(func, *factor);
+			}
+			Mutation::SplitCalls => {
+				split_at_calls(func);
+			}
+		}
+	}
+
+	let mut report = MutationReport::default();
+
+	report.function_list.push(FunctionReport {
+		path: String::new(),
+		block: Moved::new(&block, &labels(func)),
+		child: Moved::new(&child, &names(&func.child_list)),
+		upval: Moved::new(&upval, &names(&func.upval_list)),
+		value: Moved::new(&value, &names(&func.value_list)),
+	});
+
+	for (index, child_report) in child_report_list.into_iter().enumerate() {
+		for mut entry in child_report.function_list {
+			entry.path = if entry.path.is_empty() {
+				index.to_string()
+			} else {
+				format!("{}.{}", index, entry.path)
+			};
+
+			report.function_list.push(entry);
+		}
+	}
+
+	Ok(report)
+}
+
+impl fmt::Display for Moved {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "{} of {} moved", self.moved, self.before)?;
+
+		if self.before != self.after {
+			write!(f, ", {} now", self.after)?;
+		}
+
+		Ok(())
+	}
+}
+
+// a line per function a step changed anything in
+impl fmt::Display for MutationReport {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		let changed_list: Vec<&FunctionReport> = self
+			.function_list
+			.iter()
+			.filter(|v| {
+				let list = [&v.block, &v.child, &v.upval, &v.value];
+
+				!list.iter().all(|v| v.is_unchanged())
+			})
+			.collect();
+
+		if changed_list.is_empty() {
+			return writeln!(f, "no function changed");
+		}
+
+		for entry in changed_list {
+			let path = if entry.path.is_empty() {
+				"main"
+			} else {
+				&entry.path
+			};
+
+			writeln!(
+				f,
+				"{}: blocks {}, children {}, upvalues {}, constants {}",
+				path, entry.block, entry.child, entry.upval, entry.value
+			)?;
+		}
+
+		Ok(())
+	}
+}
+
+// `TForPrep` can only jump forward onto its `TForCall` and `TForLoop` can only
+// jump back to the loop body, so a shuffled call block is moved past both
+fn keep_iterator_order(block_list: &mut Vec<Block>) {
+	let mut index = 0;
+
+	while index < block_list.len() {
+		let label = block_list[index].label;
+		let last = match block_list[index].edge {
+			Control::Loop(Loop::Iterator(_), _, Target::Label(body)) => {
+				let prep = block_list.iter().position(|v| {
+					matches!(v.edge, Control::Loop(Loop::IteratorPrep(_), _, Target::Label(id)) if id == label)
+				});
+				let body = block_list
+					.iter()
+					.position(|v| v.label == body && v.label != label);
+
+				prep.max(body)
+			}
+			_ => None,
+		};
+
+		match last {
+			Some(last) if last > index => {
+				let blk = block_list.remove(index);
+
+				block_list.insert(last, blk);
+			}
+			_ => index += 1,
+		}
+	}
+}
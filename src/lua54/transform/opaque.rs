@@ -0,0 +1,125 @@
+use crate::{
+	common::types::Function,
+	lua54::common::inst::{Block, BlockBuilder, Condition, Control, MetaMethod, Target, IR},
+};
+use rand::{rngs::StdRng, Rng};
+
+// `LOADI` takes a signed 17 bit immediate, which squares without wrapping
+// around to a negative number on 8 byte integers, while 4 byte ones only have
+// room for the square of the largest seed below `sqrt(i32::MAX)`
+fn max_seed(size_integer: u8) -> i32 {
+	match size_integer {
+		4 => 46340,
+		_ => (1 << 16) - 1,
+	}
+}
+
+// wraps about half of the plain jumps of a function in `x * x >= 0` over a
+// register taken past the top of the stack, the false arm goes to a junk block
+// that jumps somewhere random but is never taken, `size_integer` is the chunk's
+// since nested functions don't keep a layout of their own
+pub fn insert_opaque(func: &mut Function<Block>, size_integer: u8, rng: &mut StdRng) {
+	let junk_reg = func.stack_info.num_stack;
+	let max_seed = max_seed(size_integer);
+	let label_list: Vec<u32> = func.block_list.iter().map(|v| v.label).collect();
+	let mut next_label = match label_list.iter().max() {
+		Some(label) if junk_reg != u8::MAX => label + 1,
+		_ => return,
+	};
+
+	let mut extra_list = Vec::new();
+
+	for blk in &mut func.block_list {
+		let target = match &blk.edge {
+			Control::Unconditional(Target::Label(target)) if rng.gen_bool(0.5) => *target,
+			_ => continue,
+		};
+
+		let junk = next_label;
+		let seed = rng.gen_range(-max_seed..=max_seed);
+		let elsewhere = label_list[rng.gen_range(0..label_list.len())];
+		let cond = Condition::GeI(junk_reg.into(), 0);
+
+		next_label += 1;
+
+		blk.body.extend(vec![
+			IR::LoadI(junk_reg.into(), seed),
+			IR::Mul(junk_reg.into(), junk_reg.into(), junk_reg.into()),
+			IR::MmBin(junk_reg.into(), junk_reg.into(), MetaMethod::Mul),
+		]);
+		blk.edge = Control::Condition(cond, Target::Label(target), Target::Label(junk));
+
+		let blk = BlockBuilder::new()
+			.label(junk)
+			.push_instr(IR::LoadNil(junk_reg.into(), 0))
+			.edge(Control::Unconditional(Target::Label(elsewhere)))
+			.build();
+
+		extra_list.push(blk);
+	}
+
+	if extra_list.is_empty() {
+		return;
+	}
+
+	func.stack_info.num_stack += 1;
+	func.block_list.append(&mut extra_list);
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{
+		common::types::Depth,
+		lua54::{
+			common::inst::Reg,
+			transform::mutate::{mutate, Mutation},
+		},
+	};
+	use rand::SeedableRng;
+
+	const DIAMOND: &str = include_str!("../../../tests/fixtures/diamond.ron");
+
+	fn seeds(func: &Function<Block>) -> Vec<i64> {
+		let own = func
+			.block_list
+			.iter()
+			.flat_map(|v| v.body.iter())
+			.filter_map(|ir| match ir {
+				IR::LoadI(Reg::R(2), x) => Some(i64::from(*x)),
+				_ => None,
+			});
+
+		own.chain(func.child_list.iter().flat_map(|v| seeds(&v.1)))
+			.collect()
+	}
+
+	// with 4 byte integers every seed squared still fits, so the junk arm is
+	// never taken, and a nested function goes by the layout of the chunk
+	#[test]
+	fn seed_square_fits_32_bit_integers() {
+		let mut seed_list = Vec::new();
+
+		for seed in 0..200 {
+			let mut func: Function<Block> = ron::from_str(DIAMOND).unwrap();
+			let child = func.clone();
+
+			func.layout.size_integer = 4;
+			func.child_list.push(("fChild".into(), child));
+
+			mutate(
+				&mut func,
+				&[Mutation::OpaquePredicate],
+				&mut StdRng::seed_from_u64(seed),
+				Depth::new(1),
+			)
+			.unwrap();
+
+			assert!(func.child_list[0].1.layout.size_integer == 8);
+			seed_list.extend(seeds(&func));
+		}
+
+		assert!(!seed_list.is_empty());
+		assert!(seed_list.iter().all(|x| x * x <= i64::from(i32::MAX)));
+	}
+}
@@ -0,0 +1,142 @@
+use crate::{
+	common::types::Function,
+	lua54::{
+		analysis::liveness::liveness,
+		common::inst::{Block, Reg, IR},
+	},
+};
+use std::collections::BTreeSet;
+
+// the registers live right after each instruction of the body
+fn live_after(blk: &Block, mut live: BTreeSet<u8>) -> Vec<BTreeSet<u8>> {
+	let mut live_list = vec![BTreeSet::new(); blk.body.len()];
+	let (def_list, use_list) = blk.edge.defs_uses();
+
+	for reg in def_list {
+		live.remove(&reg);
+	}
+
+	live.extend(use_list);
+
+	for index in (0..blk.body.len()).rev() {
+		let (def_list, use_list) = blk.body[index].defs_uses();
+
+		live_list[index] = live.clone();
+
+		for reg in def_list {
+			live.remove(&reg);
+		}
+
+		live.extend(use_list);
+	}
+
+	live_list
+}
+
+// `MOVE a b; MOVE c a` is `MOVE c b` once `a` is dead after both, which can
+// leave `MOVE b b` behind to be dropped like any other self move
+fn collapse_moves(blk: &mut Block, live_out: BTreeSet<u8>) -> bool {
+	let mut live_list = live_after(blk, live_out);
+	let mut changed = false;
+	let mut index = 0;
+
+	while index < blk.body.len() {
+		if let IR::Move(Reg::R(a), Reg::R(b)) = blk.body[index] {
+			let next = match blk.body.get(index + 1) {
+				Some(IR::Move(Reg::R(c), Reg::R(d))) if *d == a => Some(*c),
+				_ => None,
+			};
+
+			if a == b {
+				blk.body.remove(index);
+				live_list.remove(index);
+				changed = true;
+				continue;
+			}
+
+			if let Some(c) = next.filter(|_| !live_list[index + 1].contains(&a)) {
+				blk.body[index + 1] = IR::Move(c.into(), b.into());
+				blk.body.remove(index);
+				live_list.remove(index);
+				changed = true;
+				continue;
+			}
+		}
+
+		index += 1;
+	}
+
+	changed
+}
+
+// in this function only, until no block has a chain left, dropping a copy
+// only ever makes fewer registers live so earlier choices stay sound
+pub fn peephole(func: &mut Function<Block>) {
+	loop {
+		let mut live_map = liveness(func);
+		let mut changed = false;
+
+		for blk in &mut func.block_list {
+			let live = live_map.remove(&blk.label).unwrap_or_default();
+
+			changed |= collapse_moves(blk, live.live_out);
+		}
+
+		if !changed {
+			break;
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const DIAMOND: &str = include_str!("../../../tests/fixtures/diamond.ron");
+
+	fn moves(x: &[(u8, u8)]) -> Vec<IR> {
+		x.iter()
+			.map(|&(a, b)| IR::Move(Reg::R(a), Reg::R(b)))
+			.collect()
+	}
+
+	#[test]
+	fn move_chain_collapses() {
+		let mut func: Function<Block> = ron::from_str(DIAMOND).unwrap();
+
+		// `r2` is dead past the pair, `r1` is what block 3 returns
+		func.block_list[1].body = moves(&[(2, 0), (1, 2)]);
+		func.block_list[2].body = moves(&[(0, 0), (1, 0)]);
+
+		peephole(&mut func);
+
+		assert!(matches!(
+			func.block_list[1].body[..],
+			[IR::Move(Reg::R(1), Reg::R(0))]
+		));
+		assert!(matches!(
+			func.block_list[2].body[..],
+			[IR::Move(Reg::R(1), Reg::R(0))]
+		));
+	}
+
+	#[test]
+	fn live_intermediate_is_kept() {
+		let mut func: Function<Block> = ron::from_str(DIAMOND).unwrap();
+		let mut body = moves(&[(2, 0), (1, 2)]);
+
+		body.push(IR::Add(Reg::R(1), Reg::R(1), Reg::R(2)));
+		func.block_list[1].body = body;
+
+		peephole(&mut func);
+
+		assert!(matches!(
+			func.block_list[1].body[..],
+			[
+				IR::Move(Reg::R(2), Reg::R(0)),
+				IR::Move(Reg::R(1), Reg::R(2)),
+				IR::Add(..)
+			]
+		));
+	}
+}
@@ -0,0 +1,89 @@
+use super::{
+	devirt::{devirtualize, prune_unreachable},
+	fold::fold_constants,
+	mutate::{mutate, Mutation},
+	strip::strip_debug,
+	thread::thread_jumps,
+};
+use crate::{
+	common::types::{Depth, DepthError, Function},
+	lua54::common::inst::Block,
+};
+use rand::rngs::StdRng;
+use std::str::FromStr;
+
+pub enum Pass {
+	Devirt,
+	Fold,
+	Thread,
+	Prune,
+	Strip,
+	Mutate(Mutation),
+}
+
+impl FromStr for Pass {
+	type Err = String;
+
+	fn from_str(name: &str) -> Result<Self, Self::Err> {
+		let pass = match name {
+			"devirt" => Pass::Devirt,
+			"fold" => Pass::Fold,
+			"thread" => Pass::Thread,
+			"prune" => Pass::Prune,
+			"strip" => Pass::Strip,
+			"randomize" => Pass::Mutate(Mutation::Random),
+			"sort" => Pass::Mutate(Mutation::Sorted),
+			"flatten" => Pass::Mutate(Mutation::Flatten),
+			"coalesce" => Pass::Mutate(Mutation::Coalesce),
+			"topo" => Pass::Mutate(Mutation::TopoSort),
+			"opaque" => Pass::Mutate(Mutation::OpaquePredicate),
+			"junk" => Pass::Mutate(Mutation::JunkInsert),
+			"renumber" => Pass::Mutate(Mutation::Renumber),
+			"dce" => Pass::Mutate(Mutation::DeadStore),
+			"peephole" => Pass::Mutate(Mutation::Peephole),
+			"permute-upvals" => Pass::Mutate(Mutation::PermuteUpvals),
+			"dedup" => Pass::Mutate(Mutation::Dedup),
+			"split-calls" => Pass::Mutate(Mutation::SplitCalls),
+			_ => return Err(format!("unknown pass `{}`", name)),
+		};
+
+		Ok(pass)
+	}
+}
+
+fn for_each_function(
+	func: &mut Function<Block>,
+	pass: fn(&mut Function<Block>),
+	depth: Depth,
+) -> Result<(), DepthError> {
+	for (_, child) in &mut func.child_list {
+		for_each_function(child, pass, depth.child()?)?;
+	}
+
+	pass(func);
+
+	Ok(())
+}
+
+// each pass covers `func` and all its children before the next one starts
+pub fn run_pipeline(
+	func: &mut Function<Block>,
+	pass_list: &[Pass],
+	rng: &mut StdRng,
+	depth: Depth,
+) -> Result<(), DepthError> {
+	for pass in pass_list {
+		match pass {
+			Pass::Devirt => devirtualize(func, depth)?,
+			Pass::Fold => for_each_function(func, fold_constants, depth)?,
+			Pass::Thread => for_each_function(func, thread_jumps, depth)?,
+			Pass::Prune => for_each_function(func, prune_unreachable, depth)?,
+			Pass::Strip => strip_debug(func, depth)?,
+			Pass::Mutate(step) => {
+				mutate(func, std::slice::from_ref(step), rng, depth)?;
+			}
+		}
+	}
+
+	Ok(())
+}
@@ -0,0 +1,68 @@
+use crate::{
+	common::types::{Depth, DepthError, Function},
+	lua54::{
+		analysis::postorder,
+		common::inst::{Block, Target},
+	},
+};
+use std::collections::HashMap;
+
+// labels `0..n` in reverse postorder from the entry, which keeps `0`, then
+// unreachable blocks in label order, the layout itself is left alone
+pub fn renumber_blocks(func: &mut Function<Block>) {
+	let succ_map: HashMap<u32, Vec<u32>> = func
+		.block_list
+		.iter()
+		.map(|v| {
+			let mut label_list = v.get_target_labels();
+
+			label_list.sort_unstable_by(|a, b| b.cmp(a));
+
+			(v.label, label_list)
+		})
+		.collect();
+
+	let mut order = postorder(&succ_map, 0);
+
+	order.reverse();
+
+	let mut rest: Vec<u32> = succ_map
+		.values()
+		.flatten()
+		.chain(succ_map.keys())
+		.copied()
+		.collect();
+
+	// a label nothing is defined for still gets one of its own
+	rest.sort_unstable();
+	rest.dedup();
+
+	let mut label_map: HashMap<u32, u32> = HashMap::new();
+
+	for label in order.into_iter().chain(rest) {
+		let next = label_map.len() as u32;
+
+		label_map.entry(label).or_insert(next);
+	}
+
+	for blk in &mut func.block_list {
+		blk.label = label_map[&blk.label];
+
+		for target in blk.targets_mut() {
+			if let Target::Label(label) = target {
+				*label = label_map[label];
+			}
+		}
+	}
+}
+
+// every proto is renumbered on its own
+pub fn canonicalize_labels(func: &mut Function<Block>, depth: Depth) -> Result<(), DepthError> {
+	renumber_blocks(func);
+
+	for (_, child) in &mut func.child_list {
+		canonicalize_labels(child, depth.child()?)?;
+	}
+
+	Ok(())
+}
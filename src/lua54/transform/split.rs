@@ -0,0 +1,45 @@
+use crate::{
+	common::types::Function,
+	lua54::common::inst::{Block, Control, Group, Target, IR},
+};
+
+// a call with open results is left where it is, whatever follows reads up to
+// the top it set and has to stay right behind it
+fn is_split_point(ir: &IR) -> bool {
+	matches!(
+		ir,
+		IR::Call(_, _, Group::Exactly(_)) | IR::TailCall(_, _, Group::Exactly(_))
+	)
+}
+
+// every call not already last in its body ends a block of its own, the rest
+// goes to a fresh block placed right behind so it is still fallen into
+pub fn split_at_calls(func: &mut Function<Block>) {
+	let mut next_label = match func.block_list.iter().map(|v| v.label).max() {
+		Some(label) => label + 1,
+		None => return,
+	};
+
+	let mut index = 0;
+
+	while index < func.block_list.len() {
+		let blk = &mut func.block_list[index];
+		let len = blk.body.len();
+		let split = blk.body.iter().position(is_split_point).map(|v| v + 1);
+
+		if let Some(split) = split.filter(|&v| v < len) {
+			let rest = blk.body.split_off(split);
+			let edge = std::mem::replace(
+				&mut blk.edge,
+				Control::Unconditional(Target::Label(next_label)),
+			);
+
+			func.block_list
+				.insert(index + 1, Block::new(next_label, rest, edge));
+
+			next_label += 1;
+		}
+
+		index += 1;
+	}
+}
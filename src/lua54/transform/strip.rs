@@ -0,0 +1,23 @@
+use crate::{
+	common::types::{Depth, DepthError, Function},
+	lua54::common::inst::Block,
+};
+
+// drops what `luac -s` drops: the source name, line info, locals and upvalue
+// names, here and in every child
+pub fn strip_debug(func: &mut Function<Block>, depth: Depth) -> Result<(), DepthError> {
+	func.source = None;
+	func.line_info.line_offset.clear();
+	func.line_info.line_data.clear();
+	func.local_list.clear();
+
+	for (_, upv) in &mut func.upval_list {
+		upv.name = None;
+	}
+
+	for (_, child) in &mut func.child_list {
+		strip_debug(child, depth.child()?)?;
+	}
+
+	Ok(())
+}
@@ -0,0 +1,99 @@
+use super::fold::{find_value, known_load, Known};
+use crate::{
+	common::types::{Function, Named, Value},
+	lua54::common::inst::{Block, Condition, Control, Reg, Target, IR},
+};
+use std::{cmp::Ordering, mem::replace};
+
+// only the loads ending a block are known at its edge, anything before them
+// could have been overwritten since
+pub(super) fn trailing_loads(value_list: &Named<Value>, body: &[IR]) -> Vec<(u8, Known)> {
+	let mut known_list: Vec<(u8, Known)> = Vec::new();
+
+	for ir in body.iter().rev() {
+		match known_load(value_list, ir) {
+			Some((reg, value)) => {
+				if known_list.iter().all(|v| v.0 != reg) {
+					known_list.push((reg, value));
+				}
+			}
+			None => break,
+		}
+	}
+
+	known_list
+}
+
+fn compare(lhs: &Known, rhs: &Known) -> Option<Ordering> {
+	match (lhs, rhs) {
+		(Known::Integer(a), Known::Integer(b)) => Some(a.cmp(b)),
+		_ => None,
+	}
+}
+
+fn compare_imm(lhs: &Known, rhs: i8) -> Option<Ordering> {
+	compare(lhs, &Known::Integer(rhs.into()))
+}
+
+// strings only ever order by locale and mixed types raise an error, so only
+// integer comparisons and equality have a known outcome
+fn evaluate(
+	value_list: &Named<Value>,
+	known_list: &[(u8, Known)],
+	cond: &Condition,
+) -> Option<bool> {
+	let lookup = |Reg::R(reg): &Reg| known_list.iter().find(|v| v.0 == *reg).map(|v| &v.1);
+
+	let result = match cond {
+		// both are always truthy
+		Condition::Test(a) => lookup(a).map(|_| true)?,
+		Condition::Eq(a, b) => match (lookup(a)?, lookup(b)?) {
+			(Known::Integer(a), Known::Integer(b)) => a == b,
+			(Known::String(a), Known::String(b)) => a == b,
+			_ => false,
+		},
+		Condition::EqI(a, b) => match lookup(a)? {
+			Known::Integer(a) => *a == i64::from(*b),
+			Known::String(_) => false,
+		},
+		Condition::EqK(a, b) => match (lookup(a)?, find_value(value_list, b)?) {
+			(Known::Integer(a), Value::Integer(b)) => a == b,
+			(Known::String(a), Value::String(b)) => a == b,
+			(Known::Integer(_), Value::String(_)) | (Known::String(_), Value::Integer(_)) => false,
+			_ => return None,
+		},
+		Condition::Lt(a, b) => compare(lookup(a)?, lookup(b)?)? == Ordering::Less,
+		Condition::Le(a, b) => compare(lookup(a)?, lookup(b)?)? != Ordering::Greater,
+		Condition::LtI(a, b) => compare_imm(lookup(a)?, *b)? == Ordering::Less,
+		Condition::LeI(a, b) => compare_imm(lookup(a)?, *b)? != Ordering::Greater,
+		Condition::GtI(a, b) => compare_imm(lookup(a)?, *b)? == Ordering::Greater,
+		Condition::GeI(a, b) => compare_imm(lookup(a)?, *b)? != Ordering::Less,
+		// also assigns a register on the way
+		Condition::TestSet(..) => return None,
+	};
+
+	Some(result)
+}
+
+// turns conditions with a known outcome into plain jumps to the taken target,
+// the other arm is left for `prune_unreachable` to clean up
+pub fn thread_jumps(func: &mut Function<Block>) {
+	for blk in &mut func.block_list {
+		let taken = match &blk.edge {
+			Control::Condition(cond, ..) => {
+				let known_list = trailing_loads(&func.value_list, &blk.body);
+
+				evaluate(&func.value_list, &known_list, cond)
+			}
+			_ => None,
+		};
+
+		if let Some(taken) = taken {
+			let edge = replace(&mut blk.edge, Control::Unconditional(Target::Undefined(0)));
+
+			if let Control::Condition(_, on_true, on_false) = edge {
+				blk.edge = Control::Unconditional(if taken { on_true } else { on_false });
+			}
+		}
+	}
+}
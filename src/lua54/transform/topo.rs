@@ -0,0 +1,31 @@
+use crate::{
+	common::types::Function,
+	lua54::{analysis::postorder, common::inst::Block},
+};
+use std::collections::HashMap;
+
+// reverse postorder from the entry block, visiting higher labels first so the
+// lower one of two successors ends up earlier, unreachable blocks come last
+pub fn topo_sort(func: &mut Function<Block>) {
+	let succ_map: HashMap<u32, Vec<u32>> = func
+		.block_list
+		.iter()
+		.map(|v| {
+			let mut label_list = v.get_target_labels();
+
+			label_list.sort_unstable_by(|a, b| b.cmp(a));
+
+			(v.label, label_list)
+		})
+		.collect();
+
+	let order: HashMap<u32, usize> = postorder(&succ_map, 0)
+		.into_iter()
+		.rev()
+		.enumerate()
+		.map(|(i, v)| (v, i))
+		.collect();
+
+	func.block_list
+		.sort_by_key(|v| (order.get(&v.label).copied().unwrap_or(usize::MAX), v.label));
+}
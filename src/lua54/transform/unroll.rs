@@ -0,0 +1,125 @@
+use crate::{
+	common::types::Function,
+	lua54::common::inst::{Block, Control, Loop, Reg, Target, IR},
+};
+
+// a `FORLOOP` jumping back to the start of its own block, with nothing in the
+// body writing the counter, limit, step or the variable the loop hands out
+fn is_counted(blk: &Block) -> bool {
+	let a = match blk.edge {
+		Control::Loop(Loop::Numeric(Reg::R(a)), Target::Label(_), Target::Label(body))
+			if body == blk.label =>
+		{
+			a
+		}
+		_ => return false,
+	};
+
+	blk.body
+		.iter()
+		.flat_map(|v| v.defs_uses().0)
+		.all(|reg| !(a..=a.saturating_add(3)).contains(&reg))
+}
+
+// every copy keeps its own `FORLOOP`, which already steps the induction and
+// checks the count, so each one either goes on to the next copy or leaves
+// the loop and the last goes back around to the first
+pub fn unroll_loops(func: &mut Function<Block>, factor: usize) {
+	let mut next_label = match func.block_list.iter().map(|v| v.label).max() {
+		Some(label) if factor > 1 => label + 1,
+		_ => return,
+	};
+
+	let mut index = 0;
+
+	while index < func.block_list.len() {
+		if !is_counted(&func.block_list[index]) {
+			index += 1;
+			continue;
+		}
+
+		let blk = &func.block_list[index];
+		let (kind, exit) = match &blk.edge {
+			Control::Loop(kind, exit, _) => (kind.clone(), exit.clone()),
+			_ => unreachable!(),
+		};
+		let body: Vec<IR> = blk.body.clone();
+		let first = blk.label;
+		let label_list: Vec<u32> = (next_label..).take(factor - 1).collect();
+
+		next_label += label_list.len() as u32;
+
+		let edge_to = |to: u32| Control::Loop(kind.clone(), exit.clone(), Target::Label(to));
+		let to_list = label_list
+			.iter()
+			.skip(1)
+			.copied()
+			.chain(std::iter::once(first));
+
+		func.block_list[index].edge = edge_to(label_list[0]);
+
+		for (offset, (&from, to)) in label_list.iter().zip(to_list).enumerate() {
+			let blk = Block::new(from, body.clone(), edge_to(to));
+
+			func.block_list.insert(index + offset + 1, blk);
+		}
+
+		index += factor;
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{assemble, disassemble};
+
+	// `for i = 1, 10 do s = s + i end` as one block jumping back to itself
+	const NUMERIC_LOOP: &str = include_str!("../../../tests/fixtures/numeric_loop.ron");
+
+	fn is_numeric(blk: &Block) -> bool {
+		matches!(blk.edge, Control::Loop(Loop::Numeric(_), ..))
+	}
+
+	#[test]
+	fn unrolled_loop_reassembles() {
+		let mut func: Function<Block> = ron::from_str(NUMERIC_LOOP).unwrap();
+
+		unroll_loops(&mut func, 3);
+
+		let label_list: Vec<u32> = func.block_list.iter().map(|v| v.label).collect();
+
+		assert_eq!(label_list, [0, 1, 3, 4, 2]);
+		assert_eq!(func.block_list.iter().filter(|v| is_numeric(v)).count(), 3);
+
+		let data = assemble(func).unwrap();
+		let func = disassemble(&data).unwrap();
+		let add_count = func
+			.instructions()
+			.filter(|v| matches!(v, IR::Add(..)))
+			.count();
+
+		assert_eq!(add_count, 3);
+		assert_eq!(func.block_list.iter().filter(|v| is_numeric(v)).count(), 3);
+		assert!(assemble(func).unwrap() == data);
+	}
+
+	// the counter, limit, step and the variable handed out are all off limits
+	#[test]
+	fn loop_writing_its_registers_is_left_alone() {
+		let func: Function<Block> = ron::from_str(NUMERIC_LOOP).unwrap();
+
+		assert!(is_counted(&func.block_list[1]));
+
+		for reg in 1..=4 {
+			let mut func = func.clone();
+
+			func.block_list[1].body.push(IR::LoadI(Reg::R(reg), 0));
+
+			assert!(!is_counted(&func.block_list[1]));
+
+			unroll_loops(&mut func, 3);
+
+			assert_eq!(func.block_list.len(), 3);
+		}
+	}
+}
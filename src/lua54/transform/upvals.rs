@@ -0,0 +1,141 @@
+use crate::{common::types::Function, lua54::common::inst::Block};
+use rand::{rngs::StdRng, seq::SliceRandom};
+use std::sync::Arc;
+
+// puts the upvalues of `func` in the order `order` gives, as the index each
+// new one had before, and points the children capturing them at where they
+// went, instructions name their upvalues so they need nothing
+pub fn reorder_upvals(func: &mut Function<Block>, order: &[usize]) {
+	let mut index_map = vec![0; order.len()];
+
+	for (new, &old) in order.iter().enumerate() {
+		index_map[old] = new;
+	}
+
+	let mut old_list: Vec<_> = func.upval_list.drain(..).map(Some).collect();
+
+	func.upval_list = order.iter().filter_map(|&v| old_list[v].take()).collect();
+
+	// an index past the list is left as wrong as it was
+	for (_, child) in &mut func.child_list {
+		for (_, upval) in child.upval_list.iter_mut().filter(|v| !v.1.in_stack) {
+			if let Some(&new) = index_map.get(usize::from(upval.index)) {
+				upval.index = new as u8;
+			}
+		}
+	}
+}
+
+// a chunk's main function is handed `_ENV` as its first upvalue, so that one
+// keeps its place there
+fn first_movable(func: &Function<Block>, is_main: bool) -> usize {
+	usize::from(is_main).min(func.upval_list.len())
+}
+
+pub fn permute_upvals(func: &mut Function<Block>, rng: &mut StdRng, is_main: bool) {
+	let fixed = first_movable(func, is_main);
+	let mut order: Vec<usize> = (0..func.upval_list.len()).collect();
+
+	order[fixed..].shuffle(rng);
+	reorder_upvals(func, &order);
+}
+
+pub fn sort_upvals(func: &mut Function<Block>, is_main: bool) {
+	let fixed = first_movable(func, is_main);
+	let mut order: Vec<usize> = (0..func.upval_list.len()).collect();
+
+	order[fixed..].sort_by_key(|&v| Arc::clone(&func.upval_list[v].0));
+	reorder_upvals(func, &order);
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{
+		assemble,
+		common::types::Depth,
+		disassemble,
+		lua54::{
+			common::inst::IR,
+			transform::mutate::{mutate, Mutation},
+		},
+	};
+	use rand::SeedableRng;
+
+	// a main function handing a local to a child, which hands it on to its own
+	// child next to `_ENV` and one of its locals
+	const CAPTURES: &str = include_str!("../../../tests/fixtures/captures.ron");
+
+	// what each upvalue instruction reaches, the register of the function it
+	// was captured from, by where that function sits rather than by name
+	fn reached(func: &Function<Block>, path: &str, outer: &[String], list: &mut Vec<String>) {
+		let id_list: Vec<String> = func
+			.upval_list
+			.iter()
+			.map(|(_, upval)| match upval.in_stack {
+				true => format!("{} r{}", path, upval.index),
+				false => outer[usize::from(upval.index)].clone(),
+			})
+			.collect();
+		let id_of = |name: &Arc<str>| {
+			let index = func.upval_list.iter().position(|v| v.0 == *name).unwrap();
+
+			id_list[index].clone()
+		};
+
+		for blk in &func.block_list {
+			for ir in &blk.body {
+				match ir {
+					IR::GetUpval(_, name) | IR::SetUpval(_, name) | IR::GetTabUp(_, name, _) => {
+						list.push(id_of(name))
+					}
+					IR::SetTabUp(name, ..) => list.push(id_of(name)),
+					_ => {}
+				}
+			}
+		}
+
+		for (index, (_, child)) in func.child_list.iter().enumerate() {
+			reached(child, &format!("{}/{}", path, index), &id_list, list);
+		}
+	}
+
+	fn reached_list(func: &Function<Block>) -> Vec<String> {
+		let mut list = Vec::new();
+
+		reached(func, "main", &["env".to_string()], &mut list);
+		list
+	}
+
+	#[test]
+	fn permute_keeps_captures() {
+		let func: Function<Block> = ron::from_str(CAPTURES).unwrap();
+		let expected = reached_list(&func);
+		let mut moved = false;
+
+		for seed in 0..16 {
+			let mut func = func.clone();
+
+			mutate(
+				&mut func,
+				&[Mutation::PermuteUpvals],
+				&mut StdRng::seed_from_u64(seed),
+				Depth::new(8),
+			)
+			.unwrap();
+
+			assert_eq!(reached_list(&func), expected);
+			assert_eq!(&*func.upval_list[0].0, "uEnv");
+
+			let inner = &func.child_list[0].1.child_list[0].1;
+
+			moved |= &*inner.upval_list[0].0 != "uA";
+
+			let func = disassemble(&assemble(func).unwrap()).unwrap();
+
+			assert_eq!(reached_list(&func), expected);
+		}
+
+		assert!(moved);
+	}
+}
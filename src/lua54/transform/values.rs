@@ -0,0 +1,136 @@
+use crate::{
+	common::types::Function,
+	lua54::common::inst::{Block, IR},
+};
+use rand::{rngs::StdRng, seq::SliceRandom};
+use std::{collections::HashSet, sync::Arc};
+
+// how many constants an 8 bit operand can reach, only `LOADK` and `EXTRAARG`
+// have room for more
+const NARROW_LIMIT: usize = 1 << 8;
+
+fn narrow_constants(func: &Function<Block>) -> HashSet<Arc<str>> {
+	let body = func
+		.instructions()
+		.filter(|v| !matches!(v, IR::LoadK(..) | IR::ExtraValue(_)))
+		.flat_map(IR::constants);
+	let edge = func.block_list.iter().flat_map(|v| v.edge.constants());
+
+	body.chain(edge).map(Arc::clone).collect()
+}
+
+// constants an 8 bit operand reads that the new order put out of its reach
+// trade places with ones only read through a wide operand, latest first for
+// the latest, which keeps the rest of the order as it was
+fn fit_narrow(func: &mut Function<Block>) {
+	let narrow = narrow_constants(func);
+	let is_narrow =
+		|func: &Function<Block>, index: usize| narrow.contains(&func.value_list[index].0);
+	let len = func.value_list.len();
+	let late: Vec<usize> = (NARROW_LIMIT..len)
+		.filter(|&v| is_narrow(func, v))
+		.collect();
+	let early: Vec<usize> = (0..NARROW_LIMIT.min(len))
+		.filter(|&v| !is_narrow(func, v))
+		.collect();
+
+	// more than fit was never assembled in the first place
+	for (late, early) in late.into_iter().zip(early.into_iter().rev()) {
+		func.value_list.swap(late, early);
+	}
+}
+
+// instructions name their constants, so only where those land needs care
+pub fn permute_values(func: &mut Function<Block>, rng: &mut StdRng) {
+	func.value_list.shuffle(rng);
+	fit_narrow(func);
+}
+
+pub fn sort_values(func: &mut Function<Block>) {
+	func.value_list.sort_by_key(|v| Arc::clone(&v.0));
+	fit_narrow(func);
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{
+		assemble,
+		common::types::Value,
+		disassemble,
+		lua54::common::inst::{Reg, RegOrK},
+	};
+	use rand::SeedableRng;
+
+	const DIAMOND: &str = include_str!("../../../tests/fixtures/diamond.ron");
+
+	// 600 constants, one in six read through an 8 bit operand and the rest
+	// through `LOADK`, so a shuffle is bound to put some narrow ones too far
+	fn wide_function() -> Function<Block> {
+		let mut func: Function<Block> = ron::from_str(DIAMOND).unwrap();
+
+		func.value_list = (0..600)
+			.map(|v| {
+				(
+					format!("vString_{}", v).into(),
+					Value::String(v.to_string()),
+				)
+			})
+			.collect();
+		func.block_list[3].body = func
+			.value_list
+			.iter()
+			.enumerate()
+			.map(|(i, (name, _))| match i % 6 {
+				1 => IR::SetField(Reg::R(0), Arc::clone(name), RegOrK::R(1)),
+				_ => IR::LoadK(Reg::R(1), Arc::clone(name)),
+			})
+			.collect();
+
+		func
+	}
+
+	// the value each constant operand reads, in order
+	fn read_list(func: &Function<Block>) -> Vec<String> {
+		func.instructions()
+			.flat_map(IR::constants)
+			.map(|name| {
+				let (_, value) = func.value_list.iter().find(|v| v.0 == *name).unwrap();
+
+				value.as_str().to_string()
+			})
+			.collect()
+	}
+
+	fn check_order(func: Function<Block>, expected: &[String]) {
+		let narrow = narrow_constants(&func);
+
+		assert!(func
+			.value_list
+			.iter()
+			.enumerate()
+			.all(|(i, v)| i < NARROW_LIMIT || !narrow.contains(&v.0)));
+		assert_eq!(read_list(&func), expected);
+
+		let func = disassemble(&assemble(func).unwrap()).unwrap();
+
+		assert_eq!(read_list(&func), expected);
+	}
+
+	#[test]
+	fn shuffle_keeps_narrow_in_reach() {
+		let expected = read_list(&wide_function());
+
+		for seed in 0..8 {
+			let mut func = wide_function();
+
+			permute_values(&mut func, &mut StdRng::seed_from_u64(seed));
+			check_order(func, &expected);
+		}
+
+		let mut func = wide_function();
+
+		sort_values(&mut func);
+		check_order(func, &expected);
+	}
+}
@@ -0,0 +1,373 @@
+use super::common::inst::{Block, Condition, Control, Group, Loop, Reg, RegOrK, Target, IR};
+use crate::common::types::{Depth, Function, Named};
+use std::{collections::HashSet, fmt, sync::Arc};
+
+pub enum Problem {
+	TooDeep(usize),
+	NoEntry,
+	DuplicateLabel,
+	MissingLabel(u32),
+	Register(u8, u8),
+	Value(Arc<str>),
+	Upvalue(Arc<str>),
+	Proto(Arc<str>),
+}
+
+// problems are listed per proto by the same dotted path `--proto` takes, and
+// per block when one is to blame
+pub struct ValidationError {
+	pub path: String,
+	pub label: Option<u32>,
+	pub problem: Problem,
+}
+
+// what a block refers to, gathered before being checked against the function
+#[derive(Default)]
+struct Operands<'a> {
+	reg_list: Vec<u8>,
+	value_list: Vec<&'a Arc<str>>,
+	upval_list: Vec<&'a Arc<str>>,
+	proto_list: Vec<&'a Arc<str>>,
+	label_list: Vec<u32>,
+}
+
+impl<'a> Operands<'a> {
+	fn reg(&mut self, reg: &Reg) {
+		let Reg::R(x) = reg;
+
+		self.reg_list.push(*x);
+	}
+
+	fn reg_or_k(&mut self, rk: &'a RegOrK) {
+		match rk {
+			RegOrK::R(x) => self.reg_list.push(*x),
+			RegOrK::K(k) => self.value_list.push(k),
+		}
+	}
+
+	fn target(&mut self, target: &Target) {
+		if let Target::Label(label) = target {
+			self.label_list.push(*label);
+		}
+	}
+
+	fn add_ir(&mut self, ir: &'a IR) {
+		match ir {
+			IR::Move(a, b)
+			| IR::GetI(a, b, _)
+			| IR::AddI(a, b, _)
+			| IR::ShrI(a, b, _)
+			| IR::ShlI(a, b, _)
+			| IR::MmBin(a, b, _)
+			| IR::Unm(a, b)
+			| IR::Bnot(a, b)
+			| IR::Not(a, b)
+			| IR::Len(a, b) => {
+				self.reg(a);
+				self.reg(b);
+			}
+			IR::GetTable(a, b, c)
+			| IR::Add(a, b, c)
+			| IR::Sub(a, b, c)
+			| IR::Mul(a, b, c)
+			| IR::Mod(a, b, c)
+			| IR::Pow(a, b, c)
+			| IR::Div(a, b, c)
+			| IR::IDiv(a, b, c)
+			| IR::Band(a, b, c)
+			| IR::Bor(a, b, c)
+			| IR::Bxor(a, b, c)
+			| IR::Shl(a, b, c)
+			| IR::Shr(a, b, c) => {
+				self.reg(a);
+				self.reg(b);
+				self.reg(c);
+			}
+			IR::LoadI(a, _)
+			| IR::LoadF(a, _)
+			| IR::LoadKX(a)
+			| IR::LoadFalse(a)
+			| IR::LoadTrue(a)
+			| IR::LoadNil(a, _)
+			| IR::NewTable(a, ..)
+			| IR::MmBinI(a, ..)
+			// the second operand of `Concat` is a count despite its type
+			| IR::Concat(a, ..)
+			| IR::Close(a)
+			| IR::Tbc(a)
+			| IR::Call(a, ..)
+			| IR::TailCall(a, ..)
+			| IR::TForCall(a, _)
+			| IR::SetList(a, ..)
+			| IR::Vararg(a, _)
+			| IR::VarargPrep(a) => self.reg(a),
+			IR::LoadK(a, k) | IR::MmBinK(a, k, ..) => {
+				self.reg(a);
+				self.value_list.push(k);
+			}
+			IR::GetUpval(a, u) | IR::SetUpval(a, u) => {
+				self.reg(a);
+				self.upval_list.push(u);
+			}
+			IR::GetTabUp(a, u, k) => {
+				self.reg(a);
+				self.upval_list.push(u);
+				self.value_list.push(k);
+			}
+			IR::GetField(a, b, k)
+			| IR::AddK(a, b, k)
+			| IR::SubK(a, b, k)
+			| IR::MulK(a, b, k)
+			| IR::ModK(a, b, k)
+			| IR::PowK(a, b, k)
+			| IR::DivK(a, b, k)
+			| IR::IDivK(a, b, k)
+			| IR::BandK(a, b, k)
+			| IR::BorK(a, b, k)
+			| IR::BxorK(a, b, k) => {
+				self.reg(a);
+				self.reg(b);
+				self.value_list.push(k);
+			}
+			IR::SetTabUp(u, k, rk) => {
+				self.upval_list.push(u);
+				self.value_list.push(k);
+				self.reg_or_k(rk);
+			}
+			IR::SetTable(a, b, rk) | IR::Method(a, b, rk) => {
+				self.reg(a);
+				self.reg(b);
+				self.reg_or_k(rk);
+			}
+			IR::SetI(a, _, rk) => {
+				self.reg(a);
+				self.reg_or_k(rk);
+			}
+			IR::SetField(a, k, rk) => {
+				self.reg(a);
+				self.value_list.push(k);
+				self.reg_or_k(rk);
+			}
+			IR::Closure(a, name) => {
+				self.reg(a);
+				self.proto_list.push(name);
+			}
+			IR::ExtraValue(k) => self.value_list.push(k),
+			IR::ExtraInteger(_) | IR::Invalid(_) => {}
+		}
+	}
+
+	fn add_condition(&mut self, cond: &'a Condition) {
+		match cond {
+			Condition::Test(a)
+			| Condition::EqI(a, _)
+			| Condition::GeI(a, _)
+			| Condition::GtI(a, _)
+			| Condition::LeI(a, _)
+			| Condition::LtI(a, _) => self.reg(a),
+			Condition::TestSet(a, b)
+			| Condition::Eq(a, b)
+			| Condition::Le(a, b)
+			| Condition::Lt(a, b) => {
+				self.reg(a);
+				self.reg(b);
+			}
+			Condition::EqK(a, k) => {
+				self.reg(a);
+				self.value_list.push(k);
+			}
+		}
+	}
+
+	// a return of nothing may name the first register past the stack
+	fn add_control(&mut self, edge: &'a Control) {
+		match edge {
+			Control::LFalseSkip(a, target) => {
+				self.reg(a);
+				self.target(target);
+			}
+			Control::Condition(cond, on_true, on_false) => {
+				self.add_condition(cond);
+				self.target(on_true);
+				self.target(on_false);
+			}
+			Control::Loop(kind, on_false, on_true) => {
+				match kind {
+					Loop::Iterator(a)
+					| Loop::IteratorPrep(a)
+					| Loop::Numeric(a)
+					| Loop::NumericPrep(a) => self.reg(a),
+				}
+
+				self.target(on_true);
+				self.target(on_false);
+			}
+			Control::Return(a, count, ..) => {
+				if !matches!(count, Group::Exactly(0)) {
+					self.reg(a);
+				}
+			}
+			Control::Return1(a) => self.reg(a),
+			Control::Return0(_) => {}
+			Control::Unconditional(target) => self.target(target),
+		}
+	}
+}
+
+// the registers an instruction or edge names outright, counts past them are
+// left out, for passes that need to know if a register is used at all
+pub(crate) fn ir_registers(ir: &IR) -> Vec<u8> {
+	let mut operands = Operands::default();
+
+	operands.add_ir(ir);
+	operands.reg_list
+}
+
+pub(crate) fn edge_registers(edge: &Control) -> Vec<u8> {
+	let mut operands = Operands::default();
+
+	operands.add_control(edge);
+	operands.reg_list
+}
+
+fn name_set<T>(list: &Named<T>) -> HashSet<&str> {
+	list.iter().map(|v| v.0.as_ref()).collect()
+}
+
+fn validate_function(
+	func: &Function<Block>,
+	path: &str,
+	depth: Depth,
+	error_list: &mut Vec<ValidationError>,
+) {
+	let mut push = |label, problem| {
+		error_list.push(ValidationError {
+			path: path.to_string(),
+			label,
+			problem,
+		})
+	};
+
+	let mut defined = HashSet::new();
+
+	for blk in &func.block_list {
+		if !defined.insert(blk.label) {
+			push(Some(blk.label), Problem::DuplicateLabel);
+		}
+	}
+
+	// the assembler starts at block 0 wherever it is laid out
+	if !defined.contains(&0) {
+		push(None, Problem::NoEntry);
+	}
+
+	let num_stack = func.stack_info.num_stack;
+	let value_set = name_set(&func.value_list);
+	let upval_set = name_set(&func.upval_list);
+	let proto_set = name_set(&func.child_list);
+
+	for blk in &func.block_list {
+		let mut operands = Operands::default();
+
+		blk.body.iter().for_each(|v| operands.add_ir(v));
+		operands.add_control(&blk.edge);
+
+		let label = Some(blk.label);
+
+		for to in operands.label_list {
+			if !defined.contains(&to) {
+				push(label, Problem::MissingLabel(to));
+			}
+		}
+
+		for reg in operands.reg_list {
+			if reg >= num_stack {
+				push(label, Problem::Register(reg, num_stack));
+			}
+		}
+
+		for name in operands.value_list {
+			if !value_set.contains(name.as_ref()) {
+				push(label, Problem::Value(Arc::clone(name)));
+			}
+		}
+
+		for name in operands.upval_list {
+			if !upval_set.contains(name.as_ref()) {
+				push(label, Problem::Upvalue(Arc::clone(name)));
+			}
+		}
+
+		for name in operands.proto_list {
+			if !proto_set.contains(name.as_ref()) {
+				push(label, Problem::Proto(Arc::clone(name)));
+			}
+		}
+	}
+
+	if func.child_list.is_empty() {
+		return;
+	}
+
+	// nothing past the limit is looked into, that one problem is enough
+	let depth = match depth.child() {
+		Ok(depth) => depth,
+		Err(e) => return push(None, Problem::TooDeep(e.max_depth)),
+	};
+
+	for (index, (_, child)) in func.child_list.iter().enumerate() {
+		let child_path = if path.is_empty() {
+			index.to_string()
+		} else {
+			format!("{}.{}", path, index)
+		};
+
+		validate_function(child, &child_path, depth, error_list);
+	}
+}
+
+// everything the assembler would otherwise panic on or turn into bytecode the
+// VM can't run, nested protos included
+pub fn validate(func: &Function<Block>, depth: Depth) -> Result<(), Vec<ValidationError>> {
+	let mut error_list = Vec::new();
+
+	validate_function(func, "", depth, &mut error_list);
+
+	if error_list.is_empty() {
+		Ok(())
+	} else {
+		Err(error_list)
+	}
+}
+
+impl fmt::Display for Problem {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Problem::TooDeep(max_depth) => write!(f, "proto nesting exceeds {}", max_depth),
+			Problem::NoEntry => write!(f, "no entry block 0"),
+			Problem::DuplicateLabel => write!(f, "label defined more than once"),
+			Problem::MissingLabel(to) => write!(f, "jumps to missing block {}", to),
+			Problem::Register(reg, num_stack) => {
+				write!(f, "register r{} past a stack of {}", reg, num_stack)
+			}
+			Problem::Value(name) => write!(f, "`{}` is not a valid value", name),
+			Problem::Upvalue(name) => write!(f, "`{}` is not a valid upvalue", name),
+			Problem::Proto(name) => write!(f, "`{}` is not a valid child proto", name),
+		}
+	}
+}
+
+impl fmt::Display for ValidationError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		let path = if self.path.is_empty() {
+			"main"
+		} else {
+			&self.path
+		};
+
+		match self.label {
+			Some(label) => write!(f, "{}: block {}: {}", path, label, self.problem),
+			None => write!(f, "{}: {}", path, self.problem),
+		}
+	}
+}
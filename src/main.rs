@@ -1,97 +1,826 @@
-use common::types::{Function, Instruction, Res};
 use egui::Color32;
 use egui_snarl::{ui::SnarlViewer, InPinId, NodeId, OutPinId};
-use lua54::{
-	common::{
-		inst::{Block, Condition, Control, Target},
-		types::Proto,
+use lau::{
+	assemble_as,
+	common::types::{Depth, Function, Layout, LineInfo, Local, Named, StackInfo, Upvalue, Value},
+	disassemble_as, load_chunk,
+	lua53::{types::LUA_VERSION as LUA53_VERSION, Lua53},
+	lua54::{
+		analysis::calls::call_graph,
+		common::{
+			inst::{Block, BlockBuilder, Control, Group, Loop, Reg, Target},
+			types::{Proto, LUA_SIGNATURE},
+		},
+		diff::diff_functions,
+		dot::{call_graph_dot, to_dot},
+		extract::extract_block,
+		format::{BytecodeFormat, Lua54},
+		listing::{block_listing, condition_text, disasm_listing, dump_constants, InstructionText},
+		loader::parse_header,
+		stats::collect_stats,
+		transform::{
+			devirt::devirtualize,
+			mutate::{mutate, Mutation, MutationReport},
+			pipeline::{run_pipeline, Pass},
+			strip::strip_debug,
+		},
+		validate::validate,
 	},
-	dumper::dump_lua_module,
-	loader::load_lua_module,
 };
-use rand::seq::SliceRandom;
+use rand::{rngs::StdRng, SeedableRng};
 use ron::{
 	de::from_bytes,
 	ser::{to_string_pretty, PrettyConfig},
 };
+use serde::{de::DeserializeOwned, Deserialize, Deserializer, Serialize};
+use serde_json::ser::PrettyFormatter;
 use std::{
-	arch::x86_64::__m128,
 	collections::{HashMap, HashSet, VecDeque},
-	hash::Hash,
-	io::{Result, Write},
-	rc::Rc,
+	convert::TryFrom,
+	fs::File,
+	io::{BufRead, ErrorKind, Read, Result, Write},
+	iter::Peekable,
+	path::{Path, PathBuf},
+	sync::{
+		mpsc::{self, RecvTimeoutError},
+		Arc,
+	},
+	time::Duration,
 };
 
-mod common;
-mod lua54;
+#[derive(Clone, Copy)]
+enum Format {
+	Ron,
+	Json,
+}
+
+impl Format {
+	fn extension(self) -> &'static str {
+		match self {
+			Format::Ron => "ron",
+			Format::Json => "json",
+		}
+	}
+}
+
+// how -d and -v lay out what they write, in either format
+#[derive(Clone, Copy)]
+enum Indent {
+	Default,
+	Width(usize),
+	Compact,
+}
+
+// as deep as the reference compiler lets functions nest, `LUAI_MAXCCALLS`
+const MAX_DEPTH: usize = 200;
+
+// a missing name or `-` reads the whole input from stdin
+fn read_input(name: Option<String>) -> Result<Vec<u8>> {
+	match name.as_deref() {
+		None | Some("-") => {
+			let mut data = Vec::new();
+
+			std::io::stdin().lock().read_to_end(&mut data)?;
+
+			Ok(data)
+		}
+		Some(name) => std::fs::read(name),
+	}
+}
+
+// `File::create` truncates, so a stale output never leaves trailing bytes
+fn write_output(output: Option<&Path>, data: &[u8]) -> Result<()> {
+	match output {
+		Some(path) => File::create(path)?.write_all(data),
+		None => std::io::stdout().lock().write_all(data),
+	}
+}
+
+// every argument up to the next option, `-` alone being stdin and not one
+fn take_names<I: Iterator<Item = String>>(iter: &mut Peekable<I>) -> Vec<String> {
+	let mut name_list = Vec::new();
+
+	while let Some(name) = iter.next_if(|v| v == "-" || !v.starts_with('-')) {
+		name_list.push(name);
+	}
+
+	name_list
+}
+
+// a single input goes wherever `-o` says, several are each written next to
+// themselves with their extension swapped for `ext`
+fn batch_outputs(
+	name_list: Vec<String>,
+	output: Option<&Path>,
+	ext: &str,
+) -> Result<Vec<(Option<String>, Option<PathBuf>)>> {
+	if name_list.len() < 2 {
+		return Ok(vec![(
+			name_list.into_iter().next(),
+			output.map(Path::to_path_buf),
+		)]);
+	}
+
+	let invalid = |msg: &str| std::io::Error::new(ErrorKind::InvalidInput, msg.to_string());
+
+	if output.is_some() {
+		return Err(invalid(
+			"-o names one output, not one for each of several inputs",
+		));
+	}
+
+	name_list
+		.into_iter()
+		.map(|name| {
+			if name == "-" {
+				return Err(invalid("stdin can't be one of several inputs"));
+			}
+
+			let path = Path::new(&name).with_extension(ext);
+
+			Ok((Some(name), Some(path)))
+		})
+		.collect()
+}
+
+// the offending line is quoted with a caret under the column, positions
+// start at 1 and are left out when RON doesn't know them
+fn ron_error(data: &[u8], err: ron::Error) -> std::io::Error {
+	let ron::error::Position { line, col } = err.position;
+	let line_text = String::from_utf8_lossy(data)
+		.lines()
+		.nth(line.saturating_sub(1))
+		.map(str::to_string);
+
+	let msg = match line_text {
+		Some(text) if line != 0 => {
+			let caret: String = text
+				.chars()
+				.take(col.saturating_sub(1))
+				.map(|c| if c == '\t' { '\t' } else { ' ' })
+				.collect();
+
+			format!(
+				"not valid RON at line {}, column {}: {}\n{}\n{}^",
+				line, col, err.code, text, caret
+			)
+		}
+		_ => format!("not valid RON: {}", err.code),
+	};
+
+	std::io::Error::new(ErrorKind::InvalidData, msg)
+}
+
+// the text formats are told apart by their first character since a JSON
+// function is an object while a RON one is a struct
+fn parse_function(data: &[u8]) -> Result<Function<Block>> {
+	parse_text(data)
+}
+
+// JSON always opens with a brace, which a RON struct never does
+fn text_format(data: &[u8]) -> Format {
+	match data.iter().find(|v| !v.is_ascii_whitespace()) {
+		Some(b'{') => Format::Json,
+		_ => Format::Ron,
+	}
+}
+
+fn parse_text<T: DeserializeOwned>(data: &[u8]) -> Result<T> {
+	match text_format(data) {
+		Format::Json => serde_json::from_slice(data).map_err(|e| {
+			std::io::Error::new(ErrorKind::InvalidData, format!("not valid JSON: {}", e))
+		}),
+		Format::Ron => from_bytes(data).map_err(|e| ron_error(data, e)),
+	}
+}
+
+// a chunk picks its loader from the version byte after the signature, so the
+// UI opens bytecode without going through RON first
+fn load_function(data: &[u8]) -> Result<(Function<Block>, Option<&'static dyn BytecodeFormat>)> {
+	if !data.starts_with(LUA_SIGNATURE) {
+		return Ok((parse_function(data)?, None));
+	}
+
+	let lua: &'static dyn BytecodeFormat = match data.get(LUA_SIGNATURE.len()) {
+		Some(&LUA53_VERSION) => &Lua53,
+		_ => &Lua54,
+	};
+	let func =
+		disassemble_as(data, lua).map_err(|e| std::io::Error::new(ErrorKind::InvalidData, e))?;
+
+	Ok((func, Some(lua)))
+}
+
+// a function as `-a --tree` reads it, where a child can be only a `child_ref`
+// naming the file it is in instead, relative to the file naming it
+#[derive(Deserialize)]
+struct TreeFunction {
+	#[serde(default, deserialize_with = "present")]
+	child_ref: Option<PathBuf>,
+	#[serde(default)]
+	layout: Layout,
+	source: Option<String>,
+	#[serde(default, deserialize_with = "present")]
+	stack_info: Option<StackInfo>,
+	#[serde(default, deserialize_with = "present")]
+	line_info: Option<LineInfo>,
+	#[serde(default, deserialize_with = "present")]
+	value_list: Option<Named<Value>>,
+	#[serde(default, deserialize_with = "present")]
+	local_list: Option<Vec<Local>>,
+	#[serde(default, deserialize_with = "present")]
+	upval_list: Option<Named<Upvalue>>,
+	#[serde(default, deserialize_with = "present")]
+	block_list: Option<Vec<Block>>,
+	#[serde(default, deserialize_with = "present")]
+	child_list: Option<Named<TreeFunction>>,
+}
 
-enum Mutation {
-	Random,
-	Sorted,
+// a field that is there is written as itself, not wrapped in `Some`
+fn present<'de, D, T>(de: D) -> std::result::Result<Option<T>, D::Error>
+where
+	D: Deserializer<'de>,
+	T: Deserialize<'de>,
+{
+	T::deserialize(de).map(Some)
 }
 
-fn try_mutate(func: &mut Function<Block>, opt: &[Mutation]) {
-	let mut rng = rand::thread_rng();
+impl TreeFunction {
+	// `file` being the one it was read from
+	fn resolve(self, file: &Path, depth: Depth) -> Result<Function<Block>> {
+		let invalid = |msg: String| {
+			let msg = format!("{}: {}", file.display(), msg);
+
+			std::io::Error::new(ErrorKind::InvalidData, msg)
+		};
+
+		if let Some(path) = self.child_ref {
+			let is_bare = self.source.is_none()
+				&& self.stack_info.is_none()
+				&& self.line_info.is_none()
+				&& self.value_list.is_none()
+				&& self.local_list.is_none()
+				&& self.upval_list.is_none()
+				&& self.block_list.is_none()
+				&& self.child_list.is_none();
+
+			if !is_bare {
+				let msg = format!(
+					"`child_ref` {} takes the place of the whole child",
+					path.display()
+				);
+
+				return Err(invalid(msg));
+			}
 
-	for data in &mut func.child_list {
-		try_mutate(&mut data.1, opt);
+			let dir = file.parent().unwrap_or_else(|| Path::new(""));
+
+			return load_tree(&dir.join(path), depth);
+		}
+
+		let missing = |name: &str| invalid(format!("missing field `{}`", name));
+		let source = self.source;
+		let child_list = self
+			.child_list
+			.ok_or_else(|| missing("child_list"))?
+			.into_iter()
+			.map(|(name, child)| {
+				let depth = depth.child().map_err(|e| invalid(e.to_string()))?;
+				let is_ref = child.child_ref.is_some();
+				let mut child = child.resolve(file, depth)?;
+
+				// `--proto` writes out the source a child shares with its
+				// parent, which a chunk leaves out
+				if is_ref && child.source == source {
+					child.source = None;
+				}
+
+				Ok((name, child))
+			})
+			.collect::<Result<_>>()?;
+
+		Ok(Function {
+			layout: self.layout,
+			source,
+			stack_info: self.stack_info.ok_or_else(|| missing("stack_info"))?,
+			line_info: self.line_info.ok_or_else(|| missing("line_info"))?,
+			value_list: self.value_list.ok_or_else(|| missing("value_list"))?,
+			local_list: self.local_list.ok_or_else(|| missing("local_list"))?,
+			upval_list: self.upval_list.ok_or_else(|| missing("upval_list"))?,
+			block_list: self.block_list.ok_or_else(|| missing("block_list"))?,
+			child_list,
+		})
 	}
+}
 
-	for step in opt.iter() {
-		match step {
-			Mutation::Random => {
-				func.block_list.shuffle(&mut rng);
-				func.child_list.shuffle(&mut rng);
-				func.upval_list.shuffle(&mut rng);
-				func.value_list.shuffle(&mut rng);
+// a reference back to a file already being read runs into the depth limit
+fn load_tree(path: &Path, depth: Depth) -> Result<Function<Block>> {
+	let in_file =
+		|e: std::io::Error| std::io::Error::new(e.kind(), format!("{}: {}", path.display(), e));
+	let data = std::fs::read(path).map_err(in_file)?;
+	let tree: TreeFunction = parse_text(&data).map_err(in_file)?;
+
+	tree.resolve(path, depth)
+}
+
+// follows a dotted path of child indices like `0.2.1` down from the main function
+fn select_proto(func: Function<Block>, path: Option<&str>) -> Result<Function<Block>> {
+	let mut func = func;
+
+	for part in path.into_iter().flat_map(|v| v.split('.')) {
+		let child = part
+			.parse::<usize>()
+			.ok()
+			.filter(|v| *v < func.child_list.len());
+
+		match child {
+			// a child only records its source when it differs from the parent's,
+			// and the layout lives on the main function alone
+			Some(index) => {
+				let (_, mut child) = func.child_list.swap_remove(index);
+
+				child.source = child.source.or(func.source);
+				child.layout = func.layout;
+				func = child;
 			}
-			Mutation::Sorted => {
-				func.block_list.sort_by_key(|v| v.label);
-				func.child_list.sort_by_key(|v| Rc::clone(&v.0));
-				func.upval_list.sort_by_key(|v| Rc::clone(&v.0));
-				func.value_list.sort_by_key(|v| Rc::clone(&v.0));
+			None => {
+				let msg = format!("no child proto `{}` in `{}`", part, path.unwrap());
+
+				return Err(std::io::Error::new(ErrorKind::InvalidInput, msg));
 			}
 		}
 	}
+
+	Ok(func)
 }
 
-fn assemble_data(data: &[u8], opt: &[Mutation]) -> Result<()> {
-	let mut func = from_bytes(data).expect("not valid RON");
+fn print_function(func: &Function<Block>, format: Format, indent: Indent) -> String {
+	match (format, indent) {
+		(Format::Ron, Indent::Compact) => {
+			ron::ser::to_string(func).expect("not convertible to RON")
+		}
+		(Format::Ron, _) => {
+			let mut config = PrettyConfig::new();
 
-	try_mutate(&mut func, opt);
+			if let Indent::Width(width) = indent {
+				config = config.with_indentor(" ".repeat(width));
+			}
 
-	let proto = Proto::from(func);
-	let binary = dump_lua_module(&proto)?;
+			to_string_pretty(func, config).expect("not convertible to RON")
+		}
+		(Format::Json, Indent::Compact) => {
+			serde_json::to_string(func).expect("not convertible to JSON")
+		}
+		(Format::Json, Indent::Default) => {
+			serde_json::to_string_pretty(func).expect("not convertible to JSON")
+		}
+		(Format::Json, Indent::Width(width)) => {
+			let indentor = " ".repeat(width);
+			let formatter = PrettyFormatter::with_indent(indentor.as_bytes());
+			let mut ser = serde_json::Serializer::with_formatter(Vec::new(), formatter);
 
-	std::io::stdout().lock().write_all(&binary)
+			func.serialize(&mut ser).expect("not convertible to JSON");
+			String::from_utf8(ser.into_inner()).expect("JSON is always UTF-8")
+		}
+	}
 }
 
-fn disassemble_data(data: &[u8], opt: &[Mutation]) -> Result<()> {
-	let (trail, proto) = load_lua_module(data).expect("not valid Lua 5.4 bytecode");
+// the label `--entry` gave when the function has a block there, 0 when it gave
+// none, a label that isn't there is refused rather than guessed around
+fn entry_label(func: &Function<Block>, entry: Option<u32>) -> Result<u32> {
+	match entry {
+		None => Ok(0),
+		Some(label) if func.block_list.iter().any(|v| v.label == label) => Ok(label),
+		Some(label) => Err(std::io::Error::new(
+			ErrorKind::InvalidInput,
+			format!("no block `{}` to start from in main", label),
+		)),
+	}
+}
+
+// hand edited text is checked as read, before a mutation gets to trip over
+// what it got wrong or the VM gets to run it
+fn check_function(func: &Function<Block>, depth: Depth) -> Result<()> {
+	validate(func, depth).map_err(|error_list| {
+		let msg: Vec<String> = error_list.iter().map(ToString::to_string).collect();
+
+		std::io::Error::new(ErrorKind::InvalidData, msg.join("\n"))
+	})
+}
+
+fn assemble_data(
+	mut func: Function<Block>,
+	opt: &[Mutation],
+	rng: &mut StdRng,
+	output: Option<&Path>,
+	lua: &dyn BytecodeFormat,
+	strip: bool,
+	depth: Depth,
+) -> Result<(MutationReport, usize)> {
+	check_function(&func, depth)?;
+
+	let report = mutate(&mut func, opt, rng, depth)
+		.map_err(|e| std::io::Error::new(ErrorKind::InvalidData, e))?;
+
+	if strip {
+		strip_debug(&mut func, depth)
+			.map_err(|e| std::io::Error::new(ErrorKind::InvalidData, e))?;
+	}
+
+	let binary = assemble_as(func, lua)?;
+
+	write_output(output, &binary)?;
+
+	Ok((report, binary.len()))
+}
+
+// a chunk or a text file in, a chunk out, loaded and written only once
+fn run_passes(
+	data: &[u8],
+	pass_list: &[(String, Pass)],
+	rng: &mut StdRng,
+	output: Option<&Path>,
+	lua: &dyn BytecodeFormat,
+	depth: Depth,
+	progress: bool,
+) -> Result<()> {
+	let mut func = if data.starts_with(LUA_SIGNATURE) {
+		disassemble_as(data, lua).map_err(|e| std::io::Error::new(ErrorKind::InvalidData, e))?
+	} else {
+		let func = parse_function(data)?;
+
+		check_function(&func, depth)?;
+
+		func
+	};
+
+	// each pass is done with every proto before the next starts, so running
+	// them one at a time is the same as all at once
+	for (index, (name, pass)) in pass_list.iter().enumerate() {
+		if progress {
+			eprintln!("pass {}/{}: {}", index + 1, pass_list.len(), name);
+		}
+
+		run_pipeline(&mut func, std::slice::from_ref(pass), rng, depth)
+			.map_err(|e| std::io::Error::new(ErrorKind::InvalidData, e))?;
+	}
+
+	write_output(output, &assemble_as(func, lua)?)
+}
+
+const REPL_HELP: &str = "\
+commands, one per line:
+  <pass>       apply a pass as named for --pipeline, like `fold` or `prune`
+  list [label] print the listing of every proto, or of one block of main
+  stats        summarize blocks, constants and opcodes
+  check        look for what the assembler would refuse
+  save [file]  write RON or JSON by extension, bytecode otherwise
+  quit         leave without saving";
+
+// one command applied to the function in memory, what it has to say is
+// handed back to be printed, loops being found from the block `entry`
+fn repl_command(
+	func: &mut Function<Block>,
+	command: &str,
+	arg: Option<&str>,
+	lua: &dyn BytecodeFormat,
+	rng: &mut StdRng,
+	depth: Depth,
+	entry: u32,
+) -> Result<String> {
+	let invalid = |msg: String| std::io::Error::new(ErrorKind::InvalidInput, msg);
+
+	match command {
+		"help" => Ok(REPL_HELP.to_string()),
+		"list" => match arg {
+			None => Ok(disasm_listing(func, &[], entry)),
+			Some(label) => label
+				.trim_start_matches("Block")
+				.parse()
+				.ok()
+				.and_then(|label| block_listing(func, label, entry))
+				.ok_or_else(|| invalid(format!("no block `{}` in main", label))),
+		},
+		"stats" => Ok(collect_stats(func, entry).to_string()),
+		"check" => check_function(func, depth).map(|_| "no problems".to_string()),
+		"save" => {
+			let name = arg.ok_or_else(|| invalid("file name expected".to_string()))?;
+			let data = match Path::new(name).extension().and_then(|v| v.to_str()) {
+				Some("ron") => print_function(func, Format::Ron, Indent::Default).into_bytes(),
+				Some("json") => print_function(func, Format::Json, Indent::Default).into_bytes(),
+				_ => assemble_as(func.clone(), lua)?,
+			};
+
+			write_output(Some(Path::new(name)), &data)?;
+
+			Ok(format!("wrote {} bytes to {}", data.len(), name))
+		}
+		_ => {
+			let pass: Pass = command.parse().map_err(invalid)?;
+
+			run_pipeline(func, &[pass], rng, depth)
+				.map_err(|e| std::io::Error::new(ErrorKind::InvalidData, e))?;
+
+			let stats = collect_stats(func, entry);
 
+			Ok(format!(
+				"{} blocks, {} instructions, {} nested protos",
+				stats.block_count, stats.inst_count, stats.proto_count
+			))
+		}
+	}
+}
+
+// reads commands off stdin until `quit` or the end of input, a command that
+// fails leaves the function as the pass left it and the prompt comes back
+fn run_repl(
+	data: &[u8],
+	lua: &dyn BytecodeFormat,
+	rng: &mut StdRng,
+	depth: Depth,
+	entry: Option<u32>,
+) -> Result<()> {
+	let (mut func, bytecode) = load_function(data)?;
+	let entry = entry_label(&func, entry)?;
+	let lua = match bytecode {
+		Some(format) => format,
+		None => lua,
+	};
+	let stdin = std::io::stdin();
+	let mut stdout = std::io::stdout();
+
+	loop {
+		write!(stdout, "> ")?;
+		stdout.flush()?;
+
+		let mut line = String::new();
+
+		if stdin.lock().read_line(&mut line)? == 0 {
+			writeln!(stdout)?;
+			break;
+		}
+
+		let mut word_iter = line.split_whitespace();
+		let command = match word_iter.next() {
+			Some("quit") | Some("exit") => break,
+			Some(command) => command,
+			None => continue,
+		};
+
+		match repl_command(&mut func, command, word_iter.next(), lua, rng, depth, entry) {
+			Ok(text) => writeln!(stdout, "{}", text.trim_end())?,
+			Err(e) => eprintln!("error: {}", e),
+		}
+	}
+
+	Ok(())
+}
+
+#[allow(clippy::too_many_arguments)]
+fn disassemble_data(
+	data: &[u8],
+	opt: &[Mutation],
+	rng: &mut StdRng,
+	output: Option<&Path>,
+	format: Format,
+	indent: Indent,
+	path: Option<&str>,
+	lua: &dyn BytecodeFormat,
+	depth: Depth,
+) -> Result<MutationReport> {
+	let (func, trail) =
+		load_chunk(data, lua).map_err(|e| std::io::Error::new(ErrorKind::InvalidData, e))?;
+
+	// some tools append their own metadata, the chunk itself is still fine
 	if !trail.is_empty() {
-		panic!("trailing garbage in Lua file");
+		eprintln!(
+			"warning: ignoring {} trailing bytes after the chunk",
+			trail.len()
+		);
 	}
 
-	let mut func = Function::from(proto);
+	let mut func = select_proto(func, path)?;
+	let report = mutate(&mut func, opt, rng, depth)
+		.map_err(|e| std::io::Error::new(ErrorKind::InvalidData, e))?;
 
-	try_mutate(&mut func, opt);
+	write_output(output, print_function(&func, format, indent).as_bytes())?;
 
-	let config = PrettyConfig::new();
-	let ron = to_string_pretty(&func, config).expect("not convertible to RON");
+	Ok(report)
+}
 
-	std::io::stdout().lock().write_all(ron.as_bytes())
+// goes to stderr so it never mixes with output on stdout, each file of a
+// batch under its own name
+fn print_report(report: &MutationReport, name: Option<&str>, is_batch: bool) {
+	match name {
+		Some(name) if is_batch => eprint!("{}:\n{}", name, report),
+		_ => eprint!("{}", report),
+	}
+}
+
+fn print_progress(index: usize, len: usize, name: Option<&str>) {
+	eprintln!("file {}/{}: {}", index + 1, len, name.unwrap_or("-"));
+}
+
+// on stderr like the report, the change against `--baseline` as a percentage
+fn print_size(size: usize, baseline: Option<u64>, name: Option<&str>, is_batch: bool) {
+	let mut text = format!("{} bytes", size);
+
+	if let Some(base) = baseline {
+		text.push_str(&format!(", {} in the baseline", base));
+
+		if base != 0 {
+			let delta = (size as f64 - base as f64) / base as f64 * 100.0;
+
+			text.push_str(&format!(", {:+.1}%", delta));
+		}
+	}
+
+	match name {
+		Some(name) if is_batch => eprintln!("{}: {}", name, text),
+		_ => eprintln!("{}", text),
+	}
+}
+
+// what `-d` writes and `-a` reads, for tools in other languages to check
+// their output against
+#[cfg(feature = "schema")]
+fn print_schema() -> Result<String> {
+	let schema = schemars::schema_for!(Function<Block>);
+
+	Ok(serde_json::to_string_pretty(&schema).expect("not convertible to JSON"))
+}
+
+#[cfg(not(feature = "schema"))]
+fn print_schema() -> Result<String> {
+	let msg = "built without the `schema` feature";
+
+	Err(std::io::Error::new(ErrorKind::Unsupported, msg))
+}
+
+// a file given up on leaves its thread running, which could still write its
+// output late, so a timeout always ends the run and can't be kept going past
+fn timeout_conflict() -> std::io::Error {
+	std::io::Error::new(
+		ErrorKind::InvalidInput,
+		"--timeout and --keep-going can't be used together",
+	)
+}
+
+// runs `work` on a thread of its own when there is a limit or a panic should
+// only cost the one file, one still going past the limit is left behind,
+// which is fine since the error ends the process
+fn with_timeout<T, F>(
+	timeout: Option<Duration>,
+	isolate: bool,
+	name: Option<&str>,
+	work: F,
+) -> Result<T>
+where
+	T: Send + 'static,
+	F: FnOnce() -> Result<T> + Send + 'static,
+{
+	if timeout.is_none() && !isolate {
+		return work();
+	}
+
+	let (sender, receiver) = mpsc::channel();
+
+	std::thread::spawn(move || sender.send(work()));
+
+	let result = match timeout {
+		Some(limit) => receiver.recv_timeout(limit),
+		None => receiver.recv().map_err(|_| RecvTimeoutError::Disconnected),
+	};
+
+	match result {
+		Ok(result) => result,
+		Err(RecvTimeoutError::Timeout) => {
+			let msg = format!(
+				"{}: gave up after {} s",
+				name.unwrap_or("stdin"),
+				timeout.unwrap_or_default().as_secs()
+			);
+
+			Err(std::io::Error::new(ErrorKind::TimedOut, msg))
+		}
+		// the panic has already been reported by the thread itself
+		Err(RecvTimeoutError::Disconnected) => {
+			let msg = format!("{}: the pass panicked", name.unwrap_or("stdin"));
+
+			Err(std::io::Error::other(msg))
+		}
+	}
+}
+
+// with `--keep-going` a file of a batch that fails is told about on stderr
+// and counted instead of ending the run
+fn skip_failed(
+	result: Result<()>,
+	name: Option<&str>,
+	keep_going: bool,
+	failed: &mut usize,
+) -> Result<()> {
+	match result {
+		Err(err) if keep_going => {
+			eprintln!("{}: {}", name.unwrap_or("stdin"), err);
+			*failed += 1;
+
+			Ok(())
+		}
+		result => result,
+	}
+}
+
+fn verify_roundtrip(data: &[u8], lua: &dyn BytecodeFormat) -> std::result::Result<(), String> {
+	let (_, proto) = lua.load(data).map_err(|e| e.to_string())?;
+	let proto = Proto::from(Function::try_from(proto).map_err(|e| e.to_string())?);
+	let binary = lua.dump(&proto).map_err(|e| e.to_string())?;
+
+	let mismatch = data.iter().zip(&binary).position(|(a, b)| a != b);
+
+	match mismatch {
+		Some(offset) => Err(format!(
+			"roundtrip mismatch at offset {:#x}: expected {:#04x}, got {:#04x}",
+			offset, data[offset], binary[offset]
+		)),
+		None if data.len() != binary.len() => Err(format!(
+			"roundtrip length mismatch: expected {} bytes, got {}",
+			data.len(),
+			binary.len()
+		)),
+		None => Ok(()),
+	}
 }
 
 fn list_help() {
 	println!("usage: lau [options]");
 	println!("  -h | --help                show the help message");
-	println!("  -a | --assemble [file]     assemble a RON file into bytecode");
-	println!("  -d | --disassemble [file]  disassemble a bytecode file into RON");
+	println!("  -a | --assemble [files]    assemble RON or JSON files into bytecode");
+	println!(
+		"  -a --tree [dir]            assemble dir/main.ron along with the child files it names"
+	);
+	println!("  -d | --disassemble [files] disassemble bytecode files into RON");
+	println!("  -o | --output [file]       write results to a file instead of stdout");
 	println!("  -r | --randomize           queue a randomization step");
-	println!("  -ui                        start UI mode");
+	println!("  -ui [file]                 start UI mode on a RON, JSON or bytecode file");
 	println!("  -v | --devirt              devritualize a RON file made by vsecure");
 	println!("  -s | --sort                queue a sorting step");
+	println!(
+		"  --baseline [file]          bytecode file --to-luac compares the size of its output to"
+	);
+	println!("  --calls                    make --dot export which protos make and call which");
+	println!("  --coalesce                 queue a step merging straight line block chains");
+	println!("  --compact                  write -d and -v output on a single line");
+	println!("  --constants [file]         print the constant pool of a bytecode file by index, type and value");
+	println!("  --dce                      queue a step removing stores nothing reads back");
+	println!("  --dedup                    queue a step merging equal constants into one");
+	println!("  --diff [file] [file]       compare the blocks and constants of two RON files");
+	println!("  --dot [file]               export the control flow graph of a RON file as DOT");
+	println!("  --entry [label]            block main starts at for -ui, --list, --repl and --stats, 0 by default");
+	println!("  --extract [label] [file]   print one block of a RON file as a function of its own");
+	println!("  --faithful                 make -d refuse queued steps and chunks that don't reassemble identically");
+	println!("  --flatten                  queue a control flow flattening step");
+	println!("  --format [ron|json]        text format written by -d and -v, read back either way");
+	println!("  --grep [term]              make --list keep blocks with an opcode or operand like this, repeat for more");
+	println!("  --header [file]            show the version and sizes from a bytecode header");
+	println!("  --highlight-opcode [name]  outline the blocks -ui shows that have an instruction like `CALL`");
+	println!("  --hspace [number]          distance -ui leaves between the ranks of its layout, 250 by default");
+	println!("  --indent [number]          indent -d and -v output by this many spaces");
+	println!("  --junk                     queue a step inserting dead instructions into blocks");
+	println!("  --keep-going               go on with the rest of a batch past a file that fails, exiting nonzero after, not with --timeout");
+	println!("  --list [file]              print a plain text listing of a bytecode file");
+	println!("  --lua [5.3|5.4]            bytecode version to read, only 5.4 can be written");
+	println!("  --max-depth [number]       refuse protos nested deeper than this, 200 by default");
+	println!(
+		"  --opaque                   queue a step hiding plain jumps behind opaque predicates"
+	);
+	println!("  --peephole                 queue a step collapsing chains of MOVE through dead registers");
+	println!("  --permute-upvals           queue a step shuffling upvalues, keeping the children capturing them right");
+	println!("  --pipeline [passes] [file] run comma separated passes over a file, write bytecode");
+	println!("  --progress                 tell on stderr which file of a batch and which --pipeline pass is underway");
+	println!("  --proto [path]             only export the child proto at a path like `0.2.1`");
+	println!("  --recursive                make --constants print the pools of nested protos too");
+	println!("  --reformat [file]          rewrite a RON or JSON file in place in the default layout, keeping its format");
+	println!("  --renumber                 queue a step relabeling blocks 0..n from the entry");
+	println!(
+		"  --report                   tell on stderr what the steps queued for -a and -d moved"
+	);
+	println!("  --repl [file]              apply passes to a file one command at a time, `help` lists them");
+	println!("  --schema                   print the JSON schema of the RON and JSON formats");
+	println!("  --seed [number]            seed the randomization steps for reproducible output");
+	println!("  --split-calls              queue a step ending a block after every call with a fixed result count");
+	println!("  --stats [file]             summarize the blocks, constants and opcodes of a bytecode file");
+	println!("  --strip                    leave debug info out of bytecode made by -a");
+	println!("  --timeout [seconds]        give up on a file -a, -d, -v or --pipeline spends longer on than this");
+	println!("  --to-luac [files]          assemble like -a, then tell on stderr how many bytes came out");
+	println!("  --topo                     queue a step ordering blocks by reachability");
+	println!("  --unroll [factor]          queue a step repeating single block numeric for loops this many times");
+	println!("  --verify [file]            check that a bytecode file reassembles identically");
+	println!("  --vspace [number]          distance -ui leaves between the blocks of one rank, 150 by default");
+	println!();
+	println!("for -a, -d, --constants, --dot, --extract, --header, --list, --reformat and --stats, a [file] of `-` or no [file] at all reads from stdin");
+	println!("several [files] given to -a or -d are each written next to themselves as .luac, .ron or .json");
+	println!("RUST_LOG=lau=info or lau=debug has -v tell on stderr what it found and changed");
 }
 
 /* NODES LOGIC */
@@ -105,89 +834,161 @@ of instructions is gonna be turned into a list of nodes
 /* UI APP LOGIC */
 use eframe::egui;
 
-struct BlocksViewer;
+// deleting a block has to drop it from the label map of the app as well
+struct BlocksViewer<'a> {
+	node_map: &'a mut HashMap<u32, NodeId>,
+	// the block clicked last and the blocks its edge targets
+	selected: Option<NodeId>,
+	successor_list: Vec<NodeId>,
+	// the blocks as they were before the first edit made this frame
+	before_edit: Option<Vec<Block>>,
+	// the longest body shown, which gets the strongest color
+	max_body: usize,
+	// the blocks with an instruction of the opcode typed in the highlight box
+	marked: HashSet<NodeId>,
+	// how the shown proto's instructions read, once there is one
+	inst_text: Option<InstructionText>,
+}
 
-impl Block {
-	fn name(&self) -> String {
-		return format!("Block {}", self.label);
-	}
+const PIN_COLOR: Color32 = Color32::from_rgb(255, 0, 0);
+const HIGHLIGHT_COLOR: Color32 = Color32::from_rgb(255, 200, 0);
+const BODY_COLOR: Color32 = Color32::from_rgb(255, 90, 0);
+const MARK_COLOR: Color32 = Color32::from_rgb(0, 200, 255);
 
-	fn is_unconditionnal(&self) -> bool {
-		match &self.edge {
-			Control::Unconditional(_) => {
-				return true;
-			}
+// between ranks and between the blocks of a rank, unless `--hspace` or
+// `--vspace` say otherwise
+const DEFAULT_SPACING: egui::Vec2 = egui::vec2(250.0, 150.0);
 
-			_ => {
-				return false;
-			}
-		}
-	}
+// bodies fade in with their length, so trampolines stay pale next to the
+// blocks that do the work
+fn body_color(len: usize, max_body: usize) -> Color32 {
+	const MIN_ALPHA: f32 = 8.0;
+	const MAX_ALPHA: f32 = 140.0;
 
-	fn get_target_labels(&self) -> Vec<u32> {
-		let mut ret: Vec<u32> = Vec::new();
+	let t = len as f32 / max_body.max(1) as f32;
+	let alpha = MIN_ALPHA + (MAX_ALPHA - MIN_ALPHA) * t;
 
-		match &self.edge {
-			Control::Condition(_, on_true, on_false) => {
-				match on_true {
-					Target::Label(to_label) => {
-						ret.push(*to_label);
-					}
-					_ => {}
-				}
+	Color32::from_rgba_unmultiplied(BODY_COLOR.r(), BODY_COLOR.g(), BODY_COLOR.b(), alpha as u8)
+}
 
-				match on_false {
-					Target::Label(to_label) => {
-						ret.push(*to_label);
-					}
+impl BlocksViewer<'_> {
+	fn is_highlighted(&self, node: NodeId) -> bool {
+		self.selected == Some(node) || self.successor_list.contains(&node)
+	}
 
-					_ => {}
-				}
-			}
+	fn record_edit(&mut self, snarl: &egui_snarl::Snarl<Block>) {
+		if self.before_edit.is_none() {
+			self.before_edit = Some(snarl_blocks(snarl, self.node_map));
+		}
+	}
+}
 
-			Control::Unconditional(target) => match target {
-				Target::Label(to_label) => {
-					ret.push(*to_label);
-				}
+// the blocks the label map still holds, nodes keep the slot they were inserted
+// in so the original layout order is kept as well
+fn snarl_blocks(snarl: &egui_snarl::Snarl<Block>, node_map: &HashMap<u32, NodeId>) -> Vec<Block> {
+	snarl
+		.nodes_ids_data()
+		.filter(|(id, node)| node_map.get(&node.value.label) == Some(id))
+		.map(|(_, node)| node.value.clone())
+		.collect()
+}
 
-				_ => {}
-			},
+// the synthetic sink every returning block is wired to, it is never put in
+// the label map so nothing else can jump to it or save it
+const EXIT_LABEL: u32 = u32::MAX;
 
-			Control::Loop(_, on_false, on_true) => {
-				match on_true {
-					Target::Label(to_label) => {
-						ret.push(*to_label);
-					}
-					_ => {}
-				}
+fn is_return(edge: &Control) -> bool {
+	matches!(
+		edge,
+		Control::Return(..) | Control::Return0(_) | Control::Return1(_)
+	)
+}
 
-				match on_false {
-					Target::Label(to_label) => {
-						ret.push(*to_label);
-					}
+// what an output pin leads to, worded after the listing
+fn pin_label(edge: &Control, output: usize) -> String {
+	let target = |t: &Target| match t {
+		Target::Label(label) => label.to_string(),
+		Target::Undefined(offset) => format!("offset {}", offset),
+	};
 
-					_ => {}
-				}
-			}
+	match (edge, output) {
+		(Control::Unconditional(to) | Control::LFalseSkip(_, to), _) => {
+			format!("-> {}", target(to))
+		}
+		(Control::Condition(cond, on_true, _), 0) => {
+			format!("if {}: {}", condition_text(cond), target(on_true))
+		}
+		(Control::Condition(_, _, on_false), _) => format!("else: {}", target(on_false)),
+		(Control::Loop(Loop::NumericPrep(_), _, on_skip), 0) => {
+			format!("skip: {}", target(on_skip))
+		}
+		(Control::Loop(Loop::NumericPrep(_), on_body, _), _) => {
+			format!("body: {}", target(on_body))
+		}
+		// the prep always jumps to the iterator call
+		(Control::Loop(Loop::IteratorPrep(_), _, on_call), 0) => {
+			format!("call: {}", target(on_call))
+		}
+		(Control::Loop(Loop::IteratorPrep(_), next, _), _) => format!("unused: {}", target(next)),
+		(Control::Loop(_, _, on_body), 0) => format!("loop: {}", target(on_body)),
+		(Control::Loop(_, on_exit, _), _) => format!("exit: {}", target(on_exit)),
+		// the exit wire says how many values go back, which a call site of this
+		// function gets to see
+		(Control::Return(Reg::R(a), Group::Many, _, _), _) => {
+			format!("return r{}..top, all values", a)
+		}
+		(Control::Return(_, Group::Exactly(0), _, _) | Control::Return0(_), _) => {
+			"return, no values".to_string()
+		}
+		(Control::Return(Reg::R(a), Group::Exactly(1), _, _) | Control::Return1(Reg::R(a)), _) => {
+			format!("return r{}, 1 value", a)
+		}
+		(Control::Return(Reg::R(a), Group::Exactly(n), _, _), _) => format!(
+			"return r{}..r{}, {} values",
+			a,
+			u32::from(*a) + u32::from(*n) - 1,
+			n
+		),
+	}
+}
 
-			Control::LFalseSkip(_, target) => match target {
-				Target::Label(to_label) => {
-					ret.push(*to_label);
-				}
+// points the edge behind `from` at `to`, an output pin only ever has one wire
+fn redirect_pin(from: OutPinId, to: NodeId, snarl: &mut egui_snarl::Snarl<Block>) {
+	let label = match snarl.get_node(to) {
+		Some(blk) if blk.label != EXIT_LABEL => blk.label,
+		_ => return,
+	};
+	// output pins are numbered in the order `targets_mut` lists the targets
+	let target = snarl
+		.get_node_mut(from.node)
+		.and_then(|blk| blk.targets_mut().into_iter().nth(from.output));
+
+	if let Some(target) = target {
+		*target = Target::Label(label);
+
+		snarl.drop_outputs(from);
+		snarl.connect(from, InPinId { node: to, input: 0 });
+	}
+}
 
-				_ => {}
-			},
-			_ => {}
+// what the node graph needs from a block on top of the library
+trait BlockNode {
+	fn name(&self) -> String;
+	fn target_labels_to_nodeid(&self, map: &HashMap<u32, NodeId>) -> Vec<NodeId>;
+}
+
+impl BlockNode for Block {
+	fn name(&self) -> String {
+		if self.label == EXIT_LABEL {
+			return "EXIT".to_string();
 		}
 
-		ret
+		format!("Block {}", self.label)
 	}
 
-	fn target_labels_to_nodeid(&self, node_map: HashMap<u32, NodeId>) -> Vec<NodeId> {
+	fn target_labels_to_nodeid(&self, map: &HashMap<u32, NodeId>) -> Vec<NodeId> {
 		let mut ret: Vec<NodeId> = Vec::new();
 
-		let map = node_map;
-
 		for target in self.get_target_labels() {
 			if let Some(tg_id) = map.get(&target) {
 				ret.push(*tg_id);
@@ -198,58 +999,121 @@ impl Block {
 	}
 }
 
-impl SnarlViewer<Block> for BlocksViewer {
+impl SnarlViewer<Block> for BlocksViewer<'_> {
 	fn title(&mut self, node: &Block) -> String {
-		return node.name();
+		node.name()
+	}
+
+	fn show_header(
+		&mut self,
+		node: NodeId,
+		_inputs: &[egui_snarl::InPin],
+		_outputs: &[egui_snarl::OutPin],
+		ui: &mut egui::Ui,
+		_scale: f32,
+		snarl: &mut egui_snarl::Snarl<Block>,
+	) {
+		let mut text = egui::RichText::new(self.title(&snarl[node]));
+
+		if self.is_highlighted(node) {
+			text = text.color(HIGHLIGHT_COLOR).strong();
+		}
+
+		ui.label(text);
 	}
 
 	fn outputs(&mut self, node: &Block) -> usize {
-		// depending on the edge, a block can be linked to zero , one or two nodes
+		// depending on the edge, a block can be linked to one or two nodes, a
+		// return goes to the exit which leads nowhere
+		if node.label == EXIT_LABEL {
+			return 0;
+		}
+
 		match node.edge {
-			Control::Unconditional(_) => {
-				return 1;
-			}
-			Control::Condition(_, _, _) => {
-				return 2;
-			}
-			Control::Loop(_, _, _) => {
-				return 2;
-			}
-			Control::Return(_, _, _, _) => {
-				return 0;
-			}
-			Control::Return0 => {
-				return 0;
-			}
-			Control::Return1(_) => {
-				return 0;
-			}
-			Control::LFalseSkip(_, _) => {
-				return 1;
-			}
-			_ => {
-				return 0;
-			}
+			Control::Unconditional(_) => 1,
+			Control::Condition(_, _, _) => 2,
+			Control::Loop(_, _, _) => 2,
+			Control::Return(_, _, _, _) => 1,
+			Control::Return0(_) => 1,
+			Control::Return1(_) => 1,
+			Control::LFalseSkip(_, _) => 1,
 		}
 	}
 
-	fn inputs(&mut self, node: &Block) -> usize {
+	fn inputs(&mut self, _node: &Block) -> usize {
 		// a node always have 1 input
-		return 1;
+		1
+	}
+
+	fn has_body(&mut self, node: &Block) -> bool {
+		node.label != EXIT_LABEL
+	}
+
+	fn show_body(
+		&mut self,
+		node: NodeId,
+		_inputs: &[egui_snarl::InPin],
+		_outputs: &[egui_snarl::OutPin],
+		ui: &mut egui::Ui,
+		_scale: f32,
+		snarl: &mut egui_snarl::Snarl<Block>,
+	) {
+		const MAX_BODY_HEIGHT: f32 = 200.0;
+
+		if let Some(block) = snarl.get_node(node) {
+			let fill = body_color(block.body.len(), self.max_body);
+			let mut frame = egui::Frame::none().fill(fill);
+
+			if self.marked.contains(&node) {
+				frame = frame
+					.stroke(egui::Stroke::new(2.0, MARK_COLOR))
+					.inner_margin(2.0);
+			}
+
+			// instructions as `--list` prints them, the edge in the RON's own
+			// notation since no one opcode stands for it
+			frame.show(ui, |ui| {
+				egui::ScrollArea::vertical()
+					.id_salt(node)
+					.max_height(MAX_BODY_HEIGHT)
+					.show(ui, |ui| {
+						for inst in &block.body {
+							let text = match &self.inst_text {
+								Some(inst_text) => inst_text.text(inst),
+								None => inst.opcode().name().to_string(),
+							};
+
+							ui.label(egui::RichText::new(text).monospace());
+						}
+
+						let text = ron::ser::to_string(&block.edge).unwrap_or_default();
+
+						ui.label(egui::RichText::new(text).monospace().strong());
+					});
+			});
+		}
 	}
 
 	fn show_input(
 		&mut self,
 		pin: &egui_snarl::InPin,
 		ui: &mut egui::Ui,
-		scale: f32,
+		_scale: f32,
 		snarl: &mut egui_snarl::Snarl<Block>,
 	) -> egui_snarl::ui::PinInfo {
-		if let Some(block) = snarl.get_node(pin.id.node) {
-			return egui_snarl::ui::PinInfo::circle().with_fill(Color32::from_rgb(255, 0, 0));
+		if let Some(_block) = snarl.get_node(pin.id.node) {
+			// wires take the mix of both pin colors, so an edge out of the
+			// selected block is only emphasized end to end
+			let fill = if self.successor_list.contains(&pin.id.node) {
+				HIGHLIGHT_COLOR
+			} else {
+				PIN_COLOR
+			};
+
+			egui_snarl::ui::PinInfo::circle().with_fill(fill)
 		} else {
 			ui.label("Dead Input");
-			return egui_snarl::ui::PinInfo::circle();
+			egui_snarl::ui::PinInfo::circle()
 		}
 	}
 
@@ -257,32 +1121,87 @@ impl SnarlViewer<Block> for BlocksViewer {
 		&mut self,
 		pin: &egui_snarl::OutPin,
 		ui: &mut egui::Ui,
-		scale: f32,
+		_scale: f32,
 		snarl: &mut egui_snarl::Snarl<Block>,
 	) -> egui_snarl::ui::PinInfo {
 		if let Some(block) = snarl.get_node(pin.id.node) {
-			match block.edge {
-				Control::Unconditional(_) => {
-					ui.label("Unconditional");
-					return egui_snarl::ui::PinInfo::star();
-				}
-				Control::Condition(_, _, _) => {
-					ui.label("Conditional");
-					return egui_snarl::ui::PinInfo::square();
-				}
-				Control::Loop(_, _, _) | Control::LFalseSkip(_, _) => {
-					ui.label("Loop");
-					return egui_snarl::ui::PinInfo::circle();
-				}
-				_ => {
-					// no render for return anyways
-					ui.label("Unknown");
-					return egui_snarl::ui::PinInfo::circle();
-				}
+			ui.label(pin_label(&block.edge, pin.id.output));
+
+			let info = match block.edge {
+				Control::Unconditional(_) => egui_snarl::ui::PinInfo::star(),
+				Control::Condition(_, _, _) => egui_snarl::ui::PinInfo::square(),
+				_ => egui_snarl::ui::PinInfo::circle(),
+			};
+
+			if self.selected == Some(pin.id.node) {
+				info.with_fill(HIGHLIGHT_COLOR)
+			} else {
+				info
 			}
 		} else {
 			ui.label("Dead output");
-			return egui_snarl::ui::PinInfo::circle();
+			egui_snarl::ui::PinInfo::circle()
+		}
+	}
+
+	fn connect(
+		&mut self,
+		from: &egui_snarl::OutPin,
+		to: &egui_snarl::InPin,
+		snarl: &mut egui_snarl::Snarl<Block>,
+	) {
+		self.record_edit(snarl);
+		redirect_pin(from.id, to.id.node, snarl);
+	}
+
+	fn has_node_menu(&mut self, node: &Block) -> bool {
+		node.label != EXIT_LABEL
+	}
+
+	fn show_node_menu(
+		&mut self,
+		node: NodeId,
+		_inputs: &[egui_snarl::InPin],
+		outputs: &[egui_snarl::OutPin],
+		ui: &mut egui::Ui,
+		_scale: f32,
+		snarl: &mut egui_snarl::Snarl<Block>,
+	) {
+		let mut label_list: Vec<(u32, NodeId)> =
+			self.node_map.iter().map(|(k, v)| (*k, *v)).collect();
+
+		label_list.sort_unstable_by_key(|v| v.0);
+
+		// a return always goes to the exit
+		let returns = is_return(&snarl[node].edge);
+
+		for pin in outputs.iter().filter(|_| !returns) {
+			let text = match (outputs.len(), pin.id.output) {
+				(1, _) => "Redirect edge",
+				(_, 0) => "Redirect true edge",
+				_ => "Redirect false edge",
+			};
+
+			ui.menu_button(text, |ui| {
+				egui::ScrollArea::vertical().show(ui, |ui| {
+					for &(label, to) in &label_list {
+						if ui.button(format!("Block {}", label)).clicked() {
+							self.record_edit(snarl);
+							redirect_pin(pin.id, to, snarl);
+							ui.close_menu();
+						}
+					}
+				});
+			});
+		}
+
+		if ui.button("Delete block").clicked() {
+			self.record_edit(snarl);
+
+			let blk = snarl.remove_node(node);
+
+			self.node_map.remove(&blk.label);
+			ui.close_menu();
 		}
 	}
 }
@@ -290,270 +1209,647 @@ impl SnarlViewer<Block> for BlocksViewer {
 struct EApp {
 	snarl: egui_snarl::Snarl<Block>,
 	snarl_ui_id: Option<egui::Id>,
+	// centered on by the next frame after the view is reset
+	focus: Option<NodeId>,
+	selected: Option<NodeId>,
+	// the label typed in the search box, and why the last jump failed
+	search: String,
+	search_error: Option<String>,
+	// the opcode whose blocks are outlined, as `--highlight-opcode` gave it
+	highlight: String,
+	// the distance between ranks and between the blocks of a rank
+	spacing: egui::Vec2,
 	style: egui_snarl::ui::SnarlStyle,
 	file_path: String,
 	node_map: HashMap<u32, NodeId>,
+	// the function as loaded, the blocks of the proto at `proto` live on in
+	// the snarl while it is shown
+	func: Option<Function<Block>>,
+	proto: Vec<usize>,
+	// whole functions as they were before each edit, and as they were before
+	// each undo, the oldest first
+	undo_list: Vec<Function<Block>>,
+	redo_list: Vec<Function<Block>>,
+	exit: Option<NodeId>,
+	// set when the file was a chunk, saving then writes one back
+	bytecode: Option<&'static dyn BytecodeFormat>,
+	// the label `--entry` gave, laid out from in place of block `0`
+	entry: Option<u32>,
 }
 
 impl EApp {
-	pub fn new(cc: &eframe::CreationContext<'_>) -> Self {
+	pub fn new(_cc: &eframe::CreationContext<'_>) -> Self {
 		let mut snarl: egui_snarl::Snarl<Block> = egui_snarl::Snarl::new();
 		let style = egui_snarl::ui::SnarlStyle::new();
 
 		snarl.insert_node(
 			egui::pos2(0.0, 0.0),
-			Block::new(1, Vec::new(), Control::Unconditional(Target::Label(10))),
+			BlockBuilder::new()
+				.label(1)
+				.edge(Control::Unconditional(Target::Label(10)))
+				.build(),
 		);
 		let file_path = String::new();
 		let node_map = HashMap::new();
-		return EApp {
+		EApp {
 			snarl,
 			snarl_ui_id: None,
+			focus: None,
+			selected: None,
+			search: String::new(),
+			search_error: None,
+			highlight: String::new(),
+			spacing: DEFAULT_SPACING,
 			style,
 			file_path,
 			node_map,
-		};
+			func: None,
+			proto: Vec::new(),
+			undo_list: Vec::new(),
+			redo_list: Vec::new(),
+			exit: None,
+			bytecode: None,
+			entry: None,
+		}
 	}
 
-	pub fn set_file(&mut self, fl: String) -> () {
+	pub fn set_file(&mut self, fl: String) {
 		self.file_path = fl;
 	}
 
-	fn assign_node_levels(&mut self) -> HashMap<NodeId, u32> {
-		let mut levels: HashMap<NodeId, u32> = HashMap::new();
-		let mut visited: HashSet<NodeId> = HashSet::new();
-		let mut queue: VecDeque<(NodeId, u32)> = VecDeque::new();
+	// egui-snarl only fits the view to the graph the first time it is shown
+	// under an id, so a fresh id is how the view gets reset
+	fn reset_view(&mut self, focus: Option<NodeId>) {
+		let id = self.snarl_ui_id.unwrap_or_else(|| egui::Id::new("snarl"));
+
+		self.snarl_ui_id = Some(id.with("view"));
+		self.focus = focus;
+	}
+
+	fn go_to_label(&mut self) {
+		// titles read `Block n`, so pasting one works as well
+		let text = self.search.trim();
+		let text = text.strip_prefix("Block").unwrap_or(text).trim();
+
+		self.search_error = match text.parse::<u32>() {
+			Ok(label) => match self.node_map.get(&label).copied() {
+				Some(node) => {
+					self.reset_view(Some(node));
+					None
+				}
+				None => Some(format!("no block labeled {}", label)),
+			},
+			Err(_) => Some(format!("`{}` is not a block label", text)),
+		};
+	}
+
+	fn max_body(&self) -> usize {
+		self.node_map
+			.values()
+			.filter_map(|v| self.snarl.get_node(*v))
+			.map(|v| v.body.len())
+			.max()
+			.unwrap_or_default()
+	}
+
+	// every block with an instruction named like the highlight box says, in
+	// any case, the edges left out since no one opcode stands for them
+	fn marked_nodes(&self) -> HashSet<NodeId> {
+		let name = self.highlight.trim();
+
+		if name.is_empty() {
+			return HashSet::new();
+		}
+
+		self.node_map
+			.values()
+			.copied()
+			.filter(|v| {
+				self.snarl.get_node(*v).is_some_and(|blk| {
+					blk.body
+						.iter()
+						.any(|ir| ir.opcode().name().eq_ignore_ascii_case(name))
+				})
+			})
+			.collect()
+	}
+
+	// the names the shown proto's instructions refer to stay behind in `func`
+	fn inst_text(&self) -> Option<InstructionText> {
+		let func = self.func.as_ref()?;
+		let proto = self
+			.proto
+			.iter()
+			.fold(func, |func, &index| &func.child_list[index].1);
+
+		Some(InstructionText::new(proto))
+	}
+
+	fn show_snarl(&mut self, ui: &mut egui::Ui) {
+		let id = self.snarl_ui_id.unwrap_or_else(|| egui::Id::new("snarl"));
+		let focus = self.focus.take();
+
+		// showing the focused block alone for a frame makes that first fit land
+		// on it, the whole graph then picks up the view stored under the id
+		if let Some(node) = focus.and_then(|v| self.snarl.get_node_info(v)) {
+			let mut probe = egui_snarl::Snarl::new();
+			let mut viewer = BlocksViewer {
+				node_map: &mut HashMap::new(),
+				selected: None,
+				successor_list: Vec::new(),
+				before_edit: None,
+				max_body: self.max_body(),
+				marked: HashSet::new(),
+				inst_text: self.inst_text(),
+			};
+
+			probe.insert_node(node.pos, node.value.clone());
+			probe.show(&mut viewer, &self.style, id, ui);
+			ui.ctx().request_repaint();
+
+			return;
+		}
+
+		let successor_list = self
+			.selected
+			.and_then(|v| self.snarl.get_node(v))
+			.map_or_else(Vec::new, |blk| {
+				let exit = self.exit.filter(|_| is_return(&blk.edge));
+
+				blk.target_labels_to_nodeid(&self.node_map)
+					.into_iter()
+					.chain(exit)
+					.collect()
+			});
+		let max_body = self.max_body();
+		let marked = self.marked_nodes();
+		let inst_text = self.inst_text();
+		let mut viewer = BlocksViewer {
+			node_map: &mut self.node_map,
+			selected: self.selected,
+			successor_list,
+			before_edit: None,
+			max_body,
+			marked,
+			inst_text,
+		};
+
+		self.snarl.show(&mut viewer, &self.style, id, ui);
+
+		if let Some(block_list) = viewer.before_edit {
+			self.push_undo(block_list);
+		}
+
+		// the selection is only known after the snarl handled the clicks, it
+		// shows up highlighted from the next frame on
+		let selected = egui_snarl::Snarl::<Block>::get_selected_nodes(id, ui);
+
+		if self.selected != selected.last().copied() {
+			self.selected = selected.last().copied();
+			ui.ctx().request_repaint();
+		}
+	}
+
+	// the label given by `--entry`, which was checked to be there, or else 0
+	// when there is one, otherwise the first block nothing jumps to and the
+	// smallest label as a last resort
+	fn find_entry(&self) -> Option<NodeId> {
+		// `--entry` is about the main function only
+		if let Some(label) = self.entry.filter(|_| self.proto.is_empty()) {
+			return self.node_map.get(&label).copied();
+		}
+
+		if let Some(node_id) = self.node_map.get(&0) {
+			return Some(*node_id);
+		}
+
+		let target_set: HashSet<u32> = self
+			.node_map
+			.values()
+			.filter_map(|v| self.snarl.get_node(*v))
+			.flat_map(Block::get_target_labels)
+			.collect();
+		let mut label_list: Vec<u32> = self.node_map.keys().copied().collect();
+
+		label_list.sort_unstable();
+
+		label_list
+			.iter()
+			.find(|v| !target_set.contains(v))
+			.or_else(|| label_list.first())
+			.map(|v| self.node_map[v])
+	}
+
+	// layered layout: back edges found by a DFS from the entry are ignored so that
+	// a longest-path layering can rank the rest, then a few barycenter sweeps order
+	// the nodes of each rank to cut down on crossings
+	fn layout_layered(&mut self) {
+		const SWEEP_COUNT: usize = 4;
+
+		let spacing = self.spacing;
+
+		if self.node_map.is_empty() {
+			eprintln!("warning: no blocks to lay out");
+			return;
+		}
+
+		let mut label_list: Vec<u32> = self.node_map.keys().copied().collect();
+
+		label_list.sort_unstable();
+
+		let node_list: Vec<NodeId> = label_list.iter().map(|v| self.node_map[v]).collect();
+		let succ_map: HashMap<NodeId, Vec<NodeId>> = node_list
+			.iter()
+			.map(|&node_id| {
+				let succ = self
+					.snarl
+					.get_node(node_id)
+					.map(|blk| blk.target_labels_to_nodeid(&self.node_map))
+					.unwrap_or_default();
+
+				(node_id, succ)
+			})
+			.collect();
+
+		// forward edges only, an edge to a node still on the DFS stack closes a loop
+		let mut edge_list: Vec<(NodeId, NodeId)> = Vec::new();
+		let mut visited: HashSet<NodeId> = HashSet::new();
+		let mut on_stack: HashSet<NodeId> = HashSet::new();
+		let entry = self.find_entry();
+		let root_list = entry.iter().chain(node_list.iter());
+
+		for &root in root_list {
+			if !visited.insert(root) {
+				continue;
+			}
+
+			let mut stack = vec![(root, 0)];
+
+			on_stack.insert(root);
+
+			while let Some((node_id, index)) = stack.pop() {
+				match succ_map[&node_id].get(index) {
+					Some(&next) => {
+						stack.push((node_id, index + 1));
+
+						if on_stack.contains(&next) {
+							continue;
+						}
+
+						edge_list.push((node_id, next));
+
+						if visited.insert(next) {
+							on_stack.insert(next);
+							stack.push((next, 0));
+						}
+					}
+					None => {
+						on_stack.remove(&node_id);
+					}
+				}
+			}
+		}
+
+		let mut forward: HashMap<NodeId, Vec<NodeId>> = HashMap::new();
+		let mut backward: HashMap<NodeId, Vec<NodeId>> = HashMap::new();
+
+		for &(from, to) in &edge_list {
+			forward.entry(from).or_default().push(to);
+			backward.entry(to).or_default().push(from);
+		}
+
+		// longest path over the acyclic part in topological order
+		let mut in_degree: HashMap<NodeId, usize> = node_list
+			.iter()
+			.map(|v| (*v, backward.get(v).map_or(0, Vec::len)))
+			.collect();
+		let mut rank: HashMap<NodeId, usize> = node_list.iter().map(|v| (*v, 0)).collect();
+
+		let mut queue: VecDeque<NodeId> = node_list
+			.iter()
+			.filter(|v| in_degree[v] == 0)
+			.copied()
+			.collect();
+
+		while let Some(node_id) = queue.pop_front() {
+			for to in forward.get(&node_id).into_iter().flatten() {
+				let next_rank = rank[&node_id] + 1;
+				let to_rank = rank.get_mut(to).unwrap();
+
+				*to_rank = (*to_rank).max(next_rank);
+
+				let degree = in_degree.get_mut(to).unwrap();
+
+				*degree -= 1;
+
+				if *degree == 0 {
+					queue.push_back(*to);
+				}
+			}
+		}
+
+		let rank_count = rank.values().max().map_or(0, |v| v + 1);
+		let mut layer_list: Vec<Vec<NodeId>> = vec![Vec::new(); rank_count];
+
+		for node_id in &node_list {
+			layer_list[rank[node_id]].push(*node_id);
+		}
+
+		// alternate sweeps, each node moves to the average order of its neighbours
+		// in the rank it was swept from
+		let mut order: HashMap<NodeId, f32> = HashMap::new();
+
+		for layer in &layer_list {
+			for (i, node_id) in layer.iter().enumerate() {
+				order.insert(*node_id, i as f32);
+			}
+		}
+
+		for sweep in 0..SWEEP_COUNT {
+			let downward = sweep % 2 == 0;
+			let rank_list: Vec<usize> = if downward {
+				(1..rank_count).collect()
+			} else {
+				(0..rank_count.saturating_sub(1)).rev().collect()
+			};
+
+			for r in rank_list {
+				let layer = &mut layer_list[r];
+				let barycenter = |node_id: &NodeId| {
+					let (adjacent, other) = if downward {
+						(&backward, r - 1)
+					} else {
+						(&forward, r + 1)
+					};
+					let neighbour_list: Vec<f32> = adjacent
+						.get(node_id)
+						.into_iter()
+						.flatten()
+						.filter(|v| rank[v] == other)
+						.map(|v| order[v])
+						.collect();
+
+					if neighbour_list.is_empty() {
+						order[node_id]
+					} else {
+						neighbour_list.iter().sum::<f32>() / neighbour_list.len() as f32
+					}
+				};
+
+				let mut keyed: Vec<(f32, NodeId)> =
+					layer.iter().map(|v| (barycenter(v), *v)).collect();
+
+				keyed.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+				*layer = keyed.into_iter().map(|v| v.1).collect();
+
+				for (i, node_id) in layer.iter().enumerate() {
+					order.insert(*node_id, i as f32);
+				}
+			}
+		}
+
+		for (r, layer) in layer_list.iter().enumerate() {
+			for (i, node_id) in layer.iter().enumerate() {
+				if let Some(node) = self.snarl.get_node_info_mut(*node_id) {
+					node.pos = egui::pos2(r as f32 * spacing.x, i as f32 * spacing.y);
+				}
+			}
+		}
+
+		// the exit sits past the last rank
+		if let Some(node) = self.exit.and_then(|v| self.snarl.get_node_info_mut(v)) {
+			node.pos = egui::pos2(rank_count as f32 * spacing.x, 0.0);
+		}
+	}
+
+	pub fn populate_map(&mut self) {
+		let data = std::fs::read(&self.file_path).expect("Incorrect file path");
+		let (func, bytecode) = load_function(&data).unwrap_or_else(|e| panic!("{}", e));
+
+		self.func = Some(func);
+		self.bytecode = bytecode;
+		self.load_proto(Vec::new());
+	}
+
+	// the file as it is on disk now, which a bad file leaves alone instead of
+	// closing the app, the shown proto stays if the file still has it
+	pub fn reload(&mut self) {
+		let loaded = std::fs::read(&self.file_path).and_then(|v| load_function(&v));
+		let loaded = loaded.and_then(|(func, bytecode)| {
+			entry_label(&func, self.entry)?;
+
+			Ok((func, bytecode))
+		});
+		let (func, bytecode) = match loaded {
+			Ok(loaded) => loaded,
+			Err(e) => {
+				eprintln!("error: could not reload {}: {}", self.file_path, e);
+				return;
+			}
+		};
+		let path = if proto_paths(&func).iter().any(|v| v.0 == self.proto) {
+			self.proto.clone()
+		} else {
+			Vec::new()
+		};
+
+		// the history is of a function that is gone
+		self.func = Some(func);
+		self.bytecode = bytecode;
+		self.undo_list.clear();
+		self.redo_list.clear();
+
+		let block_list = self.load_proto(path);
+
+		self.connect_blocks(block_list);
+		self.reset_view(None);
+	}
+
+	// a fresh snarl holding the blocks of the proto at `path`, which are taken
+	// out of `func` until another proto is shown
+	fn load_proto(&mut self, path: Vec<usize>) -> Vec<Block> {
+		let func = self.func.as_mut().expect("no function loaded");
+		let block_list = std::mem::take(&mut nested_proto(func, &path).block_list);
+		let mut map: HashMap<u32, NodeId> = HashMap::new();
+
+		self.snarl = egui_snarl::Snarl::new();
+
+		// farm the data
+		for block in block_list.iter().cloned() {
+			let block_lbl = block.label;
+			let id = self.snarl.insert_node(egui::pos2(0.0, 0.0), block);
+			map.insert(block_lbl, id);
+		}
+
+		let exit = Block::new(EXIT_LABEL, Vec::new(), Control::Return0(Reg::R(0)));
 
-		let map = self.node_map.clone();
+		self.exit = Some(self.snarl.insert_node(egui::pos2(0.0, 0.0), exit));
+		self.node_map = map;
+		self.selected = None;
+		self.proto = path;
 
-		// Start traversal from the first node (assuming it's labeled 0 or choose another)
-		queue.push_back((*map.get(&0).unwrap(), 0));
+		block_list
+	}
 
-		while let Some((node_id, level)) = queue.pop_front() {
-			if !visited.insert(node_id) {
-				continue; // Skip already visited nodes
-			}
+	// the shown blocks go back where they came from before the proto at `path`
+	// takes their place, already wired and laid out
+	fn select_proto(&mut self, path: Vec<usize>) {
+		let block_list = self.snarl_blocks();
+		let func = self.func.as_mut().expect("no function loaded");
 
-			levels.insert(node_id, level);
+		nested_proto(func, &self.proto).block_list = block_list;
 
-			// Retrieve node outputs and add unvisited neighbors to the queue
-			if let Some(edge) = self.snarl.get_node(node_id) {
-				for child_edge in edge.get_target_labels() {
-					if let Some(target_node) = map.get(&child_edge) {
-						if !visited.contains(&target_node) {
-							queue.push_back((*target_node, level + 1));
-						}
-					}
-				}
-			}
-		}
+		let block_list = self.load_proto(path);
 
-		levels
+		self.connect_blocks(block_list);
+		self.reset_view(None);
 	}
 
-	fn build_tree(
-		&mut self,
-		visited: &mut HashSet<NodeId>,
-		root_idx: NodeId,
-		start_row: usize,
-		start_col: usize,
-	) -> usize {
-		const ROW_DIST: usize = 10;
-		const NODE_DIST: usize = 100;
-
-		let x = start_row * ROW_DIST;
-		let y = start_col * NODE_DIST;
-		let mut max_col = start_col;
-
-		if let Some(blk) = self.snarl.get_node_info_mut(root_idx) {
-			blk.pos = egui::pos2(x as f32, y as f32);
-			let node_ids = blk.value.target_labels_to_nodeid(self.node_map.clone());
-			node_ids.iter().enumerate().for_each(|(i, node_id)| {
-				if visited.contains(node_id) {
-					return;
-				}
+	fn snarl_blocks(&self) -> Vec<Block> {
+		snarl_blocks(&self.snarl, &self.node_map)
+	}
 
-				visited.insert(*node_id);
+	// a new edit makes whatever was undone before unreachable
+	fn push_undo(&mut self, block_list: Vec<Block>) {
+		const UNDO_LIMIT: usize = 100;
 
-				// calculate node row :
-				let rs = 100 * i / node_ids.len();
+		let mut func = self.func.clone().expect("no function loaded");
 
-				let curr_max_col = self.build_tree(visited, *node_id, rs, start_col + 2 * i);
-				if curr_max_col > max_col {
-					max_col = curr_max_col;
-				}
-			});
+		nested_proto(&mut func, &self.proto).block_list = block_list;
+
+		if self.undo_list.len() == UNDO_LIMIT {
+			self.undo_list.remove(0);
 		}
 
-		max_col
+		self.undo_list.push(func);
+		self.redo_list.clear();
 	}
 
-	fn ranker_v2(&mut self) -> () {
-		let mut visited: HashSet<NodeId> = HashSet::new();
-		let mut max_col = 0;
-
-		let map = self.node_map.clone();
-		let root_idx = *map.get(&0).unwrap();
-		visited.insert(root_idx);
-
-		let curr_max_col = self.build_tree(&mut visited, root_idx, 0, 0);
-
-		if curr_max_col > max_col {
-			max_col = curr_max_col;
-		};
+	fn undo(&mut self) {
+		if let Some(func) = self.undo_list.pop() {
+			self.redo_list.push(self.export_snarl());
+			self.restore(func);
+		}
 	}
 
-	pub fn populate_map(&mut self) -> () {
-		let data = std::fs::read(&self.file_path).expect("Incorrect file path");
-		let func: Function<Block> = from_bytes(&data).expect("Invalid RON Data");
-		let mut map: HashMap<u32, NodeId> = HashMap::new();
+	fn redo(&mut self) {
+		if let Some(func) = self.redo_list.pop() {
+			self.undo_list.push(self.export_snarl());
+			self.restore(func);
+		}
+	}
 
-		// farm the data
-		for block in func.block_list {
-			let block_lbl = block.label;
-			let id = self.snarl.insert_node(egui::pos2(0.0, 0.0), block);
-			map.insert(block_lbl, id);
+	// the shown proto is rebuilt from `func`, blocks that were already on
+	// screen go back where they were instead of where the layout puts them
+	fn restore(&mut self, func: Function<Block>) {
+		let pos_map: HashMap<u32, egui::Pos2> = self
+			.node_map
+			.iter()
+			.filter_map(|(label, id)| Some((*label, self.snarl.get_node_info(*id)?.pos)))
+			.collect();
+		let exit_pos = self
+			.exit
+			.and_then(|v| self.snarl.get_node_info(v))
+			.map(|v| v.pos);
+
+		self.func = Some(func);
+
+		let block_list = self.load_proto(self.proto.clone());
+
+		self.connect_blocks(block_list);
+
+		for (label, id) in &self.node_map {
+			if let (Some(pos), Some(node)) = (pos_map.get(label), self.snarl.get_node_info_mut(*id))
+			{
+				node.pos = *pos;
+			}
 		}
 
-		self.node_map = map;
+		if let (Some(pos), Some(node)) = (
+			exit_pos,
+			self.exit.and_then(|v| self.snarl.get_node_info_mut(v)),
+		) {
+			node.pos = pos;
+		}
 	}
 
-	pub fn parse_ron_data(&mut self) -> () {
-		let map = self.node_map.clone();
-		let data = std::fs::read(&self.file_path).expect("Incorrect file path");
-		let func: Function<Block> = from_bytes(&data).expect("Invalid RON Data");
-
-		//let node_levels = self.assign_node_levels();
-		let horizontal_spacing = 150.0;
-		let vertical_spacing = 100.0;
-		let mut level_counts: HashMap<u32, u32> = HashMap::new();
-
-		for block in func.block_list {
-			match &block.edge {
-				Control::Unconditional(target) => match target {
-					Target::Label(to_label) => {
-						let node_from = map.get(&block.label);
-						let node_to = map.get(to_label);
-						if node_to.is_some() && node_from.is_some() {
-							let out_pin: OutPinId = OutPinId {
-								node: *node_from.unwrap(),
-								output: 0,
-							};
-
-							let in_pin: InPinId = InPinId {
-								node: *node_to.unwrap(),
-								input: 0,
-							};
-							self.snarl.connect(out_pin, in_pin);
-						}
-					}
+	// the loaded function with the shown proto's blocks as they are now
+	fn export_snarl(&self) -> Function<Block> {
+		let mut func = self.func.clone().expect("no function loaded");
 
-					_ => {}
-				},
-				Control::Condition(condition, true_target, false_target) => {
-					match true_target {
-						Target::Label(to_label) => {
-							let node_from = map.get(&block.label);
-							let node_to = map.get(to_label);
-							if node_to.is_some() && node_from.is_some() {
-								let out_pin: OutPinId = OutPinId {
-									node: *node_from.unwrap(),
-									output: 0,
-								};
+		nested_proto(&mut func, &self.proto).block_list = self.snarl_blocks();
 
-								let in_pin: InPinId = InPinId {
-									node: *node_to.unwrap(),
-									input: 0, // true target is the first pin
-								};
-								self.snarl.connect(out_pin, in_pin);
-							}
-						}
-						_ => {}
-					}
-					match false_target {
-						Target::Label(to_label) => {
-							let node_from = map.get(&block.label);
-							let node_to = map.get(to_label);
-							if node_to.is_some() && node_from.is_some() {
-								let out_pin: OutPinId = OutPinId {
-									node: *node_from.unwrap(),
-									output: 1, // false target is the 2nd pin
-								};
+		func
+	}
 
-								let in_pin: InPinId = InPinId {
-									node: *node_to.unwrap(),
-									input: 0,
-								};
-								self.snarl.connect(out_pin, in_pin);
-							}
-						}
-						_ => {}
-					}
-				}
-				// loop has a reversed target order where on_false mean jmp back
-				// and on_true means exit loop, but we restore the order back
-				// on the node like a conditional
-				Control::Loop(_, on_false, on_true) => {
-					match on_true {
-						Target::Label(to_label) => {
-							let node_from = map.get(&block.label);
-							let node_to = map.get(to_label);
-							if node_to.is_some() && node_from.is_some() {
-								let out_pin: OutPinId = OutPinId {
-									node: *node_from.unwrap(),
-									output: 0,
-								};
+	pub fn save_file(&self) {
+		let func = self.export_snarl();
+		let data = match self.bytecode {
+			Some(lua) => assemble_as(func, lua),
+			None => Ok(print_function(&func, Format::Ron, Indent::Default).into_bytes()),
+		};
+		let result = data.and_then(|v| write_output(Some(Path::new(&self.file_path)), &v));
 
-								let in_pin: InPinId = InPinId {
-									node: *node_to.unwrap(),
-									input: 0, // true target is the first pin
-								};
-								self.snarl.connect(out_pin, in_pin);
-							}
-						}
-						_ => {}
-					}
+		if let Err(e) = result {
+			eprintln!("error: could not save {}: {}", self.file_path, e);
+		}
+	}
 
-					match on_false {
-						Target::Label(to_label) => {
-							let node_from = map.get(&block.label);
-							let node_to = map.get(to_label);
-							if node_to.is_some() && node_from.is_some() {
-								let out_pin: OutPinId = OutPinId {
-									node: *node_from.unwrap(),
-									output: 1, // false target is the 2nd pin
-								};
+	pub fn parse_ron_data(&mut self) {
+		let data = std::fs::read(&self.file_path).expect("Incorrect file path");
+		let (mut func, _) = load_function(&data).unwrap_or_else(|e| panic!("{}", e));
+		let block_list = std::mem::take(&mut nested_proto(&mut func, &self.proto).block_list);
 
-								let in_pin: InPinId = InPinId {
-									node: *node_to.unwrap(),
-									input: 0,
-								};
-								self.snarl.connect(out_pin, in_pin);
-							}
-						}
+		self.connect_blocks(block_list);
+	}
 
-						_ => {}
-					}
+	fn connect_blocks(&mut self, block_list: Vec<Block>) {
+		// only `snarl` is changed below, so the map can stay borrowed throughout
+		let map = &self.node_map;
+
+		for mut block in block_list {
+			let node_from = match map.get(&block.label) {
+				Some(node) => *node,
+				None => continue,
+			};
+
+			// every returning block is wired to the exit instead
+			if is_return(&block.edge) {
+				if let Some(node_to) = self.exit {
+					let out_pin = OutPinId {
+						node: node_from,
+						output: 0,
+					};
+					let in_pin = InPinId {
+						node: node_to,
+						input: 0,
+					};
+
+					self.snarl.connect(out_pin, in_pin);
 				}
-				_ => {}
 			}
-		}
-		self.ranker_v2();
-		/*
 
-			for (node_id, level) in node_levels {
-				let x = level as f32 * horizontal_spacing;
-				let y = *level_counts.entry(level).or_insert(0) as f32 * vertical_spacing;
-
-				// Set the position of the node in the snarl
-				if let Some(node_) = self.snarl.get_node_info_mut(node_id) {
-					node_.pos = egui::pos2(x, y);
+			// pins follow `targets_mut`, so a loop reads like a branch even
+			// though `Control` keeps its targets the other way around
+			for (output, target) in block.targets_mut().into_iter().enumerate() {
+				if let Target::Label(to_label) = target {
+					if let Some(node_to) = map.get(to_label) {
+						let out_pin = OutPinId {
+							node: node_from,
+							output,
+						};
+						let in_pin = InPinId {
+							node: *node_to,
+							input: 0,
+						};
+
+						self.snarl.connect(out_pin, in_pin);
+					}
 				}
-
-				// Increment node count in the current level for spacing
-				*level_counts.get_mut(&level).unwrap() += 1;
 			}
-		*/
+		}
+		self.layout_layered();
 	}
 }
 
@@ -567,15 +1863,74 @@ impl Default for EApp {
 		Self {
 			snarl,
 			snarl_ui_id: None,
+			focus: None,
+			selected: None,
+			search: String::new(),
+			search_error: None,
+			highlight: String::new(),
+			spacing: DEFAULT_SPACING,
 			style,
 			file_path,
 			node_map,
+			func: None,
+			proto: Vec::new(),
+			undo_list: Vec::new(),
+			redo_list: Vec::new(),
+			exit: None,
+			bytecode: None,
+			entry: None,
 		}
 	}
 }
 
+// dotted child indices like the listing, `main` being the top function
+fn proto_name(path: &[usize]) -> String {
+	if path.is_empty() {
+		return "main".to_string();
+	}
+
+	let index_list: Vec<String> = path.iter().map(usize::to_string).collect();
+
+	index_list.join(".")
+}
+
+fn nested_proto<'a>(func: &'a mut Function<Block>, path: &[usize]) -> &'a mut Function<Block> {
+	path.iter()
+		.fold(func, |func, &index| &mut func.child_list[index].1)
+}
+
+// every proto with its name, parents right before their children
+fn proto_paths(func: &Function<Block>) -> Vec<(Vec<usize>, Arc<str>)> {
+	let mut path_list: Vec<(Vec<usize>, Arc<str>)> = vec![(Vec::new(), Arc::from(""))];
+	let mut index = 0;
+
+	while index < path_list.len() {
+		let proto = path_list[index]
+			.0
+			.iter()
+			.fold(func, |func, &index| &func.child_list[index].1);
+		let child_list: Vec<_> = proto
+			.child_list
+			.iter()
+			.enumerate()
+			.map(|(child, (name, _))| {
+				let mut child_path = path_list[index].0.clone();
+
+				child_path.push(child);
+
+				(child_path, name.clone())
+			})
+			.collect();
+
+		path_list.splice(index + 1..index + 1, child_list);
+		index += 1;
+	}
+
+	path_list
+}
+
 impl eframe::App for EApp {
-	fn update(&mut self, ctx: &egui::Context, frame: &mut eframe::Frame) {
+	fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
 		egui::CentralPanel::default().show(ctx, |ui| {
 			ui.heading("Lau - The ultimate Lua ToolKit");
 
@@ -585,12 +1940,111 @@ impl eframe::App for EApp {
 				self.parse_ron_data();
 			}
 
-			self.snarl.show(&mut BlocksViewer, &self.style, "snarl", ui);
+			if self.func.is_some() && ui.button("save").clicked() {
+				self.save_file();
+			}
+
+			if self.func.is_some() && ui.button("reload").clicked() {
+				self.reload();
+			}
+
+			// text fields keep their own undo
+			if !ctx.wants_keyboard_input() {
+				let undo = egui::KeyboardShortcut::new(egui::Modifiers::COMMAND, egui::Key::Z);
+				let redo = egui::KeyboardShortcut::new(egui::Modifiers::COMMAND, egui::Key::Y);
+
+				if ctx.input_mut(|i| i.consume_shortcut(&undo)) {
+					self.undo();
+				}
+
+				if ctx.input_mut(|i| i.consume_shortcut(&redo)) {
+					self.redo();
+				}
+			}
+
+			ui.horizontal(|ui| {
+				if let Some(func) = &self.func {
+					let mut choice = self.proto.clone();
+
+					egui::ComboBox::from_id_salt("proto")
+						.selected_text(proto_name(&self.proto))
+						.show_ui(ui, |ui| {
+							for (path, name) in proto_paths(func) {
+								let text = match &*name {
+									"" => proto_name(&path),
+									_ => format!("{} {}", proto_name(&path), name),
+								};
+
+								ui.selectable_value(&mut choice, path, text);
+							}
+						});
+
+					if choice != self.proto {
+						self.select_proto(choice);
+					}
+				}
+
+				let undo = ui.add_enabled(!self.undo_list.is_empty(), egui::Button::new("undo"));
+
+				if undo.clicked() {
+					self.undo();
+				}
+
+				let redo = ui.add_enabled(!self.redo_list.is_empty(), egui::Button::new("redo"));
+
+				if redo.clicked() {
+					self.redo();
+				}
+
+				if ui.button("fit").clicked() {
+					self.reset_view(None);
+				}
+
+				if ui.button("go to entry").clicked() {
+					let entry = self.find_entry();
+
+					self.reset_view(entry);
+				}
+
+				let search = ui.add(
+					egui::TextEdit::singleline(&mut self.search)
+						.hint_text("block label")
+						.desired_width(100.0),
+				);
+
+				if search.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter)) {
+					self.go_to_label();
+				}
+
+				if let Some(err) = &self.search_error {
+					ui.colored_label(Color32::from_rgb(255, 0, 0), err);
+				}
+
+				ui.add(
+					egui::TextEdit::singleline(&mut self.highlight)
+						.hint_text("opcode")
+						.desired_width(100.0),
+				);
+			});
+
+			self.show_snarl(ui);
 		});
 	}
 }
 
-fn ui_mode(file_path: String) -> Result<()> {
+fn ui_mode(
+	file_path: String,
+	entry: Option<u32>,
+	highlight: Option<String>,
+	spacing: egui::Vec2,
+) -> Result<()> {
+	// a bad `--entry` is told about before any window opens
+	if entry.is_some() {
+		let (func, _) = load_function(&std::fs::read(&file_path)?)?;
+
+		entry_label(&func, entry)?;
+	}
+
 	let options = eframe::NativeOptions::default();
 	eframe::run_native(
 		"LAU | dispatch fork",
@@ -598,12 +2052,15 @@ fn ui_mode(file_path: String) -> Result<()> {
 		Box::new(|cc| {
 			let mut app = EApp::new(cc);
 			app.set_file(file_path);
+			app.entry = entry;
+			app.highlight = highlight.unwrap_or_default();
+			app.spacing = spacing;
 			app.populate_map();
 			let ret = Box::new(app);
-			return Ok(ret);
+			Ok(ret)
 		}), // Cast EApp to Box<dyn App>
 	)
-	.map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e.to_string())) // Convert eframe::Error to std::io::Error
+	.map_err(|e| std::io::Error::other(e.to_string())) // Convert eframe::Error to std::io::Error
 }
 
 /*
@@ -613,53 +2070,23 @@ fn ui_mode(file_path: String) -> Result<()> {
  *
  */
 
-fn optimize_jmp(map: &mut HashMap<u32, Block>, visited: &mut HashSet<u32>, node_id: u32) {
-	if !visited.insert(node_id) {
-		return; // already visited
-	}
-
-	if let Some(current_blk) = map.get_mut(&node_id) {
-		// the current block should be optimized ?
-		for target in current_blk.get_target_labels() {
-			if let Some(target_blk) = map.get(&target) {
-				if let Some(target_id) = target_blk.get_target_labels().get(0) {
-					if target_blk.body.is_empty() && target_blk.is_unconditionnal() {
-						// edit the target ffrrr
-						println!(
-							"fake jmp from {} to {} to {}",
-							node_id, target_blk.label, target_id
-						);
-						
-					}
-					optimize_jmp(map, visited, *target_id);
-				}
-			}
-		}
-	}
-}
-
-fn fixup_code_v1(data: &[u8]) -> () {
+fn fixup_code_v1(
+	data: &[u8],
+	output: Option<&Path>,
+	format: Format,
+	indent: Indent,
+	depth: Depth,
+) -> Result<()> {
 	// parse data from bytes
-	let func_data: Function<Block> = from_bytes(data).expect("Invalid RON data");
-
-	// we need to start from node root and process until the rest of the program from target to
-	// target
-	// we want to map labels to blocks for faster access !
-	let mut block_map: HashMap<u32, Block> = HashMap::new();
-
-	for block in func_data.block_list {
-		block_map.insert(block.label, block);
-	}
+	let mut func_data = parse_function(data)?;
 
-	let r: u32 = 0;
-	println!(
-		"{} total with first {}",
-		block_map.len(),
-		block_map[&r].label
-	);
+	devirtualize(&mut func_data, depth)
+		.map_err(|e| std::io::Error::new(ErrorKind::InvalidData, e))?;
 
-	let mut visited = HashSet::new();
-	optimize_jmp(&mut block_map, &mut visited, 0);
+	write_output(
+		output,
+		print_function(&func_data, format, indent).as_bytes(),
+	)
 }
 
 /*
@@ -670,42 +2097,471 @@ fn fixup_code_v1(data: &[u8]) -> () {
  *
  *
  */
-fn main() -> Result<()> {
-	let mut iter = std::env::args().skip(1);
+fn run() -> Result<()> {
+	let mut iter = std::env::args().skip(1).peekable();
 	let mut mutation = Vec::new();
+	let mut output: Option<PathBuf> = None;
+	let mut rng = StdRng::from_entropy();
+	let mut format = Format::Ron;
+	let mut proto: Option<String> = None;
+	let mut lua: &'static dyn BytecodeFormat = &Lua54;
+	let mut strip = false;
+	let mut depth = Depth::new(MAX_DEPTH);
+	let mut entry: Option<u32> = None;
+	let mut highlight: Option<String> = None;
+	let mut spacing = DEFAULT_SPACING;
+	let mut faithful = false;
+	let mut calls = false;
+	let mut recursive = false;
+	let mut grep = Vec::new();
+	let mut indent = Indent::Default;
+	let mut show_report = false;
+	let mut progress = false;
+	let mut keep_going = false;
+	let mut failed = 0;
+	let mut timeout: Option<Duration> = None;
+	let mut baseline: Option<u64> = None;
 
 	while let Some(val) = iter.next() {
 		match val.as_str() {
 			"-h" | "--help" => {
 				list_help();
 			}
-			"-a" | "--assemble" => {
-				let name = iter.next().expect("file name expected");
-				let data = std::fs::read(name)?;
+			"-a" | "--assemble" | "--to-luac" => {
+				let show_size = val == "--to-luac";
+				let tree = iter.next_if(|v| v == "--tree").is_some();
+				let name_list = take_names(&mut iter);
+
+				if tree && name_list.len() != 1 {
+					let msg = "--tree takes the one directory holding main.ron".to_string();
+
+					return Err(std::io::Error::new(ErrorKind::InvalidInput, msg));
+				}
+
+				let batch = batch_outputs(name_list, output.as_deref(), "luac")?;
+				let is_batch = batch.len() > 1;
+				let batch_len = batch.len();
+
+				for (index, (name, output)) in batch.into_iter().enumerate() {
+					if progress && is_batch {
+						print_progress(index, batch_len, name.as_deref());
+					}
+
+					let result = (|| {
+						let main_path = match (tree, &name) {
+							(true, Some(dir)) => Some(Path::new(dir).join("main.ron")),
+							_ => None,
+						};
+						let data = match main_path {
+							Some(_) => Vec::new(),
+							None => read_input(name.clone())?,
+						};
+						let mutation = mutation.clone();
+						let mut next = rng.clone();
+						let ((report, size), next) =
+							with_timeout(timeout, keep_going, name.as_deref(), move || {
+								let func = match &main_path {
+									Some(path) => load_tree(path, depth)?,
+									None => parse_function(&data)?,
+								};
+								let report = assemble_data(
+									func,
+									&mutation,
+									&mut next,
+									output.as_deref(),
+									lua,
+									strip,
+									depth,
+								)?;
+
+								Ok((report, next))
+							})?;
+
+						rng = next;
+
+						if show_report {
+							print_report(&report, name.as_deref(), is_batch);
+						}
+
+						if show_size {
+							print_size(size, baseline, name.as_deref(), is_batch);
+						}
 
-				assemble_data(&data, &mutation)?;
+						Ok(())
+					})();
+
+					skip_failed(result, name.as_deref(), keep_going, &mut failed)?;
+				}
 			}
 			"-d" | "--disassemble" => {
+				let name_list = take_names(&mut iter);
+				let ext = format.extension();
+
+				// a queued step is refused rather than skipped, it was
+				// asked for after all
+				if faithful && !mutation.is_empty() {
+					let msg = "--faithful refuses steps queued before -d".to_string();
+
+					return Err(std::io::Error::new(ErrorKind::InvalidInput, msg));
+				}
+
+				let batch = batch_outputs(name_list, output.as_deref(), ext)?;
+				let is_batch = batch.len() > 1;
+				let batch_len = batch.len();
+
+				for (index, (name, output)) in batch.into_iter().enumerate() {
+					if progress && is_batch {
+						print_progress(index, batch_len, name.as_deref());
+					}
+
+					let result = (|| {
+						let data = read_input(name.clone())?;
+
+						if faithful {
+							verify_roundtrip(&data, lua)
+								.map_err(|e| std::io::Error::new(ErrorKind::InvalidData, e))?;
+						}
+
+						let mutation = mutation.clone();
+						let path = proto.clone();
+						let mut next = rng.clone();
+						let (report, next) =
+							with_timeout(timeout, keep_going, name.as_deref(), move || {
+								let report = disassemble_data(
+									&data,
+									&mutation,
+									&mut next,
+									output.as_deref(),
+									format,
+									indent,
+									path.as_deref(),
+									lua,
+									depth,
+								)?;
+
+								Ok((report, next))
+							})?;
+
+						rng = next;
+
+						if show_report {
+							print_report(&report, name.as_deref(), is_batch);
+						}
+
+						Ok(())
+					})();
+
+					skip_failed(result, name.as_deref(), keep_going, &mut failed)?;
+				}
+			}
+			"--compact" => {
+				indent = Indent::Compact;
+			}
+			"--diff" => {
+				let first = read_input(Some(iter.next().expect("first file name expected")))?;
+				let second = read_input(Some(iter.next().expect("second file name expected")))?;
+				let report = diff_functions(&parse_function(&first)?, &parse_function(&second)?);
+
+				write_output(output.as_deref(), report.to_string().as_bytes())?;
+			}
+			"--dot" => {
+				let data = read_input(iter.next())?;
+				let func = select_proto(parse_function(&data)?, proto.as_deref())?;
+
+				let text = if calls {
+					call_graph_dot(&call_graph(&func))
+				} else {
+					to_dot(&func)
+				};
+
+				write_output(output.as_deref(), text.as_bytes())?;
+			}
+			"--extract" => {
+				let label = iter.next().expect("label expected");
+				let label = label.parse().expect("label must be an unsigned integer");
+				let data = read_input(iter.next())?;
+				let func = select_proto(parse_function(&data)?, proto.as_deref())?;
+				let snippet = extract_block(&func, label).ok_or_else(|| {
+					let msg = format!("no block {}", label);
+
+					std::io::Error::new(ErrorKind::InvalidInput, msg)
+				})?;
+
+				write_output(
+					output.as_deref(),
+					print_function(&snippet, format, indent).as_bytes(),
+				)?;
+			}
+			"--entry" => {
+				let label = iter.next().expect("label expected");
+				let label = label.parse().expect("label must be an unsigned integer");
+
+				entry = Some(label);
+			}
+			"--hspace" => {
+				let space = iter.next().expect("spacing expected");
+
+				spacing.x = space.parse().expect("spacing must be a number");
+			}
+			"--vspace" => {
+				let space = iter.next().expect("spacing expected");
+
+				spacing.y = space.parse().expect("spacing must be a number");
+			}
+			"--highlight-opcode" => {
+				highlight = Some(iter.next().expect("opcode expected"));
+			}
+			"--baseline" => {
+				let name = iter.next().expect("file name expected");
+
+				baseline = Some(std::fs::metadata(name)?.len());
+			}
+			"--calls" => {
+				calls = true;
+			}
+			"--recursive" => {
+				recursive = true;
+			}
+			"--faithful" => {
+				faithful = true;
+			}
+			"--format" => {
+				format = match iter.next().as_deref() {
+					Some("ron") => Format::Ron,
+					Some("json") => Format::Json,
+					_ => panic!("format must be `ron` or `json`"),
+				};
+			}
+			"--indent" => {
+				let width = iter.next().expect("width expected");
+				let width = width.parse().expect("width must be an unsigned integer");
+
+				indent = Indent::Width(width);
+			}
+			"--grep" => {
+				grep.push(iter.next().expect("search term expected"));
+			}
+			"--header" => {
+				let data = read_input(iter.next())?;
+				let header = parse_header(&data)
+					.map_err(|e| std::io::Error::new(ErrorKind::InvalidData, e))?;
+
+				write_output(output.as_deref(), header.to_string().as_bytes())?;
+			}
+			"--list" => {
+				let data = read_input(iter.next())?;
+				let func = lua
+					.load(&data)
+					.and_then(|(_, v)| Function::try_from(v))
+					.map_err(|e| std::io::Error::new(ErrorKind::InvalidData, e))?;
+				let func = select_proto(func, proto.as_deref())?;
+				let entry = entry_label(&func, entry)?;
+
+				write_output(
+					output.as_deref(),
+					disasm_listing(&func, &grep, entry).as_bytes(),
+				)?;
+			}
+			"--constants" => {
+				let data = read_input(iter.next())?;
+				let func = lua
+					.load(&data)
+					.and_then(|(_, v)| Function::try_from(v))
+					.map_err(|e| std::io::Error::new(ErrorKind::InvalidData, e))?;
+				let func = select_proto(func, proto.as_deref())?;
+
+				write_output(
+					output.as_deref(),
+					dump_constants(&func, recursive).as_bytes(),
+				)?;
+			}
+			"--stats" => {
+				let data = read_input(iter.next())?;
+				let func = lua
+					.load(&data)
+					.and_then(|(_, v)| Function::try_from(v))
+					.map_err(|e| std::io::Error::new(ErrorKind::InvalidData, e))?;
+				let func = select_proto(func, proto.as_deref())?;
+				let entry = entry_label(&func, entry)?;
+
+				write_output(
+					output.as_deref(),
+					collect_stats(&func, entry).to_string().as_bytes(),
+				)?;
+			}
+			"--lua" => {
+				lua = match iter.next().as_deref() {
+					Some("5.3") => &Lua53,
+					Some("5.4") => &Lua54,
+					_ => panic!("Lua version must be `5.3` or `5.4`"),
+				};
+			}
+			"-o" | "--output" => {
 				let name = iter.next().expect("file name expected");
-				let data = std::fs::read(name)?;
 
-				disassemble_data(&data, &mutation)?;
+				output = Some(name.into());
+			}
+			"--proto" => {
+				let path = iter.next().expect("proto path expected");
+
+				proto = Some(path);
+			}
+			"--flatten" => {
+				mutation.push(Mutation::Flatten);
+			}
+			"--coalesce" => {
+				mutation.push(Mutation::Coalesce);
+			}
+			"--dce" => {
+				mutation.push(Mutation::DeadStore);
+			}
+			"--dedup" => {
+				mutation.push(Mutation::Dedup);
+			}
+			"--peephole" => {
+				mutation.push(Mutation::Peephole);
+			}
+			"--permute-upvals" => {
+				mutation.push(Mutation::PermuteUpvals);
+			}
+			"--pipeline" => {
+				let pass_list = iter
+					.next()
+					.expect("pass list expected")
+					.split(',')
+					.map(|v| Ok((v.to_string(), v.parse()?)))
+					.collect::<std::result::Result<Vec<(String, Pass)>, String>>()
+					.map_err(|e| std::io::Error::new(ErrorKind::InvalidInput, e))?;
+				let name = iter.next();
+				let data = read_input(name.clone())?;
+				let output = output.clone();
+				let mut next = rng.clone();
+
+				rng = with_timeout(timeout, false, name.as_deref(), move || {
+					run_passes(
+						&data,
+						&pass_list,
+						&mut next,
+						output.as_deref(),
+						lua,
+						depth,
+						progress,
+					)?;
+
+					Ok(next)
+				})?;
+			}
+			"--progress" => {
+				progress = true;
+			}
+			"--keep-going" => {
+				if timeout.is_some() {
+					return Err(timeout_conflict());
+				}
+
+				keep_going = true;
+			}
+			"--report" => {
+				show_report = true;
+			}
+			"--split-calls" => {
+				mutation.push(Mutation::SplitCalls);
+			}
+			"--renumber" => {
+				mutation.push(Mutation::Renumber);
+			}
+			"--reformat" => {
+				let name = iter.next();
+				let data = read_input(name.clone())?;
+				let func = parse_function(&data)?;
+				// stdin has nowhere to be written back to but stdout
+				let target = output
+					.clone()
+					.or_else(|| name.filter(|v| v != "-").map(PathBuf::from));
+
+				// the same file always comes out the same, whatever was asked of -d
+				let text = print_function(&func, text_format(&data), Indent::Default);
+
+				write_output(target.as_deref(), text.as_bytes())?;
+			}
+			"--repl" => {
+				// stdin is where the commands come from
+				let data = read_input(Some(iter.next().expect("file name expected")))?;
+
+				run_repl(&data, lua, &mut rng, depth, entry)?;
+			}
+			"--timeout" => {
+				let secs = iter.next().expect("seconds expected");
+				let secs = secs.parse().expect("seconds must be an unsigned integer");
+
+				if keep_going {
+					return Err(timeout_conflict());
+				}
+
+				timeout = Some(Duration::from_secs(secs));
+			}
+			"--topo" => {
+				mutation.push(Mutation::TopoSort);
+			}
+			"--unroll" => {
+				let factor = iter.next().expect("factor expected");
+				let factor = factor.parse().expect("factor must be an unsigned integer");
+
+				mutation.push(Mutation::Unroll { factor });
+			}
+			"--opaque" => {
+				mutation.push(Mutation::OpaquePredicate);
+			}
+			"--junk" => {
+				mutation.push(Mutation::JunkInsert);
 			}
 			"-r" | "--randomize" => {
 				mutation.push(Mutation::Random);
 			}
+			"--strip" => {
+				strip = true;
+			}
 			"-s" | "--sort" => {
 				mutation.push(Mutation::Sorted);
 			}
+			"--max-depth" => {
+				let max_depth = iter.next().expect("depth expected");
+				let max_depth = max_depth
+					.parse()
+					.expect("depth must be an unsigned integer");
+
+				depth = Depth::new(max_depth);
+			}
+			"--schema" => {
+				write_output(output.as_deref(), print_schema()?.as_bytes())?;
+			}
+			"--seed" => {
+				let seed = iter.next().expect("seed expected");
+				let seed = seed.parse().expect("seed must be an unsigned integer");
+
+				rng = StdRng::seed_from_u64(seed);
+			}
 			"-ui" => {
 				let name = iter.next().expect("file name expected");
 
-				ui_mode(name);
+				ui_mode(name, entry, highlight.clone(), spacing)?;
 			}
 			"-v" | "--devirt" => {
 				let name = iter.next().expect("File name expected !");
-				let data = std::fs::read(name)?;
-				fixup_code_v1(&data);
+				let data = std::fs::read(&name)?;
+				let output = output.clone();
+
+				with_timeout(timeout, false, Some(&name), move || {
+					fixup_code_v1(&data, output.as_deref(), format, indent, depth)
+				})?;
+			}
+			"--verify" => {
+				let data = read_input(iter.next())?;
+
+				verify_roundtrip(&data, lua)
+					.map_err(|e| std::io::Error::new(ErrorKind::InvalidData, e))?;
+
+				println!("roundtrip ok ({} bytes)", data.len());
 			}
 			opt => {
 				panic!("unknown option `{}`", opt);
@@ -713,5 +2569,84 @@ fn main() -> Result<()> {
 		}
 	}
 
+	if failed != 0 {
+		let msg = format!("{} file(s) failed", failed);
+
+		return Err(std::io::Error::other(msg));
+	}
+
 	Ok(())
 }
+
+fn main() {
+	// diagnostics go to stderr as `RUST_LOG` asks, only errors when it is unset
+	env_logger::init();
+
+	if let Err(err) = run() {
+		eprintln!("error: {}", err);
+		std::process::exit(1);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const VSECURE: &[u8] = include_bytes!("../tests/fixtures/vsecure.luac");
+
+	// what `-d` writes and `-a` reads back has to make the same chunk again
+	fn text_roundtrip(format: Format, indent: Indent) {
+		let func = disassemble_as(VSECURE, &Lua54).unwrap();
+		let text = print_function(&func, format, indent);
+		let func = parse_function(text.as_bytes()).unwrap();
+
+		assert!(assemble_as(func, &Lua54).unwrap() == VSECURE);
+	}
+
+	#[test]
+	fn ron_roundtrip() {
+		text_roundtrip(Format::Ron, Indent::Default);
+		text_roundtrip(Format::Ron, Indent::Compact);
+	}
+
+	#[test]
+	fn json_roundtrip() {
+		text_roundtrip(Format::Json, Indent::Default);
+		text_roundtrip(Format::Json, Indent::Compact);
+	}
+
+	// the same chunk, and the same steps under the same seed, always give the
+	// same text so it can be kept under version control
+	fn disasm_text(format: Format, opt: &[Mutation]) -> String {
+		let mut func = disassemble_as(VSECURE, &Lua54).unwrap();
+
+		mutate(
+			&mut func,
+			opt,
+			&mut StdRng::seed_from_u64(1),
+			Depth::new(MAX_DEPTH),
+		)
+		.unwrap();
+		print_function(&func, format, Indent::Default)
+	}
+
+	#[test]
+	fn disassembly_is_deterministic() {
+		for format in [Format::Ron, Format::Json] {
+			assert_eq!(disasm_text(format, &[]), disasm_text(format, &[]));
+
+			let opt = [Mutation::Random, Mutation::Flatten];
+
+			assert_eq!(disasm_text(format, &opt), disasm_text(format, &opt));
+		}
+	}
+
+	#[test]
+	fn unknown_entry_is_refused() {
+		let func = disassemble_as(VSECURE, &Lua54).unwrap();
+
+		assert_eq!(entry_label(&func, None).unwrap(), 0);
+		assert_eq!(entry_label(&func, Some(1)).unwrap(), 1);
+		assert!(entry_label(&func, Some(u32::MAX)).is_err());
+	}
+}
@@ -0,0 +1,17 @@
+-- upvalues captured from the stack and passed on through an enclosing function
+local function counter(start)
+	local count = start
+
+	return function(step)
+		local function add()
+			count = count + step
+			return count
+		end
+
+		return add()
+	end
+end
+
+local next = counter(10)
+
+print(next(1), next(2), next(3))
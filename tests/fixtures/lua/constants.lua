@@ -0,0 +1,15 @@
+-- integers and floats that look alike, long and binary strings, and the
+-- constants only reachable through RK and immediate operands
+local int, float = 3, 3.0
+local big, tiny = 9007199254740993, 1e-300
+local inf, ninf = 1 / 0, -1 / 0
+local zero, nzero = 0.0, -0.0
+local bin = "a\0b\0\255\254"
+local long = string.rep("x", 40) .. "0123456789012345678901234567890123456789yz"
+local t = {}
+
+t.field = "value"
+t[1] = int + 7
+t[300] = float * 2.5
+
+print(int, float, big, tiny, inf, ninf, zero, nzero, #bin, #long, t.field, math.type(int), math.type(float))
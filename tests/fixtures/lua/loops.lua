@@ -0,0 +1,33 @@
+-- numeric and generic for loops, a while with a break and a goto
+local sum = 0
+
+for i = 1, 10 do
+	sum = sum + i
+end
+
+for i = 10, 1, -2 do
+	sum = sum - i
+end
+
+for k, v in pairs({ a = 1, b = 2 }) do
+	sum = sum + v + #k
+end
+
+local n = 0
+
+while true do
+	n = n + 1
+
+	if n > 5 then
+		break
+	end
+end
+
+::again::
+n = n - 1
+
+if n > 0 then
+	goto again
+end
+
+print(sum, n)
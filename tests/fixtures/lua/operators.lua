@@ -0,0 +1,16 @@
+-- arithmetic, bitwise, comparison and concatenation in all their operand forms
+local a, b = 7, 3
+
+local r = {
+	a + b, a - b, a * b, a / b, a // b, a % b, a ^ b, -a,
+	a & b, a | b, a ~ b, a << 2, a >> 1, ~a,
+	a + 1, a - 200, a * 2.5, a // 2, a % 4,
+	a == b, a ~= b, a < b, a <= b, a > 1, a >= 300,
+	not a, #"length", "a" .. b .. "c",
+}
+
+do
+	local closed <close> = setmetatable({}, { __close = function() end })
+end
+
+print(table.unpack(r))